@@ -0,0 +1,85 @@
+//! Seek distance of bulk extraction orders on a synthetic fragmented pak index
+//!
+//! Run with `cargo bench --bench extraction_plan`.
+
+use std::time::Instant;
+
+use ue4pak::archive::ArchivableWith;
+use ue4pak::{PakCompressedBlock, PakEntry, PakIndex, PakIndexV1, PakVersion};
+
+const ENTRIES: u64 = 20_000;
+/// One entry out of `FRAGMENTED_EVERY` has its compression blocks stored at the end of the pak
+const FRAGMENTED_EVERY: u64 = 20;
+const BLOCK_SIZE: u64 = 0x1_0000;
+
+fn synthetic_index(version: PakVersion) -> PakIndex {
+    let mut v1 = PakIndexV1::default();
+    let mut offset = 0;
+    let mut out_of_line = Vec::new();
+    for i in 0..ENTRIES {
+        // path order is unrelated to the offset order
+        let name = format!("Game/{:03}/{:05}.uasset", (i * 7919) % 251, (i * 104_729) % ENTRIES);
+        let size = 1_000 + (i * 2_654_435_761) % 300_000;
+        let blocks = size.div_ceil(BLOCK_SIZE);
+        let mut entry = PakEntry {
+            offset,
+            size,
+            uncompressed_size: size,
+            compression_method_index: 1,
+            compression_block_size: BLOCK_SIZE as u32,
+            compression_blocks: vec![PakCompressedBlock::default(); blocks as usize],
+            ..PakEntry::default()
+        };
+        let header_len = entry.ser_len_with(version);
+        if i % FRAGMENTED_EVERY == 0 {
+            offset += header_len;
+            out_of_line.push((name, entry));
+        } else {
+            set_blocks(&mut entry, header_len);
+            offset += header_len + size;
+            v1.add(name, entry);
+        }
+    }
+    // blocks appended after every other entry, relative to their entry offset
+    for (name, mut entry) in out_of_line {
+        let start = offset - entry.offset;
+        set_blocks(&mut entry, start);
+        offset += entry.size;
+        v1.add(name, entry);
+    }
+    PakIndex::V1(v1)
+}
+
+/// Contiguous blocks starting `start` bytes after the entry offset
+fn set_blocks(entry: &mut PakEntry, start: u64) {
+    let mut pos = start;
+    let mut left = entry.size;
+    for block in &mut entry.compression_blocks {
+        let len = left.min(BLOCK_SIZE);
+        *block = PakCompressedBlock { compressed_start: pos, compressed_end: pos + len };
+        pos += len;
+        left -= len;
+    }
+}
+
+fn main() {
+    let version = PakVersion::DeleteRecords;
+    let index = synthetic_index(version);
+
+    let mut path_order = index.named_entries();
+    path_order.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut offset_order = path_order.clone();
+    offset_order.sort_by_key(|(_, entry)| entry.offset);
+    let start = Instant::now();
+    let plan = index.extraction_plan(version);
+    let elapsed = start.elapsed();
+
+    let gib = |distance: u64| distance as f64 / (1024.0 * 1024.0 * 1024.0);
+    let path = PakIndex::seek_distance(path_order.iter().map(|(_, entry)| *entry), version);
+    let offset = PakIndex::seek_distance(offset_order.iter().map(|(_, entry)| *entry), version);
+    let planned = PakIndex::seek_distance(plan.iter().map(|(_, entry)| entry), version);
+    println!("{} entries, 1 out of {} fragmented", ENTRIES, FRAGMENTED_EVERY);
+    println!("path order:      {:>12.1} GiB of seeks", gib(path));
+    println!("offset order:    {:>12.1} GiB of seeks", gib(offset));
+    println!("extraction plan: {:>12.1} GiB of seeks (planned in {:?})", gib(planned), elapsed);
+}
@@ -1,11 +1,21 @@
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
-use std::{io, mem};
+use std::{io, mem, slice};
 
 use sha1::{Digest, Sha1};
 
-/// An archive reader or writer trait
+/// Aligns `size` to the nearest higher multiple of `alignment`
+///
+/// A zero `alignment` leaves `size` unchanged.
+pub const fn align(size: u64, alignment: u64) -> u64 {
+    match alignment {
+        0 => size,
+        _ => size.div_ceil(alignment) * alignment,
+    }
+}
 
+/// An archive reader or writer trait
+///
 /// There is a single trait in order to simplify `Archivable` impls.
 pub trait Archive {
     /// `true` if this is an archive reader
@@ -16,6 +26,29 @@ pub trait Archive {
 
     /// Read exactly the requested bytes into `buf` or return an error
     fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()>;
+
+    /// `true` if strings must be null terminated, see [`LenientStrings`]
+    fn strict_strings(&self) -> bool {
+        true
+    }
+
+    /// Maximum length in bytes of a string read from this archive, see [`MaxStringLen`]
+    fn max_string_len(&self) -> usize {
+        DEFAULT_MAX_STRING_LEN
+    }
+
+    /// Byte order of the numbers in this archive, see [`ByteOrder`]
+    fn endian(&self) -> Endian {
+        Endian::Little
+    }
+
+    /// Seek to `pos` and return the new position from the start of the archive
+    ///
+    /// Archives that can't seek return an `Unsupported` error, which is the default. Reading a
+    /// pak file requires a seekable archive.
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "archive can't seek"))
+    }
 }
 
 impl<A: Archive + ?Sized> Archive for &mut A {
@@ -33,14 +66,254 @@ impl<A: Archive + ?Sized> Archive for &mut A {
     fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
         (**self).read_exact(buf)
     }
+
+    #[inline]
+    fn strict_strings(&self) -> bool {
+        (**self).strict_strings()
+    }
+
+    #[inline]
+    fn max_string_len(&self) -> usize {
+        (**self).max_string_len()
+    }
+
+    #[inline]
+    fn endian(&self) -> Endian {
+        (**self).endian()
+    }
+
+    #[inline]
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        (**self).seek(pos)
+    }
+}
+
+/// An archive wrapper accepting strings without null terminator
+///
+/// Some tools write FStrings without the null terminator Unreal expects. When reading through
+/// this wrapper, a string whose last byte isn't `0` is kept whole instead of being rejected.
+pub struct LenientStrings<A>(pub A);
+
+impl<A: Archive> Archive for LenientStrings<A> {
+    fn is_reader(&self) -> bool {
+        self.0.is_reader()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.0.read_exact(buf)
+    }
+
+    fn strict_strings(&self) -> bool {
+        false
+    }
+
+    fn max_string_len(&self) -> usize {
+        self.0.max_string_len()
+    }
+
+    fn endian(&self) -> Endian {
+        self.0.endian()
+    }
+
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl<A: io::Seek> io::Seek for LenientStrings<A> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+/// Default maximum length of a string read from an archive (4 MiB)
+///
+/// Pak strings are paths and compression method names, this only guards against allocating
+/// gigabytes for the length of a corrupt archive.
+pub const DEFAULT_MAX_STRING_LEN: usize = 4 << 20;
+
+/// An archive wrapper reading strings up to `.1` bytes instead of [`DEFAULT_MAX_STRING_LEN`]
+///
+/// Longer strings are rejected with an `InvalidData` error before their buffer is allocated.
+pub struct MaxStringLen<A>(pub A, pub usize);
+
+impl<A: Archive> Archive for MaxStringLen<A> {
+    fn is_reader(&self) -> bool {
+        self.0.is_reader()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.0.read_exact(buf)
+    }
+
+    fn strict_strings(&self) -> bool {
+        self.0.strict_strings()
+    }
+
+    fn max_string_len(&self) -> usize {
+        self.1
+    }
+
+    fn endian(&self) -> Endian {
+        self.0.endian()
+    }
+
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl<A: io::Seek> io::Seek for MaxStringLen<A> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+/// Byte order of the numbers of an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Endian {
+    /// Little-endian, the byte order of pak files on PC and current consoles
+    #[default]
+    Little,
+    /// Big-endian, the byte order of pak files of some older console targets
+    Big,
+}
+
+/// An archive wrapper reading and writing numbers in the `.1` byte order
+///
+/// UTF-16 strings follow the same byte order, byte arrays (i.e. hashes) are kept as is.
+pub struct ByteOrder<A>(pub A, pub Endian);
+
+impl<A: Archive> Archive for ByteOrder<A> {
+    fn is_reader(&self) -> bool {
+        self.0.is_reader()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.0.read_exact(buf)
+    }
+
+    fn strict_strings(&self) -> bool {
+        self.0.strict_strings()
+    }
+
+    fn max_string_len(&self) -> usize {
+        self.0.max_string_len()
+    }
+
+    fn endian(&self) -> Endian {
+        self.1
+    }
+
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl<A: io::Seek> io::Seek for ByteOrder<A> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+/// Positional reads from a shared read-only source
+///
+/// Unlike a seekable `Archive`, reading doesn't require a mutable reference, so a single source
+/// (i.e. a memory mapped pak file) can be read from several threads without locking.
+pub trait ReadAt {
+    /// Read exactly `buf.len()` bytes starting at `offset` or return an error
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for &R {
+    #[inline]
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        (**self).read_at(offset, buf)
+    }
 }
 
-/// A read archive wrapper for `io::Read`
+impl<R: ReadAt + ?Sized> ReadAt for std::sync::Arc<R> {
+    #[inline]
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        (**self).read_at(offset, buf)
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let src = usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.get(start..start.checked_add(buf.len())?))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("{} bytes at {} are out of bounds ({})", buf.len(), offset, self.len()),
+                )
+            })?;
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    #[inline]
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.as_slice().read_at(offset, buf)
+    }
+}
+
+#[cfg(unix)]
+impl ReadAt for std::fs::File {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(self, buf, offset)
+    }
+}
+
+#[cfg(windows)]
+impl ReadAt for std::fs::File {
+    fn read_at(&self, mut offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+        use std::os::windows::fs::FileExt;
+
+        while !buf.is_empty() {
+            match self.seek_read(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A read archive wrapper for `io::Read + io::Seek`
 pub struct ArchiveReader<F>(pub F);
 
 /// A write archive wrapper for `io::Write`
 pub struct ArchiveWriter<F>(pub F);
 
+/// A write archive wrapper for `io::Write + io::Seek`
+///
+/// Unlike [`ArchiveWriter`], it implements [`Archive::seek`]: [`crate::PakFileBuilder`] then
+/// writes entry payloads as they come and seeks back to write their header.
+pub struct ArchiveSeekWriter<F>(pub F);
+
 /// A write archive wrapper that count written bytes
 pub struct ArchiveLen {
     len: u64,
@@ -60,6 +333,12 @@ impl ArchiveLen {
     }
 }
 
+impl Default for ArchiveLen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Archive for ArchiveLen {
     fn is_reader(&self) -> bool {
         false
@@ -75,7 +354,7 @@ impl Archive for ArchiveLen {
     }
 }
 
-impl<F: io::Read> Archive for io::BufReader<F> {
+impl<F: io::Read + io::Seek> Archive for io::BufReader<F> {
     fn is_reader(&self) -> bool {
         true
     }
@@ -87,6 +366,10 @@ impl<F: io::Read> Archive for io::BufReader<F> {
     fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
         io::Read::read_exact(self, buf)
     }
+
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        io::Seek::seek(self, pos)
+    }
 }
 
 impl<F: io::Write> Archive for io::BufWriter<F> {
@@ -115,7 +398,7 @@ impl<F: io::Read> io::Read for ArchiveReader<F> {
     }
 }
 
-impl<F: io::Read> Archive for ArchiveReader<F> {
+impl<F: io::Read + io::Seek> Archive for ArchiveReader<F> {
     fn is_reader(&self) -> bool {
         true
     }
@@ -127,6 +410,10 @@ impl<F: io::Read> Archive for ArchiveReader<F> {
     fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
         io::Read::read_exact(&mut self.0, buf)
     }
+
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        io::Seek::seek(&mut self.0, pos)
+    }
 }
 
 impl<F: io::Seek> io::Seek for ArchiveWriter<F> {
@@ -159,7 +446,46 @@ impl<F: io::Write> Archive for ArchiveWriter<F> {
     }
 }
 
-pub(crate) struct ArchiveLenSha1<A> {
+impl<F: io::Seek> io::Seek for ArchiveSeekWriter<F> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl<F: io::Write> io::Write for ArchiveSeekWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<F: io::Write + io::Seek> Archive for ArchiveSeekWriter<F> {
+    fn is_reader(&self) -> bool {
+        false
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        io::Write::write_all(&mut self.0, buf)
+    }
+
+    fn read_exact(&mut self, _buf: &mut [u8]) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "write only"))
+    }
+
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        io::Seek::seek(&mut self.0, pos)
+    }
+}
+
+/// An archive wrapper that count and SHA1 hash the bytes read or written through it
+///
+/// This is how pak files hash their index sections: wrap the archive, serialize the section, then
+/// compare [`ArchiveLenSha1::len_sha1`] with the size and hash stored in the footer or primary
+/// index. Seeking is not supported as it would skip bytes from the hash.
+pub struct ArchiveLenSha1<A> {
     ar: A,
     bytes: u64,
     sha1: Sha1,
@@ -174,13 +500,23 @@ impl<A> ArchiveLenSha1<A> {
         &mut self.ar
     }
 
+    pub fn into_inner(self) -> A {
+        self.ar
+    }
+
+    /// Number of bytes and SHA1 hash of the bytes since the last call, then reset both
     pub fn len_sha1(&mut self) -> (u64, [u8; 20]) {
         (std::mem::take(&mut self.bytes), std::mem::take(&mut self.sha1).finalize().into())
     }
 
+    /// Number of bytes since the last [`ArchiveLenSha1::len_sha1`] call
     pub const fn len(&self) -> u64 {
         self.bytes
     }
+
+    pub const fn is_empty(&self) -> bool {
+        self.bytes == 0
+    }
 }
 
 impl<A: Archive> Archive for ArchiveLenSha1<A> {
@@ -201,12 +537,34 @@ impl<A: Archive> Archive for ArchiveLenSha1<A> {
         self.bytes += buf.len() as u64;
         Ok(())
     }
+
+    fn strict_strings(&self) -> bool {
+        self.ar.strict_strings()
+    }
+
+    fn max_string_len(&self) -> usize {
+        self.ar.max_string_len()
+    }
+
+    fn endian(&self) -> Endian {
+        self.ar.endian()
+    }
+}
+
+impl<R: io::Read> io::Read for ArchiveLenSha1<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.ar.read(buf)?;
+        self.sha1.update(&buf[0..read]);
+        self.bytes += read as u64;
+        Ok(read)
+    }
 }
 
 impl<W: io::Write> io::Write for ArchiveLenSha1<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let written = self.ar.write(buf)?;
         self.sha1.update(&buf[0..written]);
+        self.bytes += written as u64;
         Ok(written)
     }
 
@@ -219,6 +577,17 @@ impl<W: io::Write> io::Write for ArchiveLenSha1<W> {
 pub trait Archivable: 'static {
     fn ser_de<A: Archive>(&mut self, ar: &mut A) -> io::Result<()>;
 
+    /// Serialize `items` one after the other, overridden by types that can do it at once
+    fn ser_de_slice<A: Archive>(items: &mut [Self], ar: &mut A) -> io::Result<()>
+    where
+        Self: Sized,
+    {
+        for item in items {
+            item.ser_de(ar)?;
+        }
+        Ok(())
+    }
+
     fn ser_de_len(&mut self) -> u64 {
         let mut ar = ArchiveLen::new();
         self.ser_de(&mut ar).unwrap();
@@ -305,10 +674,18 @@ impl Archivable for [u32] {
     }
 }
 
+/// Fixed-size arrays are serialized as their elements, without length
+///
+/// Byte arrays (i.e. hashes) are read or written at once, like `[u8]`.
+impl<T: Archivable + Default + Copy, const N: usize> Archivable for [T; N] {
+    fn ser_de<A: Archive>(&mut self, ar: &mut A) -> io::Result<()> {
+        T::ser_de_slice(self, ar)
+    }
+}
+
 impl<T: Archivable + Default> Archivable for Vec<T> {
     fn ser_de<A: Archive>(&mut self, ar: &mut A) -> io::Result<()> {
-        let mut len =
-            u32::try_from(self.len()).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let mut len = u32::try_from(self.len()).map_err(io::Error::other)?;
         len.ser_de(ar)?;
         if ar.is_reader() {
             self.clear();
@@ -322,8 +699,7 @@ impl<T: Archivable + Default> Archivable for Vec<T> {
 }
 impl<E: Copy, T: ArchivableWith<E> + Default> ArchivableWith<E> for Vec<T> {
     fn ser_de_with<A: Archive>(&mut self, ar: &mut A, extra: E) -> io::Result<()> {
-        let mut len =
-            u32::try_from(self.len()).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let mut len = u32::try_from(self.len()).map_err(io::Error::other)?;
         len.ser_de(ar)?;
         if ar.is_reader() {
             self.clear();
@@ -336,24 +712,30 @@ impl<E: Copy, T: ArchivableWith<E> + Default> ArchivableWith<E> for Vec<T> {
     }
 }
 
+/// Pairs are serialized as the first element followed by the second one
+impl<T: Archivable, U: Archivable> Archivable for (T, U) {
+    fn ser_de<A: Archive>(&mut self, ar: &mut A) -> io::Result<()> {
+        self.0.ser_de(ar)?;
+        self.1.ser_de(ar)
+    }
+}
+
 impl<K: Archivable + Default + Clone + Ord, V: Archivable + Default> Archivable for BTreeMap<K, V> {
     fn ser_de<A: Archive>(&mut self, ar: &mut A) -> io::Result<()> {
-        let mut len =
-            u32::try_from(self.len()).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let mut len = u32::try_from(self.len()).map_err(io::Error::other)?;
         len.ser_de(ar)?;
         if ar.is_reader() {
             self.clear();
             for _ in 0..len {
-                let mut key = K::default();
-                key.ser_de(ar)?;
-                let mut value = V::default();
-                value.ser_de(ar)?;
+                let (key, value) = <(K, V)>::de(ar)?;
                 self.insert(key, value);
             }
         } else {
             for (key, value) in self {
-                key.clone().ser_de(ar)?;
-                value.ser_de(ar)?
+                let mut pair = (key.clone(), mem::take(value));
+                let res = pair.ser_de(ar);
+                *value = pair.1;
+                res?;
             }
         }
         Ok(())
@@ -367,16 +749,33 @@ macro_rules! doit {
                 if ar.is_reader() {
                     let mut bytes: [u8; mem::size_of::<Self>()] = Default::default();
                     bytes.ser_de(ar)?;
-                    *self = Self::from_le_bytes(bytes);
+                    *self = match ar.endian() {
+                        Endian::Little => Self::from_le_bytes(bytes),
+                        Endian::Big => Self::from_be_bytes(bytes),
+                    };
                     Ok(())
                 } else {
-                    self.to_le_bytes().ser_de(ar)
+                    match ar.endian() {
+                        Endian::Little => self.to_le_bytes(),
+                        Endian::Big => self.to_be_bytes(),
+                    }
+                    .ser_de(ar)
                 }
             }
         }
     )+};
 }
-doit!(u8, u16, u32, u64, i8, i16, i32, i64, usize);
+doit!(u16, u32, u64, i8, i16, i32, i64, usize);
+
+impl Archivable for u8 {
+    fn ser_de<A: Archive>(&mut self, ar: &mut A) -> io::Result<()> {
+        slice::from_mut(self).ser_de(ar)
+    }
+
+    fn ser_de_slice<A: Archive>(items: &mut [Self], ar: &mut A) -> io::Result<()> {
+        items.ser_de(ar)
+    }
+}
 
 impl Archivable for bool {
     fn ser_de<A: Archive>(&mut self, ar: &mut A) -> io::Result<()> {
@@ -392,30 +791,214 @@ impl Archivable for bool {
 }
 
 impl Archivable for String {
+    /// FString: an `i32` length including the null terminator, followed by the characters
+    ///
+    /// Positive lengths are UTF-8 (ASCII for UE) bytes, negative lengths are UTF-16 code units in
+    /// the archive byte order.
+    /// Empty strings have no data. ASCII strings are written as bytes, others as UTF-16.
     fn ser_de<A: Archive>(&mut self, ar: &mut A) -> io::Result<()> {
-        let mut len =
-            u32::try_from(self.len()).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
-        len.ser_de(ar)?;
-        let tmp = mem::take(self);
-        let mut buffer = tmp.into_bytes();
-        if ar.is_reader() {
-            buffer.resize(len as usize, 0);
-            ar.read_exact(&mut buffer)?;
-            match buffer.pop() {
-                Some(0) => (),
-                _ => {
-                    return Err(io::Error::new(io::ErrorKind::Other, "strings are null terminated"))
+        if !ar.is_reader() {
+            let mut len = if self.is_empty() {
+                0
+            } else if self.is_ascii() {
+                i32::try_from(self.len() + 1).map_err(io::Error::other)?
+            } else {
+                let units = self.encode_utf16().count() + 1;
+                -i32::try_from(units).map_err(io::Error::other)?
+            };
+            len.ser_de(ar)?;
+            if len > 0 {
+                ar.write_all(self.as_bytes())?;
+                ar.write_all(&[0])?;
+            } else if len < 0 {
+                for mut unit in self.encode_utf16().chain(std::iter::once(0)) {
+                    unit.ser_de(ar)?;
                 }
             }
-            *self = String::from_utf8(buffer)
-                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
-        } else {
-            buffer.push(0u8);
-            ar.write_all(&buffer)?;
-            buffer.pop();
-            *self = String::from_utf8(buffer)
-                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            return Ok(());
+        }
+
+        let mut len = 0i32;
+        len.ser_de(ar)?;
+        self.clear();
+        let (units, unit_size) = match len {
+            0 => return Ok(()),
+            len if len > 0 => (len as usize, 1),
+            len => (len.unsigned_abs() as usize, 2),
+        };
+        if units * unit_size > ar.max_string_len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "string length {} is larger than the {} bytes limit",
+                    units * unit_size,
+                    ar.max_string_len()
+                ),
+            ));
         }
+        let mut buffer = vec![0u8; units * unit_size];
+        ar.read_exact(&mut buffer)?;
+        let terminated = buffer[buffer.len() - unit_size..].iter().all(|b| *b == 0);
+        if terminated {
+            buffer.truncate(buffer.len() - unit_size);
+        } else if ar.strict_strings() {
+            return Err(io::Error::other("strings are null terminated"));
+        }
+        *self = if unit_size == 1 {
+            String::from_utf8(buffer).map_err(io::Error::other)?
+        } else {
+            let endian = ar.endian();
+            let units = buffer.chunks_exact(2).map(|unit| match endian {
+                Endian::Little => u16::from_le_bytes([unit[0], unit[1]]),
+                Endian::Big => u16::from_be_bytes([unit[0], unit[1]]),
+            });
+            char::decode_utf16(units)
+                .collect::<Result<String, _>>()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+        };
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::AES_BLOCK_SIZE;
+
+    #[test]
+    fn align_boundaries() {
+        assert_eq!(align(0, AES_BLOCK_SIZE), 0);
+        assert_eq!(align(1, AES_BLOCK_SIZE), 16);
+        assert_eq!(align(15, AES_BLOCK_SIZE), 16);
+        assert_eq!(align(16, AES_BLOCK_SIZE), 16);
+        assert_eq!(align(17, AES_BLOCK_SIZE), 32);
+        assert_eq!(align(17, 1), 17);
+        assert_eq!(align(17, 0), 17);
+        assert_eq!(align(17, 12), 24);
+        assert_eq!(align(24, 12), 24);
+    }
+
+    #[test]
+    fn string_round_trip() {
+        for value in &["", "a", "../../../"] {
+            let mut ar = ArchiveWriter(io::Cursor::new(Vec::new()));
+            value.to_string().ser_de(&mut ar).unwrap();
+            let bytes = ar.0.into_inner();
+            assert_eq!(bytes.len(), if value.is_empty() { 4 } else { 4 + value.len() + 1 });
+            let decoded = String::de(&mut ArchiveReader(io::Cursor::new(bytes))).unwrap();
+            assert_eq!(&decoded, value);
+        }
+    }
+
+    #[test]
+    fn string_utf16() {
+        let mut ar = ArchiveWriter(io::Cursor::new(Vec::new()));
+        "Jeu/Forêt".to_owned().ser_de(&mut ar).unwrap();
+        let bytes = ar.0.into_inner();
+        assert_eq!(&bytes[..4], (-10i32).to_le_bytes());
+        assert_eq!(&bytes[4..8], b"J\0e\0");
+        assert_eq!(&bytes[bytes.len() - 2..], b"\0\0");
+        let decoded = String::de(&mut ArchiveReader(io::Cursor::new(&bytes))).unwrap();
+        assert_eq!(decoded, "Jeu/Forêt");
+
+        // unpaired surrogate
+        let bytes = b"\xFE\xFF\xFF\xFF\x00\xD8\0\0";
+        let err = String::de(&mut ArchiveReader(io::Cursor::new(bytes))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // i32::MIN code units
+        let bytes = i32::MIN.to_le_bytes();
+        let err = String::de(&mut ArchiveReader(io::Cursor::new(bytes))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn string_bogus_length() {
+        for len in [0x7FFF_FFF0u32, DEFAULT_MAX_STRING_LEN as u32 + 1] {
+            let mut bytes = len.to_le_bytes().to_vec();
+            bytes.extend_from_slice(b"abc\0");
+            let err = String::de(&mut ArchiveReader(io::Cursor::new(&bytes))).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let bytes = b"\x04\0\0\0abc\0";
+        let err = String::de(&mut MaxStringLen(ArchiveReader(io::Cursor::new(bytes)), 3));
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let value = String::de(&mut MaxStringLen(ArchiveReader(io::Cursor::new(bytes)), 4));
+        assert_eq!(value.unwrap(), "abc");
+    }
+
+    #[test]
+    fn pair_round_trip() {
+        let mut ar = ArchiveWriter(io::Cursor::new(Vec::new()));
+        ("a".to_owned(), 0x1234u32).ser(&mut ar).unwrap();
+        let bytes = ar.0.into_inner();
+        assert_eq!(bytes, b"\x02\0\0\0a\0\x34\x12\0\0");
+        let decoded = <(String, u32)>::de(&mut ArchiveReader(io::Cursor::new(bytes))).unwrap();
+        assert_eq!(decoded, ("a".to_owned(), 0x1234));
+    }
+
+    #[test]
+    fn lenient_strings() {
+        let terminated = b"\x04\0\0\0abc\0";
+        let unterminated = b"\x03\0\0\0abc";
+
+        let strict = |bytes: &[u8]| String::de(&mut ArchiveReader(io::Cursor::new(bytes)));
+        assert_eq!(strict(terminated).unwrap(), "abc");
+        assert!(strict(unterminated).is_err());
+
+        let lenient =
+            |bytes: &[u8]| String::de(&mut LenientStrings(ArchiveReader(io::Cursor::new(bytes))));
+        assert_eq!(lenient(terminated).unwrap(), "abc");
+        assert_eq!(lenient(unterminated).unwrap(), "abc");
+    }
+
+    #[test]
+    fn seek() {
+        let mut ar = MaxStringLen(LenientStrings(ArchiveReader(io::Cursor::new(b"abcd"))), 8);
+        assert_eq!(ar.seek(io::SeekFrom::End(-1)).unwrap(), 3);
+        let mut byte = [0u8];
+        ar.read_exact(&mut byte).unwrap();
+        assert_eq!(&byte, b"d");
+
+        let err = Archive::seek(&mut ArchiveLen::new(), io::SeekFrom::Start(0)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn fixed_size_arrays() {
+        let mut hash: [u8; 20] = std::array::from_fn(|i| i as u8);
+        let mut guid = [0x01020304u32, 5, 6, 0xFFFF_FFFF];
+        let mut from_slices = ArchiveWriter(Vec::new());
+        hash[..].ser_de(&mut from_slices).unwrap();
+        guid[..].ser_de(&mut from_slices).unwrap();
+        let mut from_arrays = ArchiveWriter(Vec::new());
+        hash.ser_de(&mut from_arrays).unwrap();
+        guid.ser_de(&mut from_arrays).unwrap();
+        assert_eq!(from_arrays.0, from_slices.0);
+        assert_eq!((hash.ser_de_len(), guid.ser_de_len()), (20, 16));
+
+        let mut ar = ArchiveReader(io::Cursor::new(from_arrays.0));
+        assert_eq!(<[u8; 20]>::de(&mut ar).unwrap(), hash);
+        assert_eq!(<[u32; 4]>::de(&mut ar).unwrap(), guid);
+    }
+
+    #[test]
+    fn len_sha1() {
+        let data = b"some index bytes";
+        let expected: [u8; 20] = Sha1::digest(data).into();
+
+        let mut ar = ArchiveLenSha1::new(ArchiveReader(io::Cursor::new(data)));
+        let mut buf = [0u8; 4];
+        ar.read_exact(&mut buf).unwrap();
+        assert_eq!(ar.len(), 4);
+        io::Read::read_to_end(&mut ar, &mut Vec::new()).unwrap();
+        assert_eq!(ar.len_sha1(), (data.len() as u64, expected));
+        assert!(ar.is_empty());
+
+        let mut ar = ArchiveLenSha1::new(ArchiveWriter(Vec::new()));
+        Archive::write_all(&mut ar, &data[..4]).unwrap();
+        io::Write::write_all(&mut ar, &data[4..]).unwrap();
+        assert_eq!(ar.len_sha1(), (data.len() as u64, expected));
+        assert_eq!(ar.into_inner().0, data);
+    }
+}
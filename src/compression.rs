@@ -0,0 +1,99 @@
+use std::io::{self, Read, Write};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+
+use crate::PakError;
+
+/// Decompressor of a single compression block
+///
+/// It gets the compressed block and an output buffer sized to the decompressed block size.
+pub type BlockDecompressor = Box<dyn Fn(&[u8], &mut [u8]) -> io::Result<()> + Send + Sync>;
+
+/// Decompress a single compression block with the compression method named `method`
+///
+/// The decompressed data is appended to `out`. Method names are the ones stored in the pak file
+/// (`PakInfo::compression_methods`) and are compared case insensitively. Blocks have no fixed
+/// decompressed size, the last block of an entry is usually shorter than the others.
+pub fn decompress_block(method: &str, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+    if method.eq_ignore_ascii_case("Zlib") {
+        ZlibDecoder::new(input).read_to_end(out)?;
+    } else if method.eq_ignore_ascii_case("Gzip") {
+        GzDecoder::new(input).read_to_end(out)?;
+    } else {
+        return Err(PakError::UnsupportedCompression(method.to_owned()).into());
+    }
+    Ok(())
+}
+
+/// `true` if [`compress_block`] supports the compression method named `method`
+pub fn can_compress(method: &str) -> bool {
+    method.eq_ignore_ascii_case("Zlib") || method.eq_ignore_ascii_case("Gzip")
+}
+
+/// Compress a single compression block with the compression method named `method`
+///
+/// The compressed data is appended to `out`. Only "Zlib" and "Gzip" are supported, with the
+/// default compression level.
+pub fn compress_block(method: &str, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+    if method.eq_ignore_ascii_case("Zlib") {
+        let mut encoder = ZlibEncoder::new(out, Compression::default());
+        encoder.write_all(input)?;
+        encoder.finish()?;
+    } else if method.eq_ignore_ascii_case("Gzip") {
+        let mut encoder = GzEncoder::new(out, Compression::default());
+        encoder.write_all(input)?;
+        encoder.finish()?;
+    } else {
+        return Err(PakError::UnsupportedCompression(method.to_owned()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zlib_blocks() {
+        let mut out = Vec::new();
+        for block in [&[1u8; 100][..], &[2u8; 10][..]] {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(block).unwrap();
+            decompress_block("zlib", &encoder.finish().unwrap(), &mut out).unwrap();
+        }
+        assert_eq!(out.len(), 110);
+        assert!(out[..100].iter().all(|b| *b == 1) && out[100..].iter().all(|b| *b == 2));
+
+        let err = decompress_block("Oodle", &[], &mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(decompress_block("Zlib", &[0xFF; 8], &mut out).is_err());
+    }
+
+    #[test]
+    fn gzip_blocks() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"gzip block").unwrap();
+        let mut out = Vec::new();
+        decompress_block("Gzip", &encoder.finish().unwrap(), &mut out).unwrap();
+        assert_eq!(out, b"gzip block");
+        assert!(decompress_block("Gzip", &[0xFF; 8], &mut out).is_err());
+    }
+
+    #[test]
+    fn compress_blocks() {
+        for method in ["Zlib", "gzip"] {
+            assert!(can_compress(method));
+            let mut compressed = Vec::new();
+            compress_block(method, &[7u8; 1000], &mut compressed).unwrap();
+            assert!(compressed.len() < 1000);
+            let mut out = Vec::new();
+            decompress_block(method, &compressed, &mut out).unwrap();
+            assert_eq!(out, [7u8; 1000]);
+        }
+        assert!(!can_compress("Oodle"));
+        let err = compress_block("Oodle", &[], &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,128 @@
+use std::{error, fmt, io, str};
+
+use crate::archive::{Archivable, Archive};
+
+/// FGuid archivable, a 128 bits identifier stored as four `u32`
+///
+/// Pak files use it to name the encryption key, a zero guid means the embedded (default) key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Guid(pub [u32; 4]);
+
+impl Guid {
+    /// The zero guid
+    pub const ZERO: Guid = Guid([0; 4]);
+
+    pub const fn is_zero(&self) -> bool {
+        let Guid([a, b, c, d]) = *self;
+        a == 0 && b == 0 && c == 0 && d == 0
+    }
+}
+
+impl From<[u32; 4]> for Guid {
+    fn from(parts: [u32; 4]) -> Self {
+        Guid(parts)
+    }
+}
+
+impl Archivable for Guid {
+    fn ser_de<A: Archive>(&mut self, ar: &mut A) -> io::Result<()> {
+        self.0.ser_de(ar)
+    }
+}
+
+/// UE `EGuidFormats::DigitsWithHyphens` format, i.e. `00000001-0002-0000-0003-000000000004`
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Guid([a, b, c, d]) = *self;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:04X}-{:04X}{:08X}",
+            a,
+            b >> 16,
+            b & 0xFFFF,
+            c >> 16,
+            c & 0xFFFF,
+            d
+        )
+    }
+}
+
+/// Parses the [`Display`](fmt::Display) format as well as UE `EGuidFormats::Digits` (32 hex
+/// digits, the crypto.json format), optionally enclosed in braces
+impl str::FromStr for Guid {
+    type Err = ParseGuidError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix('{').and_then(|s| s.strip_suffix('}')).unwrap_or(s);
+        let digits: String = match s.len() {
+            32 => s.to_owned(),
+            36 if [8, 13, 18, 23].iter().all(|&i| s.as_bytes()[i] == b'-') => {
+                s.split('-').collect()
+            }
+            _ => return Err(ParseGuidError(s.to_owned())),
+        };
+        if digits.len() != 32 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseGuidError(s.to_owned()));
+        }
+        let mut parts = [0u32; 4];
+        for (part, chunk) in parts.iter_mut().zip(digits.as_bytes().chunks_exact(8)) {
+            let chunk = str::from_utf8(chunk).expect("hex digits are ASCII");
+            *part = u32::from_str_radix(chunk, 16).map_err(|_| ParseGuidError(s.to_owned()))?;
+        }
+        Ok(Guid(parts))
+    }
+}
+
+/// Serialized in the [`Display`](fmt::Display) format
+#[cfg(feature = "serde")]
+impl serde::Serialize for Guid {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Guid {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let guid = <String as serde::Deserialize>::deserialize(deserializer)?;
+        guid.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Error of [`Guid::from_str`](str::FromStr::from_str)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseGuidError(String);
+
+impl fmt::Display for ParseGuidError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid guid {:?}", self.0)
+    }
+}
+
+impl error::Error for ParseGuidError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_and_parse() {
+        let guid = Guid([0x01234567, 0x89ABCDEF, 0x00112233, 0x44556677]);
+        let text = guid.to_string();
+        assert_eq!(text, "01234567-89AB-CDEF-0011-223344556677");
+        assert_eq!(text.parse(), Ok(guid));
+        assert_eq!(format!("{{{}}}", text).parse(), Ok(guid));
+        assert_eq!("0123456789abcdef0011223344556677".parse(), Ok(guid));
+
+        assert!(Guid::ZERO.is_zero());
+        assert!(!guid.is_zero());
+        assert_eq!(Guid::ZERO.to_string().parse(), Ok(Guid::ZERO));
+
+        for invalid in
+            ["", "01234567-89AB-CDEF-0011-22334455667", "0123456789ABCDEF001122334455667G"]
+        {
+            assert!(invalid.parse::<Guid>().is_err(), "{:?}", invalid);
+        }
+        assert!("01234567089AB0CDEF000110223344556677".parse::<Guid>().is_err());
+    }
+}
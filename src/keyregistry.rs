@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "crypto-json")]
+use std::io;
+
+#[cfg(feature = "crypto-json")]
+use serde_json::Value;
+
+use crate::{aes256_base64_key, Aes256Key, Guid, PakError};
+
+/// Encryption keys by encryption key guid
+///
+/// The key registered under [`Guid::ZERO`] is the default (embedded) key, used by pak files that
+/// don't name their key. See [`PakFile::load_any_with_registry`](crate::PakFile).
+#[derive(Clone, Default)]
+pub struct KeyRegistry {
+    keys: HashMap<Guid, Aes256Key>,
+}
+
+impl fmt::Debug for KeyRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // keys are secrets, only show which guids have one
+        f.debug_set().entries(self.keys.keys()).finish()
+    }
+}
+
+impl KeyRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the base64 encoded AES key `key` for `guid`, replacing any previous one
+    pub fn insert(&mut self, guid: Guid, key: &str) -> Result<(), PakError> {
+        self.keys.insert(guid, aes256_base64_key(key)?);
+        Ok(())
+    }
+
+    /// Raw 32 bytes key registered for `guid`
+    pub fn get(&self, guid: Guid) -> Option<&[u8]> {
+        self.keys.get(&guid).map(|key| &key[..])
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub(crate) fn aes_key(&self, guid: Guid) -> Option<&Aes256Key> {
+        self.keys.get(&guid)
+    }
+
+    /// Load the keys of a UE crypto.json file
+    ///
+    /// `EncryptionKey` is registered as the default key and every `SecondaryEncryptionKeys` item
+    /// under its `Guid`. Missing or empty keys are skipped. Requires the `crypto-json` feature.
+    #[cfg(feature = "crypto-json")]
+    pub fn from_crypto_json(json: &str) -> Result<Self, PakError> {
+        let json: Value = serde_json::from_str(json)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let mut registry = Self::new();
+        if let Some(key) = json["EncryptionKey"]["Key"].as_str().filter(|key| !key.is_empty()) {
+            registry.insert(Guid::ZERO, key)?;
+        }
+        for secondary in json["SecondaryEncryptionKeys"].as_array().into_iter().flatten() {
+            let key = match secondary["Key"].as_str().filter(|key| !key.is_empty()) {
+                Some(key) => key,
+                None => continue,
+            };
+            let guid = secondary["Guid"].as_str().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "secondary encryption key without guid")
+            })?;
+            let guid =
+                guid.parse().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            registry.insert(guid, key)?;
+        }
+        Ok(registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+    #[test]
+    #[cfg(feature = "crypto-json")]
+    fn crypto_json() {
+        let json = r#"{
+            "$types": {},
+            "EncryptionKey": { "Name": null, "Guid": null, "Key": "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=" },
+            "SecondaryEncryptionKeys": [
+                { "Name": "DLC", "Guid": "0123456789ABCDEF0011223344556677", "Key": "HwAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=" },
+                { "Name": "Unused", "Guid": "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF", "Key": "" }
+            ],
+            "bEnablePakIndexEncryption": true
+        }"#;
+        let registry = KeyRegistry::from_crypto_json(json).unwrap();
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.get(Guid::ZERO), Some(&(0..32).collect::<Vec<u8>>()[..]));
+        let guid = Guid([0x01234567, 0x89ABCDEF, 0x00112233, 0x44556677]);
+        assert_eq!(registry.get(guid).map(|key| key[0]), Some(0x1F));
+        assert!(registry.get(Guid([u32::MAX; 4])).is_none());
+        assert!(KeyRegistry::from_crypto_json("{").is_err());
+    }
+
+    #[test]
+    fn insert() {
+        let guid = Guid([0x01234567, 0x89ABCDEF, 0x00112233, 0x44556677]);
+        let mut registry = KeyRegistry::new();
+        assert!(matches!(registry.insert(guid, "not a key"), Err(PakError::InvalidKey(_))));
+        registry.insert(guid, KEY).unwrap();
+        assert!(registry.get(Guid::ZERO).is_none());
+    }
+}
@@ -1,46 +1,66 @@
 /// Raw FArchive tools
 pub mod archive;
+/// Compression methods of pak entries
+pub mod compression;
+mod guid;
+mod keyregistry;
 mod pakbuilder;
 mod pakentry;
+mod pakerror;
 mod pakfile;
 mod pakindex;
 mod pakindexv1;
 mod pakindexv2;
 mod pakinfo;
+/// Hex strings for SHA1 hashes
+#[cfg(feature = "serde")]
+mod serdehex;
+/// In memory pak files for tests
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
 
 use std::{fmt, io};
 
+#[allow(deprecated)]
 use aes::cipher::generic_array::GenericArray;
 use aes::{Aes256, BlockCipher, NewBlockCipher};
 use block_modes::block_padding::NoPadding;
 use block_modes::{BlockMode, Ecb};
-pub use pakbuilder::{AssetWriter, PakFileBuilder};
+pub use guid::{Guid, ParseGuidError};
+pub use keyregistry::KeyRegistry;
+pub use pakbuilder::{AssetWriter, PakFileBuilder, RepackIssue};
 pub use pakentry::{PakCompressedBlock, PakEntry};
-pub use pakfile::PakFile;
-pub use pakindex::PakIndex;
+pub use pakerror::PakError;
+pub use pakfile::{
+    EncryptionStatus, EntryReader, ExtractSummary, OwnedEntryReader, PakFile, PakSplit,
+};
+pub use pakindex::{IndexChange, PakIndex};
 pub use pakindexv1::PakIndexV1;
-pub use pakindexv2::{PakEntryLocation, PakIndexV2};
-pub use pakinfo::PakInfo;
+pub use pakindexv2::{PakEntryLocation, PakIndexV2, SectionSizes};
+pub use pakinfo::{PakInfo, PakInfoBuilder, PakInfoError};
 use sha1::digest::generic_array::typenum::Unsigned;
 
 type Aes256KeySize = <Aes256 as NewBlockCipher>::KeySize;
 type Aes256BlockSize = <Aes256 as BlockCipher>::BlockSize;
+#[allow(deprecated)]
 type Aes256Key = GenericArray<u8, Aes256KeySize>;
+#[allow(deprecated)]
+type Aes256Block = GenericArray<u8, Aes256BlockSize>;
 type Aes256Cipher = Ecb<Aes256, NoPadding>;
 
 fn aes256_base64_key(key: &str) -> io::Result<Aes256Key> {
-    let key = base64::decode(key).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let key = base64::decode(key).map_err(|err| PakError::InvalidKey(err.to_string()))?;
     if key.len() != Aes256KeySize::USIZE {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!(
-                "invalid base64 key size, found a {} bytes key, expecting a {} bytes key",
-                key.len(),
-                Aes256KeySize::USIZE
-            ),
-        ));
+        return Err(PakError::InvalidKey(format!(
+            "found a {} bytes key, expecting a {} bytes key",
+            key.len(),
+            Aes256KeySize::USIZE
+        ))
+        .into());
     }
-    Ok(*Aes256Key::from_slice(&key))
+    let mut aes_key = Aes256Key::default();
+    aes_key.copy_from_slice(&key);
+    Ok(aes_key)
 }
 
 fn aes256_ecb_cipher(key: &Aes256Key) -> Aes256Cipher {
@@ -48,6 +68,7 @@ fn aes256_ecb_cipher(key: &Aes256Key) -> Aes256Cipher {
 }
 
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PakVersion {
     Initial,
     NoTimestamps,
@@ -114,10 +135,24 @@ impl PakVersion {
             PakVersion::Fnv64BugFix => 11,
         }
     }
+
+    /// Inverse of [`PakVersion::raw`], `None` for unknown version numbers
+    ///
+    /// Version 8 is ambiguous: 4.22 paks have one less compression method slot than 4.23+ paks,
+    /// which only the footer size tells apart. It maps to `FNameBasedCompressionMethod` (4.23+).
+    pub fn from_raw(raw: i32) -> Option<Self> {
+        match raw {
+            8 => Some(PakVersion::FNameBasedCompressionMethod),
+            raw => Self::list().iter().copied().find(|version| version.raw() == raw),
+        }
+    }
 }
 
 pub mod constants {
 
+    /// Size of an AES block, encrypted data is aligned to this size
+    pub const AES_BLOCK_SIZE: u64 = 16;
+
     /// Magic number to use in header
     pub const PAK_FILE_MAGIC: u32 = 0x5A6F12E1;
     /// Size of cached data.
@@ -1,29 +1,22 @@
-use core::slice;
-use std::io::{self, Write};
+use std::fmt;
+use std::io;
 
-use aes::cipher::generic_array::GenericArray;
 use block_modes::BlockMode;
-use sha1::digest::generic_array::typenum::Unsigned;
+use sha1::{Digest, Sha1};
 
-use crate::archive::{Archivable, Archive, ArchiveLenSha1};
-use crate::pakentry::FLAG_DELETED;
+use crate::archive::{align, Archivable, ArchivableWith, Archive, ArchiveLenSha1};
+use crate::compression::{can_compress, compress_block};
+use crate::constants::AES_BLOCK_SIZE;
+use crate::pakentry::{FLAG_DELETED, FLAG_ENCRYPTED};
 use crate::pakindex::PakIndex;
-use crate::pakindexv1::PakIndexV1;
 use crate::pakindexv2::PakIndexV2;
-use crate::{aes256_base64_key, aes256_ecb_cipher, Aes256BlockSize};
-use crate::{Aes256Cipher, Aes256Key, PakEntry, PakFile, PakInfo, PakVersion};
-
-/// Aligns to the nearest higher multiple of `alignment`
-fn align_arbitrary(v: u64, alignment: u64) -> u64 {
-    match alignment {
-        0 => v,
-        _ => ((v + alignment - 1) / alignment) * alignment,
-    }
-}
+use crate::PakVersion;
+use crate::{aes256_base64_key, aes256_ecb_cipher, Aes256Block};
+use crate::{Aes256Cipher, Aes256Key, PakCompressedBlock, PakEntry, PakError, PakFile, PakInfo};
 
 pub struct Cipher {
     cipher: Aes256Cipher,
-    buf: GenericArray<u8, Aes256BlockSize>,
+    buf: Aes256Block,
     pending: usize,
 }
 
@@ -31,62 +24,371 @@ impl Cipher {
     pub fn new(cipher: Aes256Cipher) -> Self {
         Self { cipher, buf: Default::default(), pending: 0 }
     }
+
+    /// Encrypt the complete AES blocks of the pending bytes followed by `buf`
+    ///
+    /// The remaining bytes are kept pending until the next call.
+    fn update(&mut self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        let mut blocks = Vec::with_capacity(self.pending + buf.len());
+        blocks.extend_from_slice(&self.buf[..self.pending]);
+        blocks.extend_from_slice(buf);
+        let len = blocks.len() - blocks.len() % AES_BLOCK_SIZE as usize;
+        self.pending = blocks.len() - len;
+        self.buf[..self.pending].copy_from_slice(&blocks[len..]);
+        blocks.truncate(len);
+        self.cipher.clone().encrypt(&mut blocks, len).map_err(io::Error::other)?;
+        Ok(blocks)
+    }
+
+    /// Encrypt the pending bytes padded with zeros to the AES block size
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        match self.pending {
+            0 => Ok(Vec::new()),
+            pending => self.update(&[0u8; AES_BLOCK_SIZE as usize][pending..]),
+        }
+    }
+}
+
+/// Payload of an [`AssetWriter`] written to the archive as it comes
+struct PayloadStream {
+    /// Size of the header written before the payload
+    header_len: u64,
+    /// `true` if the written header is a placeholder to overwrite in [`AssetWriter::finalize`]
+    placeholder: bool,
+    len: u64,
+    sha1: Sha1,
+}
+
+/// Default size of the compression blocks of [`PakFileBuilder::add_auto`] entries
+const DEFAULT_COMPRESSION_BLOCK_SIZE: u32 = 0x10000;
+
+/// Compression methods tried by [`PakFileBuilder::add_auto`]
+const AUTO_COMPRESSION_METHODS: [&str; 2] = ["Zlib", "Gzip"];
+
+/// Compression of an [`AssetWriter`] payload
+enum BlockCompression {
+    /// Blocks are compressed as the payload is written, see [`PakFileBuilder::add_compressed`]
+    Streaming {
+        method: String,
+        method_index: u32,
+        block_size: u32,
+        /// Uncompressed bytes of the block being written
+        pending: Vec<u8>,
+    },
+    /// The payload is buffered to pick the best method, see [`PakFileBuilder::add_auto`]
+    Auto { block_size: u32, min_savings: f64 },
 }
 
+/// Writer of a single pak entry, see [`PakFileBuilder::add`]
+///
+/// The entry header precedes the payload and contains its size and hash. If the archive can seek
+/// (see [`crate::archive::ArchiveSeekWriter`]), a placeholder header is written and the payload
+/// follows as it comes, [`AssetWriter::finalize`] then seeks back to write the actual header.
+/// Imported entries have a known header which is written first. Otherwise, and for compressed
+/// entries whose size isn't known (see [`AssetWriter::set_uncompressed_size`]), the payload is
+/// buffered until `finalize` writes the header followed by the payload.
 pub struct AssetWriter<'a, A: Archive> {
     cipher: Option<Cipher>,
+    compression: Option<BlockCompression>,
     builder: &'a mut PakFileBuilder,
-    ar: ArchiveLenSha1<A>,
+    ar: A,
+    expected_size: Option<u64>,
+    started: bool,
+    stream: Option<PayloadStream>,
+    data: Vec<u8>,
     name: String,
     entry: PakEntry,
     import: bool,
 }
 
 impl<'a, A: Archive> AssetWriter<'a, A> {
+    /// Number of payload bytes written or buffered so far
+    ///
+    /// For [`PakFileBuilder::add_compressed`] entries, this is the size of the complete blocks
+    /// once compressed.
     pub fn size(&self) -> u64 {
-        self.builder.pos - self.entry.offset
+        match &self.stream {
+            Some(stream) => stream.len,
+            None => self.data.len() as u64,
+        }
+    }
+
+    /// Announce the uncompressed size of the payload before writing it
+    ///
+    /// The header of [`PakFileBuilder::add_compressed`] entries lists every compression block, so
+    /// its size depends on the payload size. Once it is known, blocks are written to a seekable
+    /// archive as soon as they are compressed instead of being buffered. [`AssetWriter::finalize`]
+    /// fails if the written payload doesn't have this size.
+    pub fn set_uncompressed_size(&mut self, size: u64) -> Result<(), PakError> {
+        if self.started {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the payload size must be set before writing it",
+            )
+            .into());
+        }
+        self.expected_size = Some(size);
+        Ok(())
     }
 
     pub fn get_mut(&mut self) -> &mut A {
-        self.ar.get_mut()
+        &mut self.ar
     }
 
-    pub fn finalize(mut self) -> io::Result<&'a mut PakEntry> {
-        if let Some(cipher) = &mut self.cipher {
-            if cipher.pending > 0 {
-                let zeros = GenericArray::<u8, Aes256BlockSize>::default();
-                let n = cipher.buf.len() - cipher.pending;
-                cipher.buf[cipher.pending..].copy_from_slice(&zeros[..n]);
-                cipher.cipher.encrypt_blocks(slice::from_mut(&mut cipher.buf));
-                self.ar.write_all(&cipher.buf)?;
-                self.builder.pos += Aes256BlockSize::U64;
-                self.entry.uncompressed_size += Aes256BlockSize::U64;
-                cipher.pending = 0;
+    /// Pad the encrypted payload, check imported entries, write the entry and add it to the index
+    ///
+    /// Imported payloads of the wrong size are reported as [`PakError::ImportMismatch`] and those
+    /// of the wrong hash as [`PakError::HashMismatch`], the payload is already written to the
+    /// archive then. Write failures keep their kind with the entry name added as context.
+    ///
+    /// The returned entry is added to the index along with the next entry (or when the pak file
+    /// is finalized), changes made to it until then are written to the index.
+    pub fn finalize(mut self) -> Result<&'a mut PakEntry, PakError> {
+        self.start()?;
+        match self.compression.take() {
+            Some(BlockCompression::Streaming { method, method_index, block_size, pending }) => {
+                if !pending.is_empty() {
+                    let mut compressed = Vec::new();
+                    compress_block(&method, &pending, &mut compressed)?;
+                    self.push_block(compressed)?;
+                }
+                self.finish_blocks(method_index, block_size);
+            }
+            Some(BlockCompression::Auto { block_size, min_savings }) => {
+                self.compress_auto(block_size, min_savings)?;
+            }
+            None => {
+                if let Some(cipher) = &mut self.cipher {
+                    let padding = cipher.finish()?;
+                    self.emit(&padding)?;
+                }
             }
         }
 
-        self.flush()?;
-        let (size, hash) = self.ar.len_sha1();
-        if self.import {
-            if self.entry.size != size {
+        if let Some(expected) = self.expected_size {
+            let written = self.entry.uncompressed_size;
+            if !self.import && written != expected {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidInput,
-                    "imported entry size doesn't match written size",
-                ));
+                    format!(
+                        "entry {:?} payload is {} bytes, {} bytes were announced",
+                        self.name, written, expected
+                    ),
+                )
+                .into());
+            }
+        }
+
+        // encrypted payloads are padded to the AES block size, `size` doesn't include padding
+        let (disk_size, hash): (u64, [u8; 20]) = match &self.stream {
+            Some(stream) => (stream.len, stream.sha1.clone().finalize().into()),
+            None => (self.data.len() as u64, Sha1::digest(&self.data).into()),
+        };
+        if self.import {
+            let expected_disk_size = self.entry.disk_size();
+            if expected_disk_size != disk_size {
+                return Err(PakError::ImportMismatch {
+                    field: "size".to_owned(),
+                    expected: expected_disk_size,
+                    got: disk_size,
+                });
             }
             if self.entry.hash != hash {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "imported entry hash doesn't match written hash",
-                ));
+                return Err(PakError::HashMismatch {
+                    context: format!("imported entry {:?}", self.name),
+                    expected: self.entry.hash,
+                    found: hash,
+                });
             }
         } else {
             self.entry.hash = hash;
-            self.entry.size = size;
+            if self.entry.compression_method_index == 0 {
+                self.entry.size = self.entry.uncompressed_size;
+            }
         }
-        let entry = self.builder.index.add(self.name, self.entry);
+
+        // the entry header stored before the payload has no offset
+        let version = self.builder.info.version;
+        let header = PakEntry { offset: 0, ..self.entry.clone() };
+        let name = &self.name;
+        let with_context = |err: io::Error| {
+            io::Error::new(err.kind(), format!("writing entry {:?}: {}", name, err))
+        };
+        let header_len = header.ser_len_with(version);
+        match &self.stream {
+            None => {
+                header.ser_with(&mut self.ar, version).map_err(with_context)?;
+                self.ar.write_all(&self.data).map_err(with_context)?;
+            }
+            Some(stream) if stream.placeholder => {
+                debug_assert_eq!(stream.header_len, header_len);
+                let payload_end = io::SeekFrom::Current(disk_size as i64);
+                let header_start = io::SeekFrom::Current(-((header_len + disk_size) as i64));
+                self.ar.seek(header_start).map_err(with_context)?;
+                header.ser_with(&mut self.ar, version).map_err(with_context)?;
+                self.ar.seek(payload_end).map_err(with_context)?;
+            }
+            Some(_) => {}
+        }
+        self.builder.pos += header_len + disk_size;
+
+        let builder = self.builder;
+        builder.flush_pending()?;
+        builder.check_add(&self.name)?;
+        let (_, entry) = builder.pending.insert((self.name, self.entry));
         Ok(entry)
     }
+
+    /// Write the header if the payload can be written as it comes, see [`AssetWriter`]
+    fn start(&mut self) -> io::Result<()> {
+        if self.started {
+            return Ok(());
+        }
+        self.started = true;
+        let placeholder = !self.import;
+        let mut header = PakEntry { offset: 0, ..self.entry.clone() };
+        if placeholder {
+            match (&self.compression, self.expected_size) {
+                (None, _) => {}
+                (
+                    Some(BlockCompression::Streaming { method_index, block_size, .. }),
+                    Some(size),
+                ) => {
+                    // only the number of blocks matters for the header size
+                    let blocks = size.div_ceil(u64::from(*block_size)) as usize;
+                    header.compression_method_index = *method_index;
+                    header.compression_blocks = vec![PakCompressedBlock::default(); blocks];
+                }
+                _ => return Ok(()),
+            }
+            match self.ar.seek(io::SeekFrom::Current(0)) {
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+        let version = self.builder.info.version;
+        header.ser_with(&mut self.ar, version)?;
+        self.stream = Some(PayloadStream {
+            header_len: header.ser_len_with(version),
+            placeholder,
+            len: 0,
+            sha1: Sha1::new(),
+        });
+        Ok(())
+    }
+
+    /// Write payload bytes to the archive, or buffer them until `finalize`
+    fn emit(&mut self, buf: &[u8]) -> io::Result<()> {
+        match &mut self.stream {
+            Some(stream) => {
+                stream.sha1.update(buf);
+                stream.len += buf.len() as u64;
+                self.ar.write_all(buf)
+            }
+            None => {
+                self.data.extend_from_slice(buf);
+                Ok(())
+            }
+        }
+    }
+
+    /// Compress the complete blocks of `buf`, keeping the rest for the next write
+    fn write_compressed(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let compressed = match &mut self.compression {
+                Some(BlockCompression::Streaming { method, block_size, pending, .. }) => {
+                    let n = (*block_size as usize - pending.len()).min(buf.len());
+                    pending.extend_from_slice(&buf[..n]);
+                    buf = &buf[n..];
+                    if pending.len() < *block_size as usize {
+                        continue;
+                    }
+                    let mut compressed = Vec::new();
+                    compress_block(method, pending, &mut compressed)?;
+                    pending.clear();
+                    compressed
+                }
+                _ => unreachable!("only streaming compression writes blocks"),
+            };
+            self.push_block(compressed)?;
+        }
+        Ok(())
+    }
+
+    /// Write a compressed block of the payload, encrypted blocks are padded to the AES block size
+    ///
+    /// Block offsets are relative to the payload start until `finish_blocks`.
+    fn push_block(&mut self, mut compressed: Vec<u8>) -> io::Result<()> {
+        let start = self.size();
+        self.entry.compression_blocks.push(PakCompressedBlock {
+            compressed_start: start,
+            compressed_end: start + compressed.len() as u64,
+        });
+        if let Some(cipher) = &self.cipher {
+            let len = align(compressed.len() as u64, AES_BLOCK_SIZE) as usize;
+            compressed.resize(len, 0);
+            cipher.cipher.clone().encrypt(&mut compressed, len).map_err(io::Error::other)?;
+        }
+        self.emit(&compressed)
+    }
+
+    /// Set the compression fields once every block has been pushed
+    ///
+    /// `size` is the padded size of all blocks while block ends don't include padding.
+    fn finish_blocks(&mut self, method_index: u32, block_size: u32) {
+        let version = self.builder.info.version;
+        self.entry.compression_method_index = method_index;
+        self.entry.compression_block_size =
+            u64::from(block_size).min(self.entry.uncompressed_size) as u32;
+        // before `RelativeChunkOffsets`, compression blocks offsets are absolute file offsets
+        let base_offset =
+            if version < PakVersion::RelativeChunkOffsets { self.entry.offset } else { 0 };
+        let payload_offset = base_offset + self.entry.ser_len_with(version);
+        for block in &mut self.entry.compression_blocks {
+            block.compressed_start += payload_offset;
+            block.compressed_end += payload_offset;
+        }
+        self.entry.size = self.size();
+    }
+
+    /// Replace the buffered payload by its blocks compressed with the best method
+    ///
+    /// If compression doesn't save at least `min_savings`, the payload is kept uncompressed.
+    fn compress_auto(&mut self, block_size: u32, min_savings: f64) -> io::Result<()> {
+        let raw = std::mem::take(&mut self.data);
+        let mut best: Option<(&str, Vec<Vec<u8>>, usize)> = None;
+        for method in AUTO_COMPRESSION_METHODS {
+            let blocks = raw
+                .chunks(block_size as usize)
+                .map(|chunk| {
+                    let mut compressed = Vec::new();
+                    compress_block(method, chunk, &mut compressed).map(|()| compressed)
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            let size = blocks.iter().map(Vec::len).sum();
+            if best.as_ref().is_none_or(|(_, _, best_size)| size < *best_size) {
+                best = Some((method, blocks, size));
+            }
+        }
+        let (method, blocks, size) = best.expect("at least one compression method");
+        if size as f64 >= raw.len() as f64 * (1.0 - min_savings) {
+            self.data = raw;
+            if let Some(cipher) = &self.cipher {
+                let len = align(self.data.len() as u64, AES_BLOCK_SIZE) as usize;
+                self.data.resize(len, 0);
+                cipher.cipher.clone().encrypt(&mut self.data, len).map_err(io::Error::other)?;
+            }
+            return Ok(());
+        }
+
+        let method_index = self.builder.register_method(method)?;
+        for compressed in blocks {
+            self.push_block(compressed)?;
+        }
+        self.finish_blocks(method_index, block_size);
+        Ok(())
+    }
 }
 
 impl<'a, A: Archive> io::Write for AssetWriter<'a, A> {
@@ -95,26 +397,21 @@ impl<'a, A: Archive> io::Write for AssetWriter<'a, A> {
         Ok(buf.len())
     }
 
-    fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
-        if let Some(cipher) = &mut self.cipher {
-            while !buf.is_empty() {
-                let n = (cipher.buf.len() - cipher.pending).min(buf.len());
-                cipher.buf[cipher.pending..].copy_from_slice(&buf[..n]);
-                cipher.pending += n;
-                if cipher.pending == Aes256BlockSize::USIZE {
-                    cipher.cipher.encrypt_blocks(slice::from_mut(&mut cipher.buf));
-                    self.ar.write_all(&cipher.buf)?;
-                    self.builder.pos += Aes256BlockSize::U64;
-                    self.entry.uncompressed_size += Aes256BlockSize::U64;
-                }
-                buf = &buf[n..];
-            }
-            //cipher.en
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.start()?;
+        if !self.import {
+            self.entry.uncompressed_size += buf.len() as u64;
+        }
+        if let Some(BlockCompression::Streaming { .. }) = self.compression {
+            self.write_compressed(buf)?;
+        } else if self.compression.is_some() {
+            // compressed and encrypted in finalize
+            self.data.extend_from_slice(buf);
+        } else if let Some(cipher) = &mut self.cipher {
+            let encrypted = cipher.update(buf)?;
+            self.emit(&encrypted)?;
         } else {
-            self.ar.write_all(buf)?;
-            let len = buf.len() as u64;
-            self.builder.pos += len;
-            self.entry.uncompressed_size += len;
+            self.emit(buf)?;
         }
         Ok(())
     }
@@ -124,67 +421,157 @@ impl<'a, A: Archive> io::Write for AssetWriter<'a, A> {
     }
 }
 
+/// Problem an entry would hit when the builder index is written for a given version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepackIssue {
+    /// The entry can't be stored in the compact encoding and is stored as a full entry
+    NotEncodable(String),
+    /// The path hashes of both entries collide
+    Collision(String, String),
+    /// The entry path has no directory part
+    NotInDirectory(String),
+}
+
+impl fmt::Display for RepackIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepackIssue::NotEncodable(name) => {
+                write!(f, "entry {:?} can't be compact encoded", name)
+            }
+            RepackIssue::Collision(name, other) => {
+                write!(f, "path hash of {:?} collides with {:?}", name, other)
+            }
+            RepackIssue::NotInDirectory(name) => {
+                write!(f, "entry {:?} is not inside a directory", name)
+            }
+        }
+    }
+}
+
 pub struct PakFileBuilder {
     pos: u64,
     info: PakInfo,
-    index: PakIndexV1,
+    index: PakIndex,
     key: Option<Aes256Key>,
+    forced_index_hash: Option<[u8; 20]>,
+    min_compression_savings: f64,
+    /// Last entry returned by [`AssetWriter::finalize`], not in the index yet so that it can
+    /// still be changed
+    pending: Option<(String, PakEntry)>,
 }
 
 impl PakFileBuilder {
     pub fn new(version: PakVersion) -> Self {
-        Self { pos: 0, info: PakInfo::new(version), index: PakIndexV1::default(), key: None }
+        Self {
+            pos: 0,
+            info: PakInfo::new(version),
+            index: Self::new_index(version),
+            key: None,
+            forced_index_hash: None,
+            min_compression_savings: 0.04,
+            pending: None,
+        }
+    }
+
+    /// Write `hash` as the index hash instead of the computed one
+    ///
+    /// This produces a pak file whose index is reported as corrupt when loaded, it is only meant
+    /// to test integrity checks.
+    pub fn force_index_hash(&mut self, hash: [u8; 20]) {
+        self.forced_index_hash = Some(hash);
     }
 
-    pub fn encrypted(&mut self, key: &str) -> io::Result<()> {
+    pub fn encrypted(&mut self, key: &str) -> Result<(), PakError> {
         self.key = Some(aes256_base64_key(key)?);
         Ok(())
     }
 
+    /// Issues entries added so far would hit when written with a `target` version index
+    ///
+    /// This is a dry run of [`PakFileBuilder::finalize`] V2 index conversion, versions without a
+    /// path hash index report no issue. Collisions are computed with the seed `finalize` uses.
+    pub fn validate_for_version(&self, target: PakVersion) -> Vec<RepackIssue> {
+        let mut issues = Vec::new();
+        if target < PakVersion::PathHashIndex {
+            return issues;
+        }
+        let mut paths = Vec::new();
+        let mut entries = self.index.named_entries();
+        entries.extend(self.pending.iter().map(|(name, entry)| (name.clone(), entry)));
+        for (name, entry) in &entries {
+            if !matches!(entry.encode_compact(target), Ok(Some(_))) {
+                issues.push(RepackIssue::NotEncodable(name.clone()));
+            }
+            if !name.contains('/') {
+                issues.push(RepackIssue::NotInDirectory(name.clone()));
+            }
+            paths.push(name.as_str());
+        }
+        let seed = match &self.index {
+            PakIndex::V1(_) => PakIndexV2::default().path_hash_seed,
+            PakIndex::V2(v2) => v2.path_hash_seed,
+        };
+        for (name, other) in PakIndexV2::check_collisions(&paths, seed, target) {
+            issues.push(RepackIssue::Collision(other, name));
+        }
+        issues
+    }
+
     /// Write the index and info blocks
-    pub fn finalize<A: Archive>(mut self, ar: &mut A) -> io::Result<PakFile> {
+    ///
+    /// For versions with a path hash index, the V2 index is written with separate path hash
+    /// index and full directory index sections.
+    pub fn finalize<A: Archive>(mut self, ar: &mut A) -> Result<PakFile, PakError> {
+        self.flush_pending()?;
         let version = self.info.version;
         self.info.index_offset = self.pos;
 
-        let mut sha1_ar = ArchiveLenSha1::new(&mut *ar);
         if self.info.index_is_frozen {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(io::Error::other(
                 "frozen index is not supported and is deprecated since UE4.26",
-            ));
-        } else {
-            self.index.ser_de(&mut sha1_ar, version)?;
+            )
+            .into());
         }
 
-        let (len, hash) = sha1_ar.len_sha1();
-        self.info.index_size = len;
-        self.info.index_hash = hash;
-        self.info.ser_de(ar)?;
-
-        let pak = PakFile {
-            info: self.info,
-            index: if version >= PakVersion::PathHashIndex {
-                let mut v2 = PakIndexV2::default();
-                for (name, entry) in self.index.take_entries() {
-                    v2.add(name, entry, version)?;
-                }
+        let index = match self.index {
+            PakIndex::V2(v2) if !v2.has_path_hash_index && !v2.has_full_directory_index => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the index needs a path hash index or a full directory index",
+                )
+                .into());
+            }
+            PakIndex::V2(mut v2) => {
+                let (len, hash) = v2.write(ar, version, self.info.index_offset)?;
+                self.info.index_size = len;
+                self.info.index_hash = hash;
                 PakIndex::V2(v2)
-            } else {
-                PakIndex::V1(self.index)
-            },
-            key: self.key,
+            }
+            PakIndex::V1(mut v1) => {
+                let mut sha1_ar = ArchiveLenSha1::new(&mut *ar);
+                v1.ser_de(&mut sha1_ar, version)?;
+                let (len, hash) = sha1_ar.len_sha1();
+                self.info.index_size = len;
+                self.info.index_hash = hash;
+                PakIndex::V1(v1)
+            }
         };
-        Ok(pak)
+        if let Some(hash) = self.forced_index_hash {
+            self.info.index_hash = hash;
+        }
+        self.info.ser_de(ar)?;
+
+        Ok(PakFile { info: self.info, index, key: self.key, oodle: None })
     }
 
     /// Write padding bytes to ensure next write is aligned to `alignement`.
-    pub fn pad<A: Archive>(&mut self, ar: A, alignment: u64) -> io::Result<()> {
-        let pos = align_arbitrary(self.pos, alignment);
+    pub fn pad<A: Archive>(&mut self, ar: A, alignment: u64) -> Result<(), PakError> {
+        let pos = align(self.pos, alignment);
         self.seek(ar, pos)
     }
 
     /// Write padding bytes up to `pos`
-    pub fn seek<A: Archive>(&mut self, mut ar: A, pos: u64) -> io::Result<()> {
+    pub fn seek<A: Archive>(&mut self, mut ar: A, pos: u64) -> Result<(), PakError> {
         while self.pos < pos {
             // fill hole with zeros
             const ZEROS: &[u8; 4096] = &[0u8; 4096];
@@ -199,7 +586,8 @@ impl PakFileBuilder {
                     "requested seek position {} is invalid, current position is {}",
                     pos, self.pos
                 ),
-            ))
+            )
+            .into())
         } else {
             Ok(())
         }
@@ -209,6 +597,9 @@ impl PakFileBuilder {
         self.key.as_ref().map(|key| Cipher::new(aes256_ecb_cipher(key)))
     }
 
+    /// Add an entry whose payload is copied as is (i.e. from another pak file)
+    ///
+    /// The written payload must match `entry` size and hash, it is not encrypted again.
     pub fn import<A: Archive>(
         &mut self,
         ar: A,
@@ -216,21 +607,304 @@ impl PakFileBuilder {
         mut entry: PakEntry,
     ) -> AssetWriter<'_, A> {
         entry.offset = self.pos;
-        let ar = ArchiveLenSha1::new(ar);
-        let cipher = self.cipher();
-        AssetWriter { builder: self, ar, name, entry, import: true, cipher }
+        AssetWriter {
+            builder: self,
+            ar,
+            expected_size: None,
+            started: false,
+            stream: None,
+            data: Vec::new(),
+            name,
+            entry,
+            import: true,
+            cipher: None,
+            compression: None,
+        }
     }
 
+    /// Add an entry, the payload is encrypted if the builder has a key
+    ///
+    /// The entry header written before the payload holds the payload size and hash. With an
+    /// archive that can't seek, like [`crate::archive::ArchiveWriter`], the whole payload is kept
+    /// in memory until [`AssetWriter::finalize`] writes the header. Use
+    /// [`crate::archive::ArchiveSeekWriter`] to write the payload as it comes.
     pub fn add<A: Archive>(&mut self, ar: A, name: String) -> AssetWriter<'_, A> {
-        let entry = PakEntry { offset: self.pos, ..PakEntry::default() };
-        let ar = ArchiveLenSha1::new(ar);
         let cipher = self.cipher();
-        AssetWriter { builder: self, ar, name, entry, import: false, cipher }
+        let flags = if cipher.is_some() { FLAG_ENCRYPTED } else { 0 };
+        let entry = PakEntry { offset: self.pos, flags, ..PakEntry::default() };
+        AssetWriter {
+            builder: self,
+            ar,
+            expected_size: None,
+            started: false,
+            stream: None,
+            data: Vec::new(),
+            name,
+            entry,
+            import: false,
+            cipher,
+            compression: None,
+        }
+    }
+
+    /// Add an entry whose payload is encrypted with `key`, whether the builder has a key or not
+    ///
+    /// The payload is padded with zeros to the AES block size, the entry hash covers the padded
+    /// ciphertext while `size` and `uncompressed_size` don't include padding. The index is only encrypted with
+    /// the builder key (see [`PakFileBuilder::encrypted`]).
+    pub fn add_encrypted<A: Archive>(
+        &mut self,
+        ar: A,
+        name: String,
+        key: &str,
+    ) -> Result<AssetWriter<'_, A>, PakError> {
+        let cipher = Cipher::new(aes256_ecb_cipher(&aes256_base64_key(key)?));
+        let mut writer = self.add(ar, name);
+        writer.entry.flags |= FLAG_ENCRYPTED;
+        writer.cipher = Some(cipher);
+        Ok(writer)
     }
 
-    pub fn deleted(&mut self, name: &str) -> io::Result<&mut PakEntry> {
+    /// Add an entry compressed with `method` in blocks of `block_size` uncompressed bytes
+    ///
+    /// Blocks are compressed as soon as they are complete, then encrypted if the builder has a
+    /// key. They are written right away to seekable archives when the payload size is announced
+    /// with [`AssetWriter::set_uncompressed_size`], otherwise the compressed payload is kept in
+    /// memory until [`AssetWriter::finalize`]. `method` is registered in the pak compression methods, see
+    /// [`compress_block`] for the supported methods.
+    pub fn add_compressed<A: Archive>(
+        &mut self,
+        ar: A,
+        name: String,
+        method: &str,
+        block_size: u32,
+    ) -> Result<AssetWriter<'_, A>, PakError> {
+        if !can_compress(method) {
+            return Err(PakError::UnsupportedCompression(method.to_owned()));
+        }
+        if block_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "compression block size must not be 0",
+            )
+            .into());
+        }
+        let method_index = self.register_method(method)?;
+        let mut writer = self.add(ar, name);
+        writer.compression = Some(BlockCompression::Streaming {
+            method: method.to_owned(),
+            method_index,
+            block_size,
+            pending: Vec::new(),
+        });
+        Ok(writer)
+    }
+
+    /// Add an entry compressed with the method giving the smallest payload
+    ///
+    /// Zlib and Gzip are tried with 64 KiB blocks, the entry is left uncompressed if the best
+    /// method doesn't save at least [`PakFileBuilder::set_min_compression_savings`] of the size.
+    /// Only the chosen method is registered in the pak compression methods.
+    pub fn add_auto<A: Archive>(&mut self, ar: A, name: String) -> AssetWriter<'_, A> {
+        let min_savings = self.min_compression_savings;
+        let mut writer = self.add(ar, name);
+        writer.compression = Some(BlockCompression::Auto {
+            block_size: DEFAULT_COMPRESSION_BLOCK_SIZE,
+            min_savings,
+        });
+        writer
+    }
+
+    /// Minimum savings ratio (`0.04` by default, 4%) for [`PakFileBuilder::add_auto`] entries to
+    /// be compressed
+    pub fn set_min_compression_savings(&mut self, savings: f64) {
+        self.min_compression_savings = savings;
+    }
+
+    /// Index of the compression method `method`, registering it if needed
+    ///
+    /// Before `FNameBasedCompressionMethod422`, compression methods are fixed flags.
+    fn register_method(&mut self, method: &str) -> io::Result<u32> {
+        if self.info.version >= PakVersion::FNameBasedCompressionMethod422 {
+            return self.info.compression_method_index(method);
+        }
+        ["Zlib", "Gzip"]
+            .iter()
+            .position(|legacy| legacy.eq_ignore_ascii_case(method))
+            .map(|idx| idx as u32 + 1)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("compression method {:?} is not supported by this version", method),
+                )
+            })
+    }
+
+    /// Add an entry sharing the payload of an already written entry
+    ///
+    /// All fields of `existing_entry`, including its offset, are copied. The payload it references
+    /// must lie within the already written region.
+    pub fn add_alias(&mut self, name: String, existing_entry: &PakEntry) -> Result<(), PakError> {
+        self.flush_pending()?;
+        let version = self.info.version;
+        let end = existing_entry.offset
+            + existing_entry.ser_len_with(version)
+            + existing_entry.disk_size();
+        if end > self.pos {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "alias {:?} references data up to {}, only {} bytes have been written",
+                    name, end, self.pos
+                ),
+            )
+            .into());
+        }
+        self.add_to_index(name, existing_entry.clone())?;
+        Ok(())
+    }
+
+    /// Add a deleted record for `name`, hiding it in the pak files this one patches
+    ///
+    /// Deleted records require `PakVersion::DeleteRecords` or later, V2 indexes store them as a
+    /// deleted location without an entry.
+    pub fn deleted(&mut self, name: &str) -> Result<(), PakError> {
+        if self.info.version < PakVersion::DeleteRecords {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("version {} has no deleted records", self.info.version),
+            )
+            .into());
+        }
+        self.flush_pending()?;
+        let version = self.info.version;
         let entry = PakEntry { offset: self.pos, flags: FLAG_DELETED, ..PakEntry::default() };
-        let entry = self.index.add(name.to_string(), entry);
-        Ok(entry)
+        match &mut self.index {
+            PakIndex::V1(v1) => {
+                v1.add(name.to_owned(), entry);
+            }
+            PakIndex::V2(v2) => {
+                v2.add(name.to_owned(), entry, version)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Index written by [`PakFileBuilder::finalize`], a V2 index with both the path hash index
+    /// and the full directory index for versions that support it
+    fn new_index(version: PakVersion) -> PakIndex {
+        let mut index = PakIndex::new(version);
+        if let PakIndex::V2(v2) = &mut index {
+            v2.has_path_hash_index = true;
+            v2.has_full_directory_index = true;
+        }
+        index
+    }
+
+    /// Add `entry` to the index, V2 indexes report collisions and invalid paths right away
+    fn add_to_index(&mut self, name: String, entry: PakEntry) -> io::Result<()> {
+        let version = self.info.version;
+        match &mut self.index {
+            PakIndex::V1(v1) => {
+                v1.add(name, entry);
+            }
+            PakIndex::V2(v2) => {
+                v2.add(name, entry, version)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Errors [`PakFileBuilder::add_to_index`] would report for `name`
+    fn check_add(&self, name: &str) -> io::Result<()> {
+        match &self.index {
+            PakIndex::V1(_) => Ok(()),
+            PakIndex::V2(v2) => v2.check_add(name, self.info.version),
+        }
+    }
+
+    /// Add the entry last returned by [`AssetWriter::finalize`] to the index
+    fn flush_pending(&mut self) -> io::Result<()> {
+        match self.pending.take() {
+            Some((name, entry)) => self.add_to_index(name, entry),
+            None => Ok(()),
+        }
+    }
+
+    /// V2 index being built, an error for versions without a V2 index
+    fn index_v2_mut(&mut self) -> io::Result<&mut PakIndexV2> {
+        match &mut self.index {
+            PakIndex::V2(v2) if v2.num_entries() == 0 && self.pending.is_none() => Ok(v2),
+            PakIndex::V2(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the index layout can't be changed once entries have been added",
+            )),
+            PakIndex::V1(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("version {} has no V2 index", self.info.version),
+            )),
+        }
+    }
+
+    /// Seed of the path hashes, 0 by default
+    ///
+    /// The engine computes the seed from the pak file name (see [`PakIndexV2::filename_seed`])
+    /// and stores it in the index, the seed must match the one the target engine computes or its
+    /// lookups by path hash will fail. It must be set before adding entries.
+    pub fn set_path_hash_seed(&mut self, seed: u64) -> Result<(), PakError> {
+        self.index_v2_mut()?.path_hash_seed = seed;
+        Ok(())
+    }
+
+    /// Write the path hash index section (enabled by default)
+    ///
+    /// Only versions from `PathHashIndex` have this section, it must be set before adding entries.
+    pub fn set_path_hash_index(&mut self, enabled: bool) -> Result<(), PakError> {
+        self.index_v2_mut()?.has_path_hash_index = enabled;
+        Ok(())
+    }
+
+    /// Write the full directory index section (enabled by default)
+    ///
+    /// Shipping paks usually only have the path hash index, along with a pruned directory index
+    /// listing the directories. Only versions from `PathHashIndex` have this section, it must be
+    /// set before adding entries.
+    pub fn set_full_directory_index(&mut self, enabled: bool) -> Result<(), PakError> {
+        self.index_v2_mut()?.has_full_directory_index = enabled;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+    use crate::archive::ArchiveSeekWriter;
+
+    #[test]
+    fn compressed_blocks_are_not_buffered() {
+        let content: Vec<u8> = (0..20000u32).map(|i| (i % 7) as u8).collect();
+        let mut ar = ArchiveSeekWriter(Cursor::new(Vec::new()));
+        let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+        builder.encrypted("qgpSqP9f7nvTsEtQFJI5ykEOhQM/Q3yaZhtgs2dfNIg=").unwrap();
+        let name = "Game/Content/a.bin".to_owned();
+        let mut writer = builder.add_compressed(&mut ar, name, "Zlib", 1024).unwrap();
+        writer.set_uncompressed_size(content.len() as u64).unwrap();
+        for chunk in content.chunks(700) {
+            writer.write_all(chunk).unwrap();
+            assert_eq!(writer.data.capacity(), 0);
+            match &writer.compression {
+                Some(BlockCompression::Streaming { pending, .. }) => assert!(pending.len() < 1024),
+                _ => unreachable!(),
+            }
+        }
+        let written = writer.size();
+        assert_eq!(
+            writer.get_mut().0.get_ref().len() as u64,
+            writer.stream.as_ref().unwrap().header_len + written
+        );
+        let entry = writer.finalize().unwrap();
+        assert_eq!(entry.compression_blocks.len(), 20);
     }
 }
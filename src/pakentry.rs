@@ -1,14 +1,16 @@
+use std::convert::TryFrom;
 use std::io;
 
-use crate::archive::{Archivable, ArchivableWith, Archive};
+use crate::archive::{align, Archivable, ArchivableWith, Archive, ArchiveReader, ArchiveWriter};
 use crate::constants::*;
-use crate::PakVersion;
+use crate::{PakIndexV2, PakVersion};
 
 pub const FLAG_ENCRYPTED: u8 = 0x01;
 pub const FLAG_DELETED: u8 = 0x02;
 
 /// FPakEntry archivable
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PakEntry {
     /// Offset into pak file where the file is stored.
     pub offset: u64,
@@ -17,6 +19,7 @@ pub struct PakEntry {
     /// Uncompressed file size.
     pub uncompressed_size: u64,
     /// Compressed file SHA1 value.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serdehex"))]
     pub hash: [u8; 20],
     /// Array of compression blocks that describe how to decompress this pak entry.
     pub compression_blocks: Vec<PakCompressedBlock>,
@@ -36,6 +39,95 @@ impl PakEntry {
     pub fn is_deleted(&self) -> bool {
         (self.flags & FLAG_DELETED) == FLAG_DELETED
     }
+
+    /// Size of the payload stored in the pak file, after the entry header
+    ///
+    /// Encrypted payloads are padded to the AES block size, which `size` doesn't include. This is
+    /// the number of bytes extraction must read from the pak file.
+    pub fn disk_size(&self) -> u64 {
+        if self.is_encrypted() {
+            align(self.size, AES_BLOCK_SIZE)
+        } else {
+            self.size
+        }
+    }
+
+    /// Name of the compression method needed to decode this entry, `None` if uncompressed
+    ///
+    /// `methods` are the pak file compression methods (`PakInfo::compression_methods`), an
+    /// index past the end of `methods` resolves to `None` as well.
+    pub fn required_method<'a>(&self, methods: &'a [String]) -> Option<&'a str> {
+        match self.compression_method_index {
+            0 => None,
+            idx => methods.get(idx as usize).map(String::as_str),
+        }
+    }
+
+    /// Peak memory needed to decompress this entry
+    ///
+    /// This is the output buffer (`uncompressed_size`) plus the input buffer of one compressed
+    /// block (`compression_block_size`).
+    pub fn decompress_peak_memory(&self) -> u64 {
+        self.uncompressed_size.saturating_add(u64::from(self.compression_block_size))
+    }
+
+    /// Check that compression blocks are consistent with the compression method
+    ///
+    /// Uncompressed entries can't have compression blocks and compressed entries with data need
+    /// at least one block.
+    pub fn validate_compression_blocks(&self) -> io::Result<()> {
+        if self.compression_method_index == 0 && !self.compression_blocks.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "uncompressed entry has {} compression blocks",
+                    self.compression_blocks.len()
+                ),
+            ));
+        }
+        if self.compression_method_index != 0
+            && self.uncompressed_size > 0
+            && self.compression_blocks.is_empty()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "entry compressed with method {} has no compression blocks for {} bytes",
+                    self.compression_method_index, self.uncompressed_size
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Number of compression blocks of a compact entry, from its first `u32` (the flags word)
+    ///
+    /// This allows gathering block statistics over [`PakIndexV2`] encoded entries without decoding
+    /// them.
+    pub fn peek_block_count(flags_word: u32) -> usize {
+        ((flags_word >> 6) & 0xffff) as usize
+    }
+
+    /// Decode an entry stored in the compact format of [`PakIndexV2`] encoded entries
+    ///
+    /// Returns the decoded entry and the number of bytes consumed from `bytes`.
+    pub fn decode_compact(bytes: &[u8], version: PakVersion) -> io::Result<(PakEntry, usize)> {
+        let mut ar = ArchiveReader(io::Cursor::new(bytes));
+        let entry = PakIndexV2::decode_entry(&mut ar, version)?;
+        Ok((entry, ar.0.position() as usize))
+    }
+
+    /// Encode this entry in the compact format of [`PakIndexV2`] encoded entries
+    ///
+    /// Returns `None` if this entry can't be represented in the compact format.
+    pub fn encode_compact(&self, version: PakVersion) -> io::Result<Option<Vec<u8>>> {
+        let mut ar = ArchiveWriter(io::Cursor::new(Vec::new()));
+        if PakIndexV2::encode_entry(&mut ar, self, version)? {
+            Ok(Some(ar.0.into_inner()))
+        } else {
+            Ok(None)
+        }
+    }
 }
 impl ArchivableWith<PakVersion> for PakEntry {
     fn ser_de_with<A: Archive>(&mut self, ar: &mut A, version: PakVersion) -> io::Result<()> {
@@ -44,24 +136,29 @@ impl ArchivableWith<PakVersion> for PakEntry {
         self.uncompressed_size.ser_de(ar)?;
         match version {
             PakVersion::FNameBasedCompressionMethod422 => {
-                let mut idx = 0u8;
+                let mut idx = u8::try_from(self.compression_method_index).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "compression method index doesn't fit in a byte",
+                    )
+                })?;
                 idx.ser_de(ar)?;
                 self.compression_method_index = From::from(idx);
             }
             ver if ver < PakVersion::FNameBasedCompressionMethod422 => {
-                let mut legacy_compression_method = 0;
+                let mut legacy_compression_method = match self.compression_method_index {
+                    0 => COMPRESS_NONE,
+                    1 => COMPRESS_ZLIB,
+                    2 => COMPRESS_GZIP,
+                    _ => COMPRESS_CUSTOM,
+                };
                 legacy_compression_method.ser_de(ar)?;
                 self.compression_method_index = match legacy_compression_method {
                     x if x == COMPRESS_NONE => 0,
                     x if (x & COMPRESS_ZLIB) > 0 => 1,
                     x if (x & COMPRESS_GZIP) > 0 => 2,
                     x if (x & COMPRESS_CUSTOM) > 0 => 3,
-                    _ => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            "unknown legacy compression type",
-                        ))
-                    }
+                    _ => return Err(io::Error::other("unknown legacy compression type")),
                 };
             }
             _ => {
@@ -80,12 +177,16 @@ impl ArchivableWith<PakVersion> for PakEntry {
             }
             self.flags.ser_de(ar)?;
             self.compression_block_size.ser_de(ar)?;
+            if ar.is_reader() {
+                self.validate_compression_blocks()?;
+            }
         }
         Ok(())
     }
 }
 
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PakCompressedBlock {
     pub compressed_start: u64,
     pub compressed_end: u64,
@@ -98,3 +199,80 @@ impl Archivable for PakCompressedBlock {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn decompress_peak_memory() {
+        let entry = PakEntry {
+            uncompressed_size: 0x30000,
+            compression_block_size: 0x10000,
+            ..PakEntry::default()
+        };
+        assert_eq!(entry.decompress_peak_memory(), 0x40000);
+        let entry = PakEntry { uncompressed_size: u64::MAX, ..entry };
+        assert_eq!(entry.decompress_peak_memory(), u64::MAX);
+    }
+
+    #[test]
+    fn disk_size() {
+        let entry = PakEntry { size: 17, ..PakEntry::default() };
+        assert_eq!(entry.disk_size(), 17);
+        let entry = PakEntry { flags: FLAG_ENCRYPTED, ..entry };
+        assert_eq!(entry.disk_size(), 32);
+        let entry = PakEntry { size: 32, ..entry };
+        assert_eq!(entry.disk_size(), 32);
+    }
+
+    #[test]
+    fn required_method() {
+        let methods = ["".to_owned(), "Zlib".to_owned(), "Oodle".to_owned()];
+        let entry =
+            |compression_method_index| PakEntry { compression_method_index, ..PakEntry::default() };
+        assert_eq!(entry(0).required_method(&methods), None);
+        assert_eq!(entry(2).required_method(&methods), Some("Oodle"));
+        assert_eq!(entry(3).required_method(&methods), None);
+    }
+
+    #[test]
+    fn peek_block_count() {
+        let version = PakVersion::Fnv64BugFix;
+        let mut entry = PakEntry {
+            size: 8,
+            uncompressed_size: 16,
+            compression_method_index: 1,
+            compression_block_size: 16,
+            compression_blocks: vec![PakCompressedBlock::default()],
+            ..PakEntry::default()
+        };
+        let header_size = entry.ser_len_with(version);
+        entry.compression_blocks[0] =
+            PakCompressedBlock { compressed_start: header_size, compressed_end: header_size + 8 };
+        let encoded = entry.encode_compact(version).unwrap().expect("entry to be encodable");
+        let flags_word = u32::from_le_bytes([encoded[0], encoded[1], encoded[2], encoded[3]]);
+        assert_eq!(PakEntry::peek_block_count(flags_word), 1);
+        assert_eq!(PakEntry::peek_block_count(u32::MAX), 0xffff);
+    }
+
+    #[test]
+    fn validate_compression_blocks() {
+        let version = PakVersion::Fnv64BugFix;
+        let entry =
+            PakEntry { uncompressed_size: 10, compression_method_index: 1, ..PakEntry::default() };
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        entry.clone().ser_with(&mut ar, version).unwrap();
+        let err = PakEntry::de_with(&mut ArchiveReader(Cursor::new(ar.0.into_inner())), version)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let block = PakCompressedBlock { compressed_start: 0, compressed_end: 10 };
+        let entry = PakEntry { compression_blocks: vec![block], ..entry };
+        assert!(entry.validate_compression_blocks().is_ok());
+        let entry = PakEntry { compression_method_index: 0, ..entry };
+        assert!(entry.validate_compression_blocks().is_err());
+    }
+}
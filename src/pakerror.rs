@@ -0,0 +1,121 @@
+use std::{error, fmt, io};
+
+/// Error of [`PakFile`](crate::PakFile) and [`PakFileBuilder`](crate::PakFileBuilder) operations
+///
+/// Causes worth handling have their own variant, everything else (I/O errors, malformed data,
+/// invalid arguments) is an [`PakError::Io`] error. Both conversions with `io::Error` are lossless:
+/// a `PakError` converted to an `io::Error` and back keeps its variant.
+#[derive(Debug)]
+pub enum PakError {
+    /// I/O error, or failure without a more specific variant
+    Io(io::Error),
+    /// AES key that isn't a base64 encoded 32 bytes key
+    InvalidKey(String),
+    /// Encrypted data and no decryption key provided
+    MissingKey,
+    /// The decryption key doesn't decrypt the pak file
+    WrongKey,
+    /// None of the `tried` candidate keys decrypts the pak file
+    NoMatchingKey { tried: usize },
+    /// Compression method that isn't supported by the operation
+    UnsupportedCompression(String),
+    /// Computed SHA1 hash of `context` doesn't match the stored one
+    HashMismatch { context: String, expected: [u8; 20], found: [u8; 20] },
+    /// The payload written for an imported entry doesn't match the entry `field`
+    ImportMismatch { field: String, expected: u64, got: u64 },
+    /// The footer matches no known pak version
+    UnsupportedVersion,
+}
+
+impl PakError {
+    /// Kind of the `io::Error` this error converts to
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            PakError::Io(err) => err.kind(),
+            PakError::InvalidKey(_) | PakError::WrongKey | PakError::NoMatchingKey { .. } => {
+                io::ErrorKind::InvalidData
+            }
+            PakError::MissingKey => io::ErrorKind::InvalidInput,
+            PakError::UnsupportedCompression(_) => io::ErrorKind::Unsupported,
+            PakError::HashMismatch { .. }
+            | PakError::ImportMismatch { .. }
+            | PakError::UnsupportedVersion => io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl fmt::Display for PakError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PakError::Io(err) => err.fmt(f),
+            PakError::InvalidKey(reason) => write!(f, "invalid key, {}", reason),
+            PakError::MissingKey => write!(f, "data is encrypted and no decryption key provided"),
+            PakError::WrongKey => {
+                write!(
+                    f,
+                    "the decrypted index doesn't match its hash, likely a wrong encryption key"
+                )
+            }
+            PakError::NoMatchingKey { tried } => {
+                write!(f, "none of the {} keys tried decrypts the pak file", tried)
+            }
+            PakError::UnsupportedCompression(method) => {
+                write!(f, "compression method {:?} is not supported", method)
+            }
+            PakError::HashMismatch { context, expected, found } => {
+                write!(f, "Corrupt {} (hash {:02X?} != {:02X?})", context, found, expected)
+            }
+            PakError::ImportMismatch { field, expected, got } => {
+                write!(f, "imported entry {} mismatch: expected {}, got {}", field, expected, got)
+            }
+            PakError::UnsupportedVersion => write!(f, "no compatible version found"),
+        }
+    }
+}
+
+impl error::Error for PakError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PakError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PakError {
+    fn from(err: io::Error) -> Self {
+        if err.get_ref().is_some_and(|inner| inner.is::<PakError>()) {
+            let inner = err.into_inner().expect("io::Error has an inner error");
+            return *inner.downcast::<PakError>().expect("inner error is a PakError");
+        }
+        PakError::Io(err)
+    }
+}
+
+impl From<PakError> for io::Error {
+    fn from(err: PakError) -> Self {
+        match err {
+            PakError::Io(err) => err,
+            err => io::Error::new(err.kind(), err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_conversions() {
+        let err = io::Error::from(PakError::UnsupportedCompression("Oodle".to_owned()));
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        let err = PakError::from(err);
+        assert!(matches!(&err, PakError::UnsupportedCompression(method) if method == "Oodle"));
+
+        let err = PakError::from(io::Error::new(io::ErrorKind::NotFound, "no entry"));
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = io::Error::from(err);
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "no entry");
+    }
+}
@@ -1,47 +1,523 @@
-use std::{convert::TryFrom, io};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ops::{ControlFlow, Range};
+use std::path::{Component, Path, PathBuf};
+use std::{fmt, fs, io, slice};
 
 use aes::Aes256;
 use block_modes::block_padding::NoPadding;
 use block_modes::{BlockMode, Ecb};
-use log::{debug, trace};
-use sha1::digest::generic_array::typenum::Unsigned;
+use log::{debug, trace, warn};
 
-use crate::archive::{Archivable, Archive, ArchiveLenSha1, ArchiveReader};
-use crate::pakindex::PakIndex;
+use crate::archive::{
+    align, Archivable, ArchivableWith, Archive, ArchiveLen, ArchiveLenSha1, ArchiveReader,
+    ArchiveWriter, ByteOrder, Endian, ReadAt,
+};
+use crate::compression::{decompress_block, BlockDecompressor};
+use crate::constants::{AES_BLOCK_SIZE, PAK_FILE_MAGIC};
+use crate::pakindex::{relative_path, PakIndex};
 use crate::pakindexv1::PakIndexV1;
 use crate::pakindexv2::PakIndexV2;
 use crate::{aes256_base64_key, aes256_ecb_cipher};
-use crate::{Aes256BlockSize, Aes256Key, PakInfo, PakVersion};
+use crate::{
+    Aes256Block, Aes256Cipher, Aes256Key, KeyRegistry, PakEntry, PakError, PakInfo, PakVersion,
+};
+
+/// Copy `len` bytes from `src` to `dst` using a fixed size buffer
+fn copy_len(src: &mut impl Archive, dst: &mut impl Archive, mut len: u64) -> io::Result<()> {
+    let mut buffer = [0u8; 4096];
+    while len > 0 {
+        let size = len.min(buffer.len() as u64) as usize;
+        src.read_exact(&mut buffer[..size])?;
+        dst.write_all(&buffer[..size])?;
+        len -= size as u64;
+    }
+    Ok(())
+}
+
+/// Outcome of [`PakFile::extract_all_with`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractSummary {
+    /// Number of entries written to disk.
+    pub extracted: usize,
+    /// Number of entries that failed and were reported to the error callback.
+    pub failed: usize,
+}
+
+/// Layout of a pak file split by [`PakFile::split`]
+///
+/// [`PakFile::recombine`] gives back the original pak file from the two streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PakSplit {
+    /// Size of the data stream.
+    pub data_size: u64,
+    /// Size of the index stream (index, secondary V2 indexes and footer).
+    pub index_size: u64,
+    /// `index_offset` of the original pak file, the index stream is moved back there when
+    /// recombined.
+    pub index_offset: u64,
+}
+
+/// Read `len` bytes at `offset` with `read_at`, decrypting them with `key` if any
+///
+/// Encrypted data is padded to the AES block size, whole blocks are read then truncated.
+fn read_decrypted(
+    read_at: &mut impl FnMut(u64, &mut [u8]) -> io::Result<()>,
+    key: Option<&Aes256Key>,
+    offset: u64,
+    len: u64,
+) -> io::Result<Vec<u8>> {
+    let disk_len = if key.is_some() { align(len, AES_BLOCK_SIZE) } else { len };
+    let mut buffer = vec![0u8; usize::try_from(disk_len).map_err(io::Error::other)?];
+    read_at(offset, &mut buffer)?;
+    if let Some(key) = key {
+        aes256_ecb_cipher(key).decrypt(&mut buffer).map_err(io::Error::other)?;
+    }
+    buffer.truncate(len as usize);
+    Ok(buffer)
+}
+
+/// Callback of [`PakFile::stream_entries`] receiving each live entry
+type EntryVisitor<'a> = dyn FnMut(&str, &PakEntry) -> io::Result<()> + 'a;
+
+/// Which parts of a pak file are encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionStatus {
+    /// Nothing is encrypted
+    None,
+    /// Only the index is encrypted
+    IndexOnly,
+    /// Only some entries are encrypted
+    EntriesOnly,
+    /// Both the index and some entries are encrypted
+    Both,
+}
+
+/// Archive reader decrypting `remaining` bytes of `ar` one AES block at a time
+///
+/// The plaintext is never fully materialized, which keeps peak memory low for large encrypted
+/// indexes. ECB mode allows each block to be decrypted independently.
+struct DecryptingReader<A> {
+    ar: A,
+    cipher: Aes256Cipher,
+    block: Aes256Block,
+    /// Number of bytes of `block` already consumed
+    pos: usize,
+    /// Number of encrypted bytes left to read from `ar`
+    remaining: u64,
+}
+
+impl<A: Archive> DecryptingReader<A> {
+    fn new(ar: A, key: &Aes256Key, size: u64) -> Self {
+        let block = Aes256Block::default();
+        Self { ar, cipher: aes256_ecb_cipher(key), pos: block.len(), block, remaining: size }
+    }
+
+    /// Start decrypting `size` bytes from the current position of the inner archive
+    fn reset(&mut self, size: u64) {
+        self.pos = self.block.len();
+        self.remaining = size;
+    }
+
+    fn next_block(&mut self) -> io::Result<()> {
+        if self.remaining < AES_BLOCK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} encrypted bytes left, expecting a full AES block", self.remaining),
+            ));
+        }
+        self.ar.read_exact(&mut self.block)?;
+        self.cipher.decrypt_blocks(slice::from_mut(&mut self.block));
+        self.remaining -= AES_BLOCK_SIZE;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<A: Archive> Archive for DecryptingReader<A> {
+    fn is_reader(&self) -> bool {
+        true
+    }
+
+    fn write_all(&mut self, _buf: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "decrypting reader is read only"))
+    }
+
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            if self.pos == self.block.len() {
+                self.next_block()?;
+            }
+            let n = (self.block.len() - self.pos).min(buf.len());
+            buf[..n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+            self.pos += n;
+            buf = &mut buf[n..];
+        }
+        Ok(())
+    }
+
+    fn strict_strings(&self) -> bool {
+        self.ar.strict_strings()
+    }
+
+    fn max_string_len(&self) -> usize {
+        self.ar.max_string_len()
+    }
+
+    fn endian(&self) -> Endian {
+        self.ar.endian()
+    }
+
+    /// Seek the encrypted archive, `pos` must be the start of an AES block
+    ///
+    /// The partially read block is dropped, the number of bytes left to decrypt is kept.
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let pos = self.ar.seek(pos)?;
+        self.pos = self.block.len();
+        Ok(pos)
+    }
+}
+
+/// Size of the chunks raw payloads are read by, a multiple of the AES block size
+const PAYLOAD_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Block by block decoding of an entry content, shared by [`EntryReader`] and
+/// [`OwnedEntryReader`]
+struct EntryDecoder {
+    entry: PakEntry,
+    key: Option<Aes256Key>,
+    method: String,
+    /// Offset compression blocks are relative to, or offset of the uncompressed payload
+    payload_offset: u64,
+    /// Index of the next compression block (or uncompressed chunk) to read
+    block_index: usize,
+    /// Decoded content of the current block
+    buffer: Vec<u8>,
+    /// Position in `buffer`
+    buffer_pos: usize,
+    /// Number of content bytes decoded so far
+    decoded: u64,
+}
+
+impl EntryDecoder {
+    /// Decode the next block in `buffer`, returns `false` once all blocks are decoded
+    fn next_block<A: Archive>(
+        &mut self,
+        ar: &mut A,
+        oodle: Option<&BlockDecompressor>,
+    ) -> io::Result<bool> {
+        let mut read_at = |offset: u64, buf: &mut [u8]| {
+            ar.seek(io::SeekFrom::Start(offset))?;
+            ar.read_exact(buf)
+        };
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        if self.entry.compression_method_index == 0 {
+            let start = self.block_index as u64 * PAYLOAD_CHUNK_SIZE;
+            if start >= self.entry.size {
+                return Ok(false);
+            }
+            let len = (self.entry.size - start).min(PAYLOAD_CHUNK_SIZE);
+            let offset = self.payload_offset + start;
+            self.buffer = read_decrypted(&mut read_at, self.key.as_ref(), offset, len)?;
+        } else {
+            let block = match self.entry.compression_blocks.get(self.block_index) {
+                Some(block) => block,
+                None if self.decoded == self.entry.uncompressed_size => return Ok(false),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "entry at {} decompressed to {} bytes, expecting {}",
+                            self.entry.offset, self.decoded, self.entry.uncompressed_size
+                        ),
+                    ))
+                }
+            };
+            let offset = self.payload_offset + block.compressed_start;
+            let len = block.compressed_end - block.compressed_start;
+            let compressed = read_decrypted(&mut read_at, self.key.as_ref(), offset, len)?;
+            decompress_entry_block(
+                oodle,
+                &self.entry,
+                &self.method,
+                &compressed,
+                self.decoded,
+                &mut self.buffer,
+            )?;
+        }
+        self.block_index += 1;
+        self.decoded += self.buffer.len() as u64;
+        Ok(true)
+    }
+
+    fn read<A: Archive>(
+        &mut self,
+        ar: &mut A,
+        oodle: Option<&BlockDecompressor>,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        while self.buffer_pos == self.buffer.len() {
+            if !self.next_block(ar, oodle)? {
+                return Ok(0);
+            }
+        }
+        let n = (self.buffer.len() - self.buffer_pos).min(buf.len());
+        buf[..n].copy_from_slice(&self.buffer[self.buffer_pos..self.buffer_pos + n]);
+        self.buffer_pos += n;
+        Ok(n)
+    }
+}
+
+/// Streaming reader over an entry content, see [`PakFile::entry_reader`]
+///
+/// Compressed entries are decoded one compression block at a time, uncompressed payloads are
+/// read in 64 KiB chunks.
+pub struct EntryReader<'a, A> {
+    pak: &'a PakFile,
+    ar: A,
+    decoder: EntryDecoder,
+}
+
+impl<A> EntryReader<'_, A> {
+    pub fn entry(&self) -> &PakEntry {
+        &self.decoder.entry
+    }
+
+    pub fn into_inner(self) -> A {
+        self.ar
+    }
+}
+
+impl<A: Archive> io::Read for EntryReader<'_, A> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(&mut self.ar, self.pak.oodle.as_ref(), buf)
+    }
+}
+
+/// Streaming reader over an entry content owning the archive, see [`PakFile::take_entry_reader`]
+///
+/// Entries are decoded like [`EntryReader`] does, only the entry, its key and the Oodle
+/// decompressor are kept from the pak file.
+pub struct OwnedEntryReader<A> {
+    ar: A,
+    oodle: Option<BlockDecompressor>,
+    decoder: EntryDecoder,
+}
+
+impl<A> OwnedEntryReader<A> {
+    pub fn entry(&self) -> &PakEntry {
+        &self.decoder.entry
+    }
+
+    pub fn into_inner(self) -> A {
+        self.ar
+    }
+}
+
+impl<A: Archive> io::Read for OwnedEntryReader<A> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(&mut self.ar, self.oodle.as_ref(), buf)
+    }
+}
+
+/// Decompress a compression block of `entry` starting at `offset` in the entry content and
+/// append it to `out`
+///
+/// Oodle blocks are decompressed with `oodle`, see [`PakFile::set_oodle_decompressor`].
+fn decompress_entry_block(
+    oodle: Option<&BlockDecompressor>,
+    entry: &PakEntry,
+    method: &str,
+    input: &[u8],
+    offset: u64,
+    out: &mut Vec<u8>,
+) -> io::Result<()> {
+    if !method.eq_ignore_ascii_case("Oodle") {
+        return decompress_block(method, input, out);
+    }
+    // Oodle is only supported with a registered decompressor
+    let oodle = oodle.ok_or_else(|| PakError::UnsupportedCompression(method.to_owned()))?;
+    // only the last block is shorter than the compression block size
+    let remaining = entry.uncompressed_size.saturating_sub(offset);
+    let block_len = remaining.min(u64::from(entry.compression_block_size)) as usize;
+    let start = out.len();
+    out.resize(start + block_len, 0);
+    oodle(input, &mut out[start..])
+}
 
-#[derive(Debug)]
 pub struct PakFile {
     pub(crate) key: Option<Aes256Key>,
     pub(crate) info: PakInfo,
     pub(crate) index: PakIndex,
+    pub(crate) oodle: Option<BlockDecompressor>,
+}
+
+impl fmt::Debug for PakFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PakFile")
+            .field("key", &self.key)
+            .field("info", &self.info)
+            .field("index", &self.index)
+            .field("oodle", &self.oodle.is_some())
+            .finish()
+    }
 }
 
 impl PakFile {
-    pub fn load_any<A: Archive + io::Seek>(ar: &mut A) -> io::Result<Self> {
+    /// Load a pak file, trying every known version from the latest one
+    ///
+    /// Version 8 footers exist in two layouts: 4.23+ (5 compression methods) is tried before
+    /// 4.22 (4 compression methods), the loaded `PakInfo::version` tells which one matched.
+    pub fn load_any<A: Archive>(ar: &mut A) -> Result<Self, PakError> {
         Self::load_versions(ar, None, PakVersion::list().iter().rev().copied())
     }
 
-    pub fn load_any_with_key<A: Archive + io::Seek>(ar: &mut A, key: &str) -> io::Result<Self> {
+    pub fn load_any_with_key<A: Archive>(ar: &mut A, key: &str) -> Result<Self, PakError> {
         Self::load_versions(ar, Some(key), PakVersion::list().iter().rev().copied())
     }
 
-    pub fn load_version<A: Archive + io::Seek>(
+    /// Load a pak file with the first of `keys` that decrypts its index
+    ///
+    /// A key decrypts the index if the SHA1 of the decrypted index matches the footer one. Keys
+    /// can't be told apart if the index isn't encrypted, the first key is used then.
+    /// [`PakError::NoMatchingKey`] is returned if no key matches.
+    pub fn load_any_with_keys<A: Archive>(ar: &mut A, keys: &[&str]) -> Result<Self, PakError> {
+        let info = Self::de_pakinfo_versions(
+            ar,
+            PakVersion::list().iter().rev().copied(),
+            PAK_FILE_MAGIC,
+        )?;
+        let keys = keys.iter().map(|key| aes256_base64_key(key)).collect::<io::Result<Vec<_>>>()?;
+        if !info.encrypted_index || keys.is_empty() {
+            let key = keys.into_iter().next();
+            let index = Self::load_index(&info, ar, &key)?;
+            return Ok(Self { info, index, key, oodle: None });
+        }
+        for (i, key) in keys.iter().enumerate() {
+            let key = Some(*key);
+            match Self::load_index(&info, ar, &key).map_err(PakError::from) {
+                Ok(index) => return Ok(Self { info, index, key, oodle: None }),
+                // a wrong key can also decrypt to an index that fails to decode
+                Err(err)
+                    if err.kind() == io::ErrorKind::InvalidData
+                        || err.kind() == io::ErrorKind::UnexpectedEof =>
+                {
+                    debug!("key {} doesn't decrypt the index: {}", i, err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(PakError::NoMatchingKey { tried: keys.len() })
+    }
+
+    /// Load a pak file with the key `registry` has for its `PakInfo::encryption_key_guid`
+    ///
+    /// Pak files with a zero guid use the default key of the registry. The pak file has no key
+    /// if the registry doesn't know its guid, which fails only if the index is encrypted.
+    pub fn load_any_with_registry<A: Archive>(
+        ar: &mut A,
+        registry: &KeyRegistry,
+    ) -> Result<Self, PakError> {
+        let info = Self::de_pakinfo_versions(
+            ar,
+            PakVersion::list().iter().rev().copied(),
+            PAK_FILE_MAGIC,
+        )?;
+        let key = registry.aes_key(info.encryption_key_guid).cloned();
+        if key.is_none() {
+            debug!("no key registered for guid {}", info.encryption_key_guid);
+        }
+        let index = Self::load_index(&info, ar, &key)?;
+        Ok(Self { info, index, key, oodle: None })
+    }
+
+    /// Load a pak file whose footer starts at `footer_offset` instead of ending the archive
+    ///
+    /// This allows reading a pak file followed by other data (i.e. embedded in a container).
+    /// Offsets stored in the footer and the index are still absolute archive offsets.
+    pub fn load_at_offset<A: Archive>(
         ar: &mut A,
-        version: PakVersion,
-    ) -> io::Result<Self> {
+        footer_offset: u64,
+        key: Option<&str>,
+    ) -> Result<Self, PakError> {
+        let info = Self::de_pakinfo_at(
+            ar,
+            PakVersion::list().iter().rev().copied(),
+            PAK_FILE_MAGIC,
+            |ar_len, info_len| Some(footer_offset).filter(|pos| pos + info_len <= ar_len),
+        )?;
+        let key = key.map(aes256_base64_key).transpose()?;
+        let index = Self::load_index(&info, ar, &key)?;
+        Ok(Self { info, index, key, oodle: None })
+    }
+
+    /// Load only the footer, trying every known version
+    ///
+    /// The index can then be loaded with [`PakIndex::load_standalone`].
+    pub fn load_footer<A: Archive>(ar: &mut A) -> Result<PakInfo, PakError> {
+        Ok(Self::de_pakinfo_versions(ar, PakVersion::list().iter().rev().copied(), PAK_FILE_MAGIC)?)
+    }
+
+    pub fn load_version<A: Archive>(ar: &mut A, version: PakVersion) -> Result<Self, PakError> {
         Self::load_versions(ar, None, [version].iter().copied())
     }
 
-    pub fn load_versions<A: Archive + io::Seek>(
+    /// Load a pak file whose footer uses `magic` instead of `PAK_FILE_MAGIC`
+    ///
+    /// Some modded games change the magic value to prevent tools from reading their pak files.
+    pub fn load_any_with_magic<A: Archive>(
+        ar: &mut A,
+        key: Option<&str>,
+        magic: u32,
+    ) -> Result<Self, PakError> {
+        Self::load_versions_with_magic(ar, key, PakVersion::list().iter().rev().copied(), magic)
+    }
+
+    /// Load a pak file, falling back to the latest known footer layout for unknown versions
+    ///
+    /// If no known version matches and the footer version is newer than the latest known one, the
+    /// pak file is read as if it was of the latest known version. This is a best effort mode:
+    /// the returned version is `Some` with the unrecognized raw version in this case, and the
+    /// loaded `PakFile` reports the latest known version.
+    pub fn load_any_forgiving<A: Archive>(
+        ar: &mut A,
+        key: Option<&str>,
+    ) -> Result<(Self, Option<i32>), PakError> {
+        let versions = PakVersion::list().iter().rev().copied();
+        match Self::load_versions(ar, key, versions) {
+            Err(PakError::UnsupportedVersion) => {
+                let (info, raw_version) = match Self::de_pakinfo_newer(ar)? {
+                    Some(found) => found,
+                    None => return Err(PakError::UnsupportedVersion),
+                };
+                warn!(
+                    "unrecognized pak version {}, reading it as version {}",
+                    raw_version, info.version
+                );
+                let key = key.map(aes256_base64_key).transpose()?;
+                let index = Self::load_index(&info, ar, &key)?;
+                Ok((Self { info, index, key, oodle: None }, Some(raw_version)))
+            }
+            res => res.map(|pak| (pak, None)),
+        }
+    }
+
+    pub fn load_versions<A: Archive>(
         ar: &mut A,
         hash: Option<&str>,
         versions: impl Iterator<Item = PakVersion>,
-    ) -> io::Result<Self> {
-        let info = Self::de_pakinfo_versions(ar, versions)?;
+    ) -> Result<Self, PakError> {
+        Self::load_versions_with_magic(ar, hash, versions, PAK_FILE_MAGIC)
+    }
+
+    fn load_versions_with_magic<A: Archive>(
+        ar: &mut A,
+        hash: Option<&str>,
+        versions: impl Iterator<Item = PakVersion>,
+        magic: u32,
+    ) -> Result<Self, PakError> {
+        let info = Self::de_pakinfo_versions(ar, versions, magic)?;
         let key = match hash {
             Some(hash) => {
                 let tmp = aes256_base64_key(hash)?;
@@ -50,7 +526,54 @@ impl PakFile {
             None => None,
         };
         let index = Self::load_index(&info, ar, &key)?;
-        Ok(Self { info, index, key })
+        Ok(Self { info, index, key, oodle: None })
+    }
+
+    /// Create a pak file from an already parsed footer and index
+    ///
+    /// The data section starts the pak file, so entries can be read from a data only source (see
+    /// [`PakFile::split`]). The pak file has no key, encrypted entries need an explicit one.
+    pub fn from_parts(info: PakInfo, index: PakIndex) -> Result<Self, PakError> {
+        let expects_v2 = info.version >= PakVersion::PathHashIndex;
+        if expects_v2 != matches!(index, PakIndex::V2(_)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "version {} expects a {} index",
+                    info.version,
+                    if expects_v2 { "V2" } else { "V1" }
+                ),
+            )
+            .into());
+        }
+        Ok(Self { info, index, key: None, oodle: None })
+    }
+
+    /// Register the decompressor of "Oodle" compression blocks
+    ///
+    /// Oodle can't be bundled with this crate, `decompressor` gets a compressed block and an
+    /// output buffer sized to the decompressed block size. Without a decompressor, reading Oodle
+    /// compressed entries fails with an `Unsupported` error.
+    pub fn set_oodle_decompressor(&mut self, decompressor: BlockDecompressor) {
+        self.oodle = Some(decompressor);
+    }
+
+    /// Split this pak file into its footer and index
+    pub fn into_parts(self) -> (PakInfo, PakIndex) {
+        (self.info, self.index)
+    }
+
+    /// Footer and index as pretty printed JSON, i.e. to diff the metadata of two builds
+    #[cfg(feature = "serde")]
+    pub fn metadata_json(&self) -> String {
+        #[derive(serde::Serialize)]
+        struct Metadata<'a> {
+            info: &'a PakInfo,
+            index: &'a PakIndex,
+        }
+
+        let metadata = Metadata { info: &self.info, index: &self.index };
+        serde_json::to_string_pretty(&metadata).expect("pak metadata serializes to JSON")
     }
 
     pub fn info(&self) -> &PakInfo {
@@ -61,31 +584,727 @@ impl PakFile {
         &self.index
     }
 
+    /// Offset of `entry` payload in the pak file
+    ///
+    /// Each entry payload is preceded by a copy of its `PakEntry` header, whose size depends on
+    /// the pak version and the entry compression blocks.
+    pub fn payload_offset(&self, entry: &PakEntry) -> Result<u64, PakError> {
+        let header_size = entry.ser_len_with(self.info.version);
+        entry.offset.checked_add(header_size).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("entry offset {} overflows with its header", entry.offset),
+            )
+            .into()
+        })
+    }
+
+    /// Check that entries end exactly where the index starts
+    ///
+    /// A gap between the last entry and the index is wasted space and is only logged as a
+    /// warning, entries overlapping the index are reported as an `InvalidData` error.
+    pub fn validate_layout(&self) -> Result<(), PakError> {
+        let last_entry_end = self.index.last_entry_end(self.info.version);
+        let index_offset = self.info.index_offset;
+        if last_entry_end > index_offset {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "entries end at {}, past the index offset {}",
+                    last_entry_end, index_offset
+                ),
+            )
+            .into());
+        }
+        if last_entry_end < index_offset {
+            warn!(
+                "{} bytes gap between the last entry end {} and the index offset {}",
+                index_offset - last_entry_end,
+                last_entry_end,
+                index_offset
+            );
+        }
+        Ok(())
+    }
+
+    /// Which parts of this pak file require a key to be read
+    ///
+    /// Unlike `info().encrypted_index`, this also scans entries as they can be encrypted even if
+    /// the index isn't.
+    pub fn encryption_status(&self) -> EncryptionStatus {
+        let encrypted_entries = self.index.entries().any(|entry| entry.is_encrypted());
+        match (self.info.encrypted_index, encrypted_entries) {
+            (false, false) => EncryptionStatus::None,
+            (true, false) => EncryptionStatus::IndexOnly,
+            (false, true) => EncryptionStatus::EntriesOnly,
+            (true, true) => EncryptionStatus::Both,
+        }
+    }
+
+    /// Split the pak file `src` into a data-only stream and an index-only stream
+    ///
+    /// `data_out` receives every entry (header and payload), `index_out` receives the index, the
+    /// V2 secondary indexes and the footer. The index stream is a pak file of its own: its footer
+    /// and secondary index offsets are made relative to it, which requires the key of an
+    /// encrypted V2 index. Entries keep their offsets, see [`PakFile::from_parts`] to read them
+    /// from the data stream.
+    pub fn split<A, D, I>(
+        &self,
+        src: &mut A,
+        data_out: &mut D,
+        index_out: &mut I,
+    ) -> Result<PakSplit, PakError>
+    where
+        A: Archive,
+        D: Archive,
+        I: Archive,
+    {
+        let src_len = src.seek(io::SeekFrom::End(0))?;
+        let data_size = self.info.index_offset;
+        if data_size > src_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("index offset {} is past the end of the pak file ({})", data_size, src_len),
+            )
+            .into());
+        }
+        let index_size = src_len - data_size;
+
+        src.seek(io::SeekFrom::Start(0))?;
+        copy_len(src, data_out, data_size)?;
+        let mut index = vec![0u8; usize::try_from(index_size).map_err(io::Error::other)?];
+        src.read_exact(&mut index)?;
+        index_out.write_all(&self.rebase_index_stream(index, data_size, 0)?)?;
+        Ok(PakSplit { data_size, index_size, index_offset: data_size })
+    }
+
+    /// Write the pak file `split` by [`PakFile::split`] into `data` and `index` back to `out`
+    ///
+    /// `self` is either the original pak file or the one loaded from the index stream. The index
+    /// stream offsets are moved back to `split.index_offset`, which requires the key of an
+    /// encrypted V2 index.
+    pub fn recombine<D, I, O>(
+        &self,
+        split: &PakSplit,
+        data: &mut D,
+        index: &mut I,
+        out: &mut O,
+    ) -> Result<(), PakError>
+    where
+        D: Archive,
+        I: Archive,
+        O: Archive,
+    {
+        copy_len(data, out, split.data_size)?;
+        let mut bytes = vec![0u8; usize::try_from(split.index_size).map_err(io::Error::other)?];
+        index.read_exact(&mut bytes)?;
+        out.write_all(&self.rebase_index_stream(bytes, 0, split.index_offset)?)?;
+        Ok(())
+    }
+
+    /// Index stream `bytes` (index sections and footer) written at `to` instead of `from`
+    ///
+    /// The footer is written again with the new index offset. For V2 indexes, the secondary
+    /// section offsets of the primary index are moved too and its hash updated.
+    fn rebase_index_stream(&self, mut bytes: Vec<u8>, from: u64, to: u64) -> io::Result<Vec<u8>> {
+        let too_short =
+            || io::Error::new(io::ErrorKind::InvalidData, "the index stream is too short");
+        let mut info = self.info.clone();
+        let index_len =
+            (bytes.len() as u64).checked_sub(info.ser_de_len()).ok_or_else(too_short)?;
+        bytes.truncate(index_len as usize);
+
+        if info.version >= PakVersion::PathHashIndex {
+            let key = match (info.encrypted_index, &self.key) {
+                (true, Some(key)) => Some(key),
+                (true, None) => return Err(PakError::MissingKey.into()),
+                (false, _) => None,
+            };
+            let len = if key.is_some() {
+                align(info.index_size, AES_BLOCK_SIZE)
+            } else {
+                info.index_size
+            };
+            let primary = bytes.get_mut(..len as usize).ok_or_else(too_short)?;
+            if let Some(key) = key {
+                aes256_ecb_cipher(key).decrypt(primary).map_err(io::Error::other)?;
+            }
+
+            let plaintext = &mut primary[..info.index_size as usize];
+            let mut index = PakIndexV2::default();
+            let mut ar = ByteOrder(ArchiveReader(io::Cursor::new(&*plaintext)), info.endian);
+            index.ser_de_primary(&mut ar, info.version)?;
+            let delta = i64::try_from(to).map_err(io::Error::other)?
+                - i64::try_from(from).map_err(io::Error::other)?;
+            index.move_secondary_sections(delta);
+            let writer = ByteOrder(ArchiveWriter(io::Cursor::new(&mut *plaintext)), info.endian);
+            let mut sha1_ar = ArchiveLenSha1::new(writer);
+            index.ser_de_primary(&mut sha1_ar, info.version)?;
+            info.index_hash = sha1_ar.len_sha1().1;
+
+            if let Some(key) = key {
+                aes256_ecb_cipher(key).encrypt(primary, len as usize).map_err(io::Error::other)?;
+            }
+        }
+
+        info.index_offset = to;
+        info.ser_de(&mut ByteOrder(ArchiveWriter(&mut bytes), info.endian))?;
+        Ok(bytes)
+    }
+
+    /// Read `entry` and return its uncompressed content
+    ///
+    /// The entry header stored before the payload is read again and must match `entry`.
+    /// Compression blocks are decompressed with [`decompress_block`] using the
+    /// compression method name, or with the decompressor registered with
+    /// [`PakFile::set_oodle_decompressor`] for Oodle.
+    /// Encrypted entries are decrypted with the key the pak file was loaded with.
+    pub fn read_entry<A: Archive>(
+        &self,
+        ar: &mut A,
+        entry: &PakEntry,
+    ) -> Result<Vec<u8>, PakError> {
+        Ok(self.read_entry_impl(ar, entry, None)?)
+    }
+
+    /// Same as [`PakFile::read_entry`], checking the payload with [`PakFile::verify_entry`] first
+    pub fn read_entry_verified<A: Archive>(
+        &self,
+        ar: &mut A,
+        entry: &PakEntry,
+    ) -> Result<Vec<u8>, PakError> {
+        self.verify_entry(ar, entry)?;
+        self.read_entry(ar, entry)
+    }
+
+    /// Check that the SHA1 of `entry` payload matches `entry.hash`
+    ///
+    /// The payload is hashed as stored (compressed, encrypted and padded), without being decoded.
+    /// Compact encoded V2 entries have no hash, the hash of the entry header stored before the
+    /// payload is used instead. A mismatch is reported as a [`PakError::HashMismatch`].
+    pub fn verify_entry<A: Archive>(&self, ar: &mut A, entry: &PakEntry) -> Result<(), PakError> {
+        let disk_size = entry.disk_size();
+        ar.seek(io::SeekFrom::Start(entry.offset))?;
+        let header =
+            PakEntry::de_with(&mut ByteOrder(&mut *ar, self.info.endian), self.info.version)?;
+        let expected = if entry.hash == [0; 20] { header.hash } else { entry.hash };
+        ar.seek(io::SeekFrom::Start(self.payload_offset(entry)?))?;
+        let mut sha1_ar = ArchiveLenSha1::new(ar);
+        let mut buffer = vec![0u8; disk_size.min(PAYLOAD_CHUNK_SIZE) as usize];
+        let mut remaining = disk_size;
+        while remaining > 0 {
+            let n = remaining.min(buffer.len() as u64) as usize;
+            sha1_ar.read_exact(&mut buffer[..n])?;
+            remaining -= n as u64;
+        }
+        let (_, hash) = sha1_ar.len_sha1();
+        if hash != expected {
+            return Err(PakError::HashMismatch {
+                context: format!("entry at {}", entry.offset),
+                expected,
+                found: hash,
+            });
+        }
+        Ok(())
+    }
+
+    /// Same as [`PakFile::read_entry`], decrypting the entry with the base64 encoded `key`
+    ///
+    /// Encrypted payloads are padded to the AES block size, the padding is removed after
+    /// decryption. Compression blocks are each padded and decrypted before being decompressed.
+    pub fn read_entry_with_key<A: Archive>(
+        &self,
+        ar: &mut A,
+        entry: &PakEntry,
+        key: &str,
+    ) -> Result<Vec<u8>, PakError> {
+        Ok(self.read_entry_impl(ar, entry, Some(key))?)
+    }
+
+    fn read_entry_impl<A: Archive>(
+        &self,
+        ar: &mut A,
+        entry: &PakEntry,
+        key: Option<&str>,
+    ) -> io::Result<Vec<u8>> {
+        let version = self.info.version;
+        ar.seek(io::SeekFrom::Start(entry.offset))?;
+        let header = PakEntry::de_with(&mut ByteOrder(&mut *ar, self.info.endian), version)?;
+        if (header.size, header.uncompressed_size, header.compression_method_index)
+            != (entry.size, entry.uncompressed_size, entry.compression_method_index)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("entry header at {} doesn't match the index entry", entry.offset),
+            ));
+        }
+        let key = self.entry_key(entry, key)?;
+        let mut read = |offset: u64, len: u64| {
+            let mut read_at = |offset: u64, buf: &mut [u8]| {
+                ar.seek(io::SeekFrom::Start(offset))?;
+                ar.read_exact(buf)
+            };
+            read_decrypted(&mut read_at, key.as_ref(), offset, len)
+        };
+
+        if entry.compression_method_index == 0 {
+            return read(self.payload_offset(entry)?, entry.size);
+        }
+        let method = entry.required_method(&self.info.compression_methods).unwrap_or_default();
+        let mut content = Vec::new();
+        for range in self.compression_block_ranges(entry) {
+            let compressed = read(range.start, range.end - range.start)?;
+            let offset = content.len() as u64;
+            decompress_entry_block(
+                self.oodle.as_ref(),
+                entry,
+                method,
+                &compressed,
+                offset,
+                &mut content,
+            )?;
+        }
+        if content.len() as u64 != entry.uncompressed_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "entry at {} decompressed to {} bytes, expecting {}",
+                    entry.offset,
+                    content.len(),
+                    entry.uncompressed_size
+                ),
+            ));
+        }
+        Ok(content)
+    }
+
+    /// Read at most the first `n` bytes of `entry` payload
+    ///
+    /// This is meant for content sniffing: only the AES blocks covering the requested bytes are
+    /// read and decrypted, and only the compression blocks needed are decompressed. `key`
+    /// overrides the key the pak file was loaded with.
+    pub fn read_entry_prefix<A: Archive>(
+        &self,
+        ar: &mut A,
+        entry: &PakEntry,
+        n: usize,
+        key: Option<&str>,
+    ) -> Result<Vec<u8>, PakError> {
+        Ok(self.read_payload(entry, n, key, |offset, buf| {
+            ar.seek(io::SeekFrom::Start(offset))?;
+            ar.read_exact(buf)
+        })?)
+    }
+
+    /// Same as [`PakFile::read_entry_prefix`], reading from a shared source
+    ///
+    /// As `src` is only borrowed immutably, entries can be read concurrently from several threads.
+    pub fn read_entry_prefix_at<R: ReadAt + ?Sized>(
+        &self,
+        src: &R,
+        entry: &PakEntry,
+        n: usize,
+        key: Option<&str>,
+    ) -> Result<Vec<u8>, PakError> {
+        Ok(self.read_payload(entry, n, key, |offset, buf| src.read_at(offset, buf))?)
+    }
+
+    /// Read, decrypt and decompress at most `n` bytes of `entry` content with `read_at`
+    ///
+    /// Compressed entries are decompressed block by block until `n` bytes are available.
+    fn read_payload(
+        &self,
+        entry: &PakEntry,
+        n: usize,
+        key: Option<&str>,
+        mut read_at: impl FnMut(u64, &mut [u8]) -> io::Result<()>,
+    ) -> io::Result<Vec<u8>> {
+        let key = self.entry_key(entry, key)?;
+        if entry.compression_method_index == 0 {
+            let len = entry.size.min(n as u64);
+            return read_decrypted(&mut read_at, key.as_ref(), self.payload_offset(entry)?, len);
+        }
+
+        let method = entry.required_method(&self.info.compression_methods).unwrap_or_default();
+        let mut content = Vec::new();
+        for range in self.compression_block_ranges(entry) {
+            if content.len() >= n {
+                break;
+            }
+            let len = range.end - range.start;
+            let compressed = read_decrypted(&mut read_at, key.as_ref(), range.start, len)?;
+            let offset = content.len() as u64;
+            decompress_entry_block(
+                self.oodle.as_ref(),
+                entry,
+                method,
+                &compressed,
+                offset,
+                &mut content,
+            )?;
+        }
+        content.truncate(n);
+        Ok(content)
+    }
+
+    /// Absolute byte ranges of `entry` compression blocks in the pak file
+    ///
+    /// Since `RelativeChunkOffsets`, compression blocks offsets are relative to the entry offset.
+    /// Ranges don't include the padding of encrypted blocks.
+    pub fn compression_block_ranges(&self, entry: &PakEntry) -> Vec<Range<u64>> {
+        let base_offset = self.chunk_base_offset(entry);
+        entry
+            .compression_blocks
+            .iter()
+            .map(|block| base_offset + block.compressed_start..base_offset + block.compressed_end)
+            .collect()
+    }
+
+    /// Offset compression blocks of `entry` are relative to
+    ///
+    /// Before `RelativeChunkOffsets`, compression blocks offsets are absolute file offsets.
+    fn chunk_base_offset(&self, entry: &PakEntry) -> u64 {
+        if self.info.version < PakVersion::RelativeChunkOffsets {
+            0
+        } else {
+            entry.offset
+        }
+    }
+
+    /// Key to decrypt `entry` with, `None` if `entry` isn't encrypted
+    ///
+    /// `key` overrides the key the pak file was loaded with.
+    fn entry_key(&self, entry: &PakEntry, key: Option<&str>) -> io::Result<Option<Aes256Key>> {
+        if !entry.is_encrypted() {
+            return Ok(None);
+        }
+        match key {
+            Some(key) => aes256_base64_key(key).map(Some),
+            None => self.key.map(Some).ok_or_else(|| PakError::MissingKey.into()),
+        }
+    }
+
+    /// Extract every live entry under `out_dir`, returns the number of extracted entries
+    ///
+    /// Entries are written at their full path (mount point included) made relative with
+    /// [`PakIndex::extraction_path`] rules, so `../../../` mount points can't escape `out_dir`.
+    /// Payloads are checked with [`PakFile::verify_entry`] and `key` overrides the key the pak
+    /// file was loaded with. Extraction stops at the first error.
+    pub fn extract_all<A: Archive>(
+        &self,
+        ar: &mut A,
+        out_dir: &Path,
+        key: Option<&str>,
+    ) -> Result<usize, PakError> {
+        let mut first_error = None;
+        let summary = self.extract_all_with(
+            ar,
+            out_dir,
+            key,
+            |full_path| Some(PathBuf::from(relative_path(full_path))),
+            |_, err| {
+                first_error = Some(err);
+                ControlFlow::Break(())
+            },
+        )?;
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(summary.extracted),
+        }
+    }
+
+    /// Same as [`PakFile::extract_all`] with custom output paths and error handling
+    ///
+    /// `mount_remap` maps each entry full path (mount point included) to its path relative to
+    /// `out_dir`, or `None` to skip the entry. Remapped paths that aren't relative or contain `..`
+    /// components are rejected. Errors of a single entry (read, decompression, hash mismatch,
+    /// write) are given to `on_error` with the entry full path, which decides whether extraction
+    /// continues. Only errors unrelated to a single entry are returned, like the `Unsupported`
+    /// error of pak files with entries but no full directory index, whose entry paths are unknown.
+    pub fn extract_all_with<A, R, E>(
+        &self,
+        ar: &mut A,
+        out_dir: &Path,
+        key: Option<&str>,
+        mount_remap: R,
+        mut on_error: E,
+    ) -> Result<ExtractSummary, PakError>
+    where
+        A: Archive,
+        R: Fn(&str) -> Option<PathBuf>,
+        E: FnMut(&str, PakError) -> ControlFlow<()>,
+    {
+        if let PakIndex::V2(v2) = &self.index {
+            if !v2.has_full_directory_index && v2.num_entries() > 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "pak file has no full directory index, entry paths are unknown",
+                )
+                .into());
+            }
+        }
+        let mount_point = self.index.mount_point();
+        let mut summary = ExtractSummary::default();
+        for (name, entry) in self.index.extraction_plan(self.info.version) {
+            if entry.is_deleted() {
+                continue;
+            }
+            let full_path = format!("{}{}", mount_point, name);
+            let target = match mount_remap(&full_path) {
+                Some(target) => target,
+                None => continue,
+            };
+            match self.extract_entry(ar, &entry, key, out_dir, &target) {
+                Ok(()) => summary.extracted += 1,
+                Err(err) => {
+                    summary.failed += 1;
+                    if on_error(&full_path, err.into()).is_break() {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Write `entry` content at `target` relative to `out_dir`
+    fn extract_entry<A: Archive>(
+        &self,
+        ar: &mut A,
+        entry: &PakEntry,
+        key: Option<&str>,
+        out_dir: &Path,
+        target: &Path,
+    ) -> io::Result<()> {
+        let mut path = out_dir.to_path_buf();
+        for component in target.components() {
+            match component {
+                Component::Normal(component) => path.push(component),
+                Component::CurDir => {}
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{:?} escapes the output directory", target),
+                    ))
+                }
+            }
+        }
+        self.verify_entry(ar, entry)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut reader = self.entry_reader_impl(&mut *ar, entry, key)?;
+        io::copy(&mut reader, &mut fs::File::create(&path)?)?;
+        Ok(())
+    }
+
+    /// Streaming reader over `entry` content
+    ///
+    /// Unlike [`PakFile::read_entry`], the content is never fully loaded in memory: compression
+    /// blocks are read, decrypted and decompressed one at a time when needed. Encrypted entries
+    /// are decrypted with the key the pak file was loaded with.
+    pub fn entry_reader<A: Archive>(
+        &self,
+        ar: A,
+        entry: &PakEntry,
+    ) -> Result<EntryReader<'_, A>, PakError> {
+        Ok(self.entry_reader_impl(ar, entry, None)?)
+    }
+
+    fn entry_reader_impl<A: Archive>(
+        &self,
+        ar: A,
+        entry: &PakEntry,
+        key: Option<&str>,
+    ) -> io::Result<EntryReader<'_, A>> {
+        Ok(EntryReader { pak: self, ar, decoder: self.entry_decoder(entry, key)? })
+    }
+
+    /// Decoding state of `entry` content, `key` overrides the key the pak file was loaded with
+    fn entry_decoder(&self, entry: &PakEntry, key: Option<&str>) -> io::Result<EntryDecoder> {
+        let payload_offset = if entry.compression_method_index == 0 {
+            self.payload_offset(entry)?
+        } else {
+            self.chunk_base_offset(entry)
+        };
+        Ok(EntryDecoder {
+            entry: entry.clone(),
+            key: self.entry_key(entry, key)?,
+            method: entry
+                .required_method(&self.info.compression_methods)
+                .unwrap_or_default()
+                .to_owned(),
+            payload_offset,
+            block_index: 0,
+            buffer: Vec::new(),
+            buffer_pos: 0,
+            decoded: 0,
+        })
+    }
+
+    /// Consume this pak file and return a streaming reader over the entry at `path`
+    ///
+    /// `path` is relative to the mount point. The index is dropped, only the entry, the key (if
+    /// the entry is encrypted) and the Oodle decompressor are kept. Like
+    /// [`PakFile::entry_reader`], compressed entries are decoded one block at a time.
+    pub fn take_entry_reader<A: Archive>(
+        mut self,
+        ar: A,
+        path: &str,
+    ) -> Result<OwnedEntryReader<A>, PakError> {
+        let entry = self.index.find(path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no entry at {:?}", path))
+        })?;
+        let decoder = self.entry_decoder(entry, None)?;
+        Ok(OwnedEntryReader { ar, oodle: self.oodle.take(), decoder })
+    }
+
     /// Create a new cipher that can encrypt/decrypt entry
     pub fn cipher(&self) -> Option<Ecb<Aes256, NoPadding>> {
         self.key.as_ref().map(aes256_ecb_cipher)
     }
 
-    fn decrypt_index(
-        ar: &mut impl Archive,
-        size: u64,
-        key: &Aes256Key,
-    ) -> io::Result<ArchiveReader<io::Cursor<Vec<u8>>>> {
-        let index_size =
-            usize::try_from(size).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
-        let mut block = vec![0u8; index_size];
-        ar.read_exact(&mut block)?;
-        let cipher = aes256_ecb_cipher(key);
-        cipher.decrypt(&mut block).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
-        Ok(ArchiveReader(io::Cursor::new(block)))
+    /// Raw (decrypted) bytes of every index section described by `info`
+    ///
+    /// The primary index is returned as `PakIndex`. For V2 indexes, the path hash index and the
+    /// full directory index sections are returned as `PathHashIndex` and `FullDirectoryIndex`.
+    /// Secondary sections are located by parsing only the primary index header, so they are
+    /// returned even if they can't be parsed. If the primary index can't be parsed, only its bytes
+    /// are returned.
+    pub fn read_full_index_bytes<A: Archive>(
+        ar: &mut A,
+        info: &PakInfo,
+        key: Option<&str>,
+    ) -> Result<BTreeMap<&'static str, Vec<u8>>, PakError> {
+        let key = match (info.encrypted_index, key) {
+            (true, Some(key)) => Some(aes256_base64_key(key)?),
+            (true, None) => return Err(PakError::MissingKey),
+            (false, _) => None,
+        };
+        let mut read_section = |offset: u64, size: u64| -> io::Result<Vec<u8>> {
+            let len = if key.is_some() { align(size, AES_BLOCK_SIZE) } else { size };
+            let mut bytes = vec![0u8; usize::try_from(len).map_err(io::Error::other)?];
+            ar.seek(io::SeekFrom::Start(offset))?;
+            ar.read_exact(&mut bytes)?;
+            if let Some(key) = &key {
+                aes256_ecb_cipher(key).decrypt(&mut bytes).map_err(io::Error::other)?;
+            }
+            bytes.truncate(size as usize);
+            Ok(bytes)
+        };
+
+        let mut sections = BTreeMap::new();
+        let primary = read_section(info.index_offset, info.index_size)?;
+        if info.version >= PakVersion::PathHashIndex {
+            let mut index = PakIndexV2::default();
+            let mut primary_ar = ByteOrder(ArchiveReader(io::Cursor::new(&primary)), info.endian);
+            match index.ser_de_primary(&mut primary_ar, info.version) {
+                Ok(()) => {
+                    for (name, offset, size) in index.secondary_sections() {
+                        sections.insert(name, read_section(offset, size)?);
+                    }
+                }
+                Err(err) => warn!("failed to parse the primary index: {}", err),
+            }
+        }
+        sections.insert("PakIndex", primary);
+        Ok(sections)
+    }
+
+    /// Call `f` for every entry of the pak file without keeping the index
+    ///
+    /// V1 index entries are deserialized one at a time. For V2 indexes, only the primary index
+    /// (which holds the encoded entries) is kept while the full directory index section is walked,
+    /// entries being decoded as their path is read. Deleted entries are skipped.
+    ///
+    /// Every index section is hashed before being decoded, `f` only receives verified entries.
+    /// V2 pak files without a full directory index (i.e. with only the pruned directory index
+    /// that UE writes by default) have no entry paths and are reported as `Unsupported`.
+    ///
+    /// Iteration stops at the first error returned by `f`, which is returned as is, so a caller
+    /// can return its own sentinel error to stop early.
+    pub fn stream_entries<A, F>(ar: &mut A, key: Option<&str>, mut f: F) -> Result<(), PakError>
+    where
+        A: Archive,
+        F: FnMut(&str, &PakEntry) -> io::Result<()>,
+    {
+        let info = Self::de_pakinfo_versions(
+            ar,
+            PakVersion::list().iter().rev().copied(),
+            PAK_FILE_MAGIC,
+        )?;
+        let key = key.map(aes256_base64_key).transpose()?;
+        let mut visit = |name: &str, entry: &PakEntry| {
+            if entry.is_deleted() {
+                Ok(())
+            } else {
+                f(name, entry)
+            }
+        };
+        let ar = &mut ByteOrder(ar, info.endian);
+        if !info.encrypted_index {
+            let open = |ar: &mut ByteOrder<&mut A>, offset, _size| {
+                ar.seek(io::SeekFrom::Start(offset)).map(|_| ())
+            };
+            return Ok(Self::stream_index(&info, ar, open, &mut visit)?);
+        }
+        let key = key.ok_or(PakError::MissingKey)?;
+        let mut decrypted_ar = DecryptingReader::new(ar, &key, info.index_size);
+        let open = |ar: &mut DecryptingReader<_>, offset, size| {
+            ar.seek(io::SeekFrom::Start(offset))?;
+            ar.reset(size);
+            Ok(())
+        };
+        Ok(Self::stream_index(&info, &mut decrypted_ar, open, &mut visit)?)
+    }
+
+    /// Walk the index sections of [`PakFile::stream_entries`]
+    ///
+    /// `open(ar, offset, size)` positions `ar` at the start of a section.
+    fn stream_index<A, O>(
+        info: &PakInfo,
+        ar: &mut A,
+        mut open: O,
+        visit: &mut EntryVisitor<'_>,
+    ) -> io::Result<()>
+    where
+        A: Archive,
+        O: FnMut(&mut A, u64, u64) -> io::Result<()>,
+    {
+        let mut open_checked = |ar: &mut A, ctx, offset, size, hash| {
+            open(ar, offset, size)?;
+            let mut sha1_ar = ArchiveLenSha1::new(ArchiveLen::new());
+            copy_len(ar, &mut sha1_ar, size)?;
+            Self::check_index_section(info, ctx, sha1_ar.len_sha1(), size, hash)?;
+            open(ar, offset, size)
+        };
+        open_checked(ar, "PakIndex", info.index_offset, info.index_size, info.index_hash)?;
+        if info.version < PakVersion::PathHashIndex {
+            PakIndexV1::try_for_each_entry(ar, info.version, visit)?;
+            return Ok(());
+        }
+
+        let mut index = PakIndexV2::default();
+        index.ser_de_primary(ar, info.version)?;
+        let section = index.secondary_sections().into_iter().find(|s| s.0 == "FullDirectoryIndex");
+        let (ctx, offset, size) = section.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "pak file has no full directory index, entry paths are unknown",
+            )
+        })?;
+        let hash = index.full_directory_index_hash;
+        open_checked(ar, ctx, offset, size, hash)?;
+        index.try_for_each_full_entry(ar, info.version, visit)
     }
 
-    fn load_index<A: Archive + io::Seek>(
+    pub(crate) fn load_index<A: Archive>(
         info: &PakInfo,
         ar: &mut A,
         key: &Option<Aes256Key>,
     ) -> io::Result<PakIndex> {
         trace!("trying to decode PakIndex at {:x} (size: {})", info.index_offset, info.index_size,);
+        let ar = &mut ByteOrder(ar, info.endian);
         ar.seek(io::SeekFrom::Start(info.index_offset))?;
 
         if info.version >= PakVersion::FrozenIndex && info.index_is_frozen {
@@ -96,21 +1315,24 @@ impl PakFile {
         }
         if info.encrypted_index {
             if let Some(key) = key {
-                let mut decrypted_ar = Self::decrypt_index(ar, info.index_size, key)?;
+                let mut decrypted_ar = DecryptingReader::new(ar, key, info.index_size);
+                Self::check_index_key(info, &mut decrypted_ar)?;
+                decrypted_ar.seek(io::SeekFrom::Start(info.index_offset))?;
+                decrypted_ar.reset(info.index_size);
                 Self::_load_index(
                     info,
                     &mut decrypted_ar,
-                    move |decrypted_ar, offset, size| {
-                        ar.seek(io::SeekFrom::Start(offset))?;
-                        *decrypted_ar = Self::decrypt_index(ar, size, key)?;
+                    |decrypted_ar, offset, size| {
+                        decrypted_ar.seek(io::SeekFrom::Start(offset))?;
+                        decrypted_ar.reset(size);
                         Ok(())
                     },
                     |sha1_ar, size| {
                         if sha1_ar.len() < size {
                             let pad_size = size - sha1_ar.len();
-                            if pad_size < Aes256BlockSize::U64 {
+                            if pad_size < AES_BLOCK_SIZE {
                                 // read at most one block size
-                                let mut b = [0u8; Aes256BlockSize::USIZE];
+                                let mut b = [0u8; AES_BLOCK_SIZE as usize];
                                 sha1_ar.read_exact(&mut b[0..pad_size as usize])?;
                             }
                         }
@@ -118,10 +1340,7 @@ impl PakFile {
                     },
                 )
             } else {
-                Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "PakFile is encrypted and no decryption key provided",
-                ))
+                Err(PakError::MissingKey.into())
             }
         } else {
             Self::_load_index(
@@ -152,16 +1371,13 @@ impl PakFile {
             let mut pak_index = PakIndexV2::default();
             pak_index.ser_de(&mut sha1_ar, info.version, |sha1_ar, offset, size, hash, ctx| {
                 pad(sha1_ar, next_size)?;
-                let (ar_len, ar_hash) = sha1_ar.len_sha1();
-                if next_size != ar_len || ar_hash != next_hash {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        format!(
-                            "Corrupt {} ({} != {} or {:X?} != {:X?})",
-                            next_ctx, ar_len, next_size, ar_hash, next_hash
-                        ),
-                    ));
-                }
+                Self::check_index_section(
+                    info,
+                    next_ctx,
+                    sha1_ar.len_sha1(),
+                    next_size,
+                    next_hash,
+                )?;
                 next_size = size;
                 next_hash = hash;
                 next_ctx = ctx;
@@ -175,51 +1391,244 @@ impl PakFile {
             PakIndex::V1(pak_index)
         };
         pad(&mut sha1_ar, next_size)?;
-        let (ar_len, ar_hash) = sha1_ar.len_sha1();
-        if next_size != ar_len || ar_hash != next_hash {
+        Self::check_index_section(info, next_ctx, sha1_ar.len_sha1(), next_size, next_hash)?;
+
+        Ok(pak_index)
+    }
+
+    /// Check the SHA1 of the decrypted primary index before decoding it
+    ///
+    /// A wrong key decrypts the index to garbage, which would otherwise fail to decode with a
+    /// misleading error (i.e. an invalid string length).
+    fn check_index_key<A: Archive>(info: &PakInfo, ar: &mut A) -> io::Result<()> {
+        let mut sha1_ar = ArchiveLenSha1::new(ArchiveLen::new());
+        copy_len(ar, &mut sha1_ar, info.index_size)?;
+        let (_, hash) = sha1_ar.len_sha1();
+        if hash != info.index_hash {
+            return Err(PakError::WrongKey.into());
+        }
+        Ok(())
+    }
+
+    /// Check the length and SHA1 of the index section `ctx` that has just been read
+    ///
+    /// A hash mismatch of an encrypted index is reported as [`PakError::WrongKey`], a wrong key
+    /// being far more likely than a corruption that still decodes.
+    fn check_index_section(
+        info: &PakInfo,
+        ctx: &str,
+        (len, hash): (u64, [u8; 20]),
+        expected_len: u64,
+        expected_hash: [u8; 20],
+    ) -> io::Result<()> {
+        if len != expected_len {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                format!(
-                    "Corrupt {} ({} != {} or {:X?} != {:X?})",
-                    next_ctx, ar_len, next_size, ar_hash, next_hash
-                ),
+                format!("Corrupt {} ({} != {})", ctx, len, expected_len),
             ));
         }
+        if hash != expected_hash {
+            let err = if info.encrypted_index {
+                PakError::WrongKey
+            } else {
+                PakError::HashMismatch {
+                    context: ctx.to_owned(),
+                    expected: expected_hash,
+                    found: hash,
+                }
+            };
+            return Err(err.into());
+        }
+        Ok(())
+    }
 
-        Ok(pak_index)
+    fn de_pakinfo_versions<A: Archive>(
+        ar: &mut A,
+        versions: impl Iterator<Item = PakVersion>,
+        magic: u32,
+    ) -> io::Result<PakInfo> {
+        // the footer ends the pak file
+        Self::de_pakinfo_at(ar, versions, magic, |ar_len, info_len| {
+            ar_len.checked_sub(info_len).filter(|pos| *pos > 0)
+        })
     }
 
-    fn de_pakinfo_versions<A: Archive + io::Seek>(
+    /// Decode the footer of the first matching version of `versions`
+    ///
+    /// `footer_pos` returns the position of the footer from the archive length and the footer
+    /// length, versions it returns `None` for are skipped. Versions sharing the same raw value
+    /// are told apart by their footer length, see [`PakFile::is_plausible_pakinfo`]. Footers are
+    /// decoded little-endian first, then big-endian: the magic only matches in the pak byte order.
+    fn de_pakinfo_at<A: Archive>(
         ar: &mut A,
         versions: impl Iterator<Item = PakVersion>,
+        magic: u32,
+        footer_pos: impl Fn(u64, u64) -> Option<u64>,
     ) -> io::Result<PakInfo> {
         let ar_len = ar.seek(io::SeekFrom::End(0))?;
+        let versions: Vec<_> = versions.collect();
 
-        for version in versions {
-            let mut info = PakInfo::new(version);
-            let info_len = info.ser_de_len();
-            if info_len < ar_len {
-                trace!(
-                    "trying to decode PakInfo version {:?} at {:x} (size: {})",
-                    info.version,
-                    ar_len - info_len,
-                    info_len
-                );
-                ar.seek(io::SeekFrom::Start(ar_len - info_len))?;
-                match info.ser_de(ar) {
-                    Err(err) if err.kind() == io::ErrorKind::InvalidInput => {
-                        // try older version
-                    }
-                    Err(err) => return Err(err),
-                    Ok(()) => {
-                        debug!("found PakInfo version {}", info.version);
-                        ar.seek(io::SeekFrom::Start(0))?;
-                        return Ok(info);
+        for endian in [Endian::Little, Endian::Big] {
+            let mut ar = ByteOrder(&mut *ar, endian);
+            for &version in &versions {
+                let mut info = PakInfo { magic, ..PakInfo::new(version) };
+                let info_len = info.ser_de_len();
+                if let Some(pos) = footer_pos(ar_len, info_len) {
+                    trace!(
+                        "trying to decode PakInfo version {:?} ({:?}) at {:x} (size: {})",
+                        info.version,
+                        endian,
+                        pos,
+                        info_len
+                    );
+                    ar.seek(io::SeekFrom::Start(pos))?;
+                    match info.ser_de(&mut ar) {
+                        Err(err) if err.kind() == io::ErrorKind::InvalidInput => {
+                            // try older version
+                        }
+                        Err(err) => return Err(err),
+                        Ok(()) if !Self::is_plausible_pakinfo(&info, pos) => {
+                            // try the 4.22 layout of the same raw version
+                        }
+                        Ok(()) => {
+                            debug!("found PakInfo version {} ({:?})", info.version, endian);
+                            ar.seek(io::SeekFrom::Start(0))?;
+                            return Ok(info);
+                        }
                     }
                 }
             }
         }
 
-        Err(io::Error::new(io::ErrorKind::InvalidData, "no compatible version found"))
+        Err(PakError::UnsupportedVersion.into())
+    }
+
+    /// Sanity check of a footer decoded at `pos` before trusting its layout
+    ///
+    /// `FNameBasedCompressionMethod` and `FNameBasedCompressionMethod422` share the raw version
+    /// 8 and only differ by the number of compression method slots (5 and 4). The larger layout
+    /// is tried first, read on a 4.22 footer it starts 32 bytes early so the magic usually
+    /// doesn't match. Should the index bytes there look like a footer, the index of the decoded
+    /// footer wouldn't end before it and the 4.22 layout is tried next.
+    fn is_plausible_pakinfo(info: &PakInfo, pos: u64) -> bool {
+        info.version != PakVersion::FNameBasedCompressionMethod
+            || info.index_offset.checked_add(info.index_size).is_some_and(|end| end <= pos)
+    }
+
+    /// Decode the footer with the latest known layout if its version is newer than known ones
+    ///
+    /// Both byte orders are tried, the magic tells which one the footer uses.
+    fn de_pakinfo_newer<A: Archive>(ar: &mut A) -> io::Result<Option<(PakInfo, i32)>> {
+        let latest = *PakVersion::list().last().expect("at least one version");
+        let ar_len = ar.seek(io::SeekFrom::End(0))?;
+        let info_len = PakInfo::new(latest).ser_de_len();
+        if info_len >= ar_len {
+            return Ok(None);
+        }
+
+        let mut footer = vec![0u8; info_len as usize];
+        ar.seek(io::SeekFrom::Start(ar_len - info_len))?;
+        ar.read_exact(&mut footer)?;
+        // encryption key guid, encrypted index flag and magic precede the version
+        const VERSION_POS: usize = 16 + 1 + 4;
+        let mut raw_version = [0u8; 4];
+        raw_version.copy_from_slice(&footer[VERSION_POS..VERSION_POS + 4]);
+        for endian in [Endian::Little, Endian::Big] {
+            let (raw_version, latest_bytes) = match endian {
+                Endian::Little => (i32::from_le_bytes(raw_version), latest.raw().to_le_bytes()),
+                Endian::Big => (i32::from_be_bytes(raw_version), latest.raw().to_be_bytes()),
+            };
+            if raw_version <= latest.raw() {
+                continue;
+            }
+
+            let mut patched = footer.clone();
+            patched[VERSION_POS..VERSION_POS + 4].copy_from_slice(&latest_bytes);
+            let mut info = PakInfo::new(latest);
+            match info.ser_de(&mut ByteOrder(ArchiveReader(io::Cursor::new(patched)), endian)) {
+                Err(err) if err.kind() == io::ErrorKind::InvalidInput => continue,
+                res => res?,
+            }
+            ar.seek(io::SeekFrom::Start(0))?;
+            return Ok(Some((info, raw_version)));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn decrypting_reader() {
+        let key = aes256_base64_key("AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=").unwrap();
+        let plaintext: Vec<u8> = (0..48).collect();
+        let mut encrypted = plaintext.clone();
+        aes256_ecb_cipher(&key).encrypt(&mut encrypted, plaintext.len()).unwrap();
+
+        let mut ar = DecryptingReader::new(ArchiveReader(Cursor::new(&encrypted)), &key, 48);
+        let mut decrypted = vec![0u8; 48];
+        for range in [0..5, 5..16, 16..48] {
+            ar.read_exact(&mut decrypted[range]).unwrap();
+        }
+        assert_eq!(decrypted, plaintext);
+        assert!(ar.read_exact(&mut [0u8; 1]).is_err());
+
+        let mut ar = DecryptingReader::new(ArchiveReader(Cursor::new(&encrypted)), &key, 20);
+        ar.read_exact(&mut [0u8; 16]).unwrap();
+        let err = ar.read_exact(&mut [0u8; 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // seeking drops the partially read block
+        let mut ar = DecryptingReader::new(ArchiveReader(Cursor::new(&encrypted)), &key, 48);
+        ar.read_exact(&mut [0u8; 5]).unwrap();
+        assert_eq!(ar.seek(io::SeekFrom::Start(32)).unwrap(), 32);
+        ar.reset(16);
+        let mut block = [0u8; 16];
+        ar.read_exact(&mut block).unwrap();
+        assert_eq!(block[..], plaintext[32..]);
+    }
+
+    #[test]
+    fn de_pakinfo_newer_big_endian() {
+        let latest = *PakVersion::list().last().unwrap();
+        let mut ar = ByteOrder(ArchiveWriter(Cursor::new(Vec::new())), Endian::Big);
+        PakInfo::new(latest).ser_de(&mut ar).unwrap();
+        let mut bytes = [vec![0u8; 64], ar.0 .0.into_inner()].concat();
+        // encryption key guid, encrypted index flag and magic precede the version
+        let version_pos = 64 + 16 + 1 + 4;
+        bytes[version_pos..version_pos + 4].copy_from_slice(&(latest.raw() + 1).to_be_bytes());
+
+        let mut ar = ArchiveReader(Cursor::new(&bytes));
+        let (info, raw_version) = PakFile::de_pakinfo_newer(&mut ar).unwrap().unwrap();
+        assert_eq!(raw_version, latest.raw() + 1);
+        assert_eq!(info.version, latest);
+        assert_eq!(info.endian, Endian::Big);
+    }
+
+    #[test]
+    fn read_full_index_bytes_big_endian() {
+        let version = PakVersion::Fnv64BugFix;
+        let mut index = PakIndexV2::default();
+        index.has_path_hash_index = true;
+        index.has_full_directory_index = true;
+        index
+            .add("Game/a.txt".to_owned(), PakEntry { size: 5, ..PakEntry::default() }, version)
+            .unwrap();
+        let mut ar = ByteOrder(ArchiveWriter(Cursor::new(Vec::new())), Endian::Big);
+        let (index_size, _) = index.write(&mut ar, version, 0).unwrap();
+        let bytes = ar.0 .0.into_inner();
+
+        let mut info = PakInfo::new(version);
+        info.endian = Endian::Big;
+        info.index_size = index_size;
+        let mut ar = ArchiveReader(Cursor::new(&bytes));
+        let sections = PakFile::read_full_index_bytes(&mut ar, &info, None).unwrap();
+        let names: Vec<_> = sections.keys().copied().collect();
+        assert_eq!(names, ["FullDirectoryIndex", "PakIndex", "PathHashIndex"]);
+        assert_eq!(sections.values().map(Vec::len).sum::<usize>(), bytes.len());
     }
 }
@@ -1,11 +1,80 @@
-use std::io;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+use std::{io, iter};
 
-use crate::archive::Archive;
+use sha1::{Digest, Sha1};
+
+use crate::archive::{align, Archivable, ArchivableWith, Archive};
+use crate::constants::AES_BLOCK_SIZE;
 use crate::pakindexv1::PakIndexV1;
 use crate::pakindexv2::PakIndexV2;
-use crate::PakVersion;
+use crate::{aes256_base64_key, PakEntry, PakFile, PakInfo, PakVersion};
+
+/// Offset past `entry` payload, encrypted payloads are padded to the AES block size
+fn entry_end(entry: &PakEntry, version: PakVersion) -> u64 {
+    entry.offset + entry.ser_len_with(version) + entry.disk_size()
+}
+
+/// Byte ranges read to extract `entry`, in read order: its header, then its payload
+///
+/// Compressed payloads are read block by block, fragmented pak files store blocks away from the
+/// header. Contiguous ranges are merged, encrypted blocks are padded to the AES block size.
+fn read_ranges(entry: &PakEntry, version: PakVersion) -> Vec<Range<u64>> {
+    let header_end = entry.offset + entry.ser_len_with(version);
+    let uncompressed_payload =
+        entry.compression_blocks.is_empty().then(|| header_end..header_end + entry.disk_size());
+    // before `RelativeChunkOffsets`, compression blocks offsets are absolute file offsets
+    let base_offset = if version < PakVersion::RelativeChunkOffsets { 0 } else { entry.offset };
+    let blocks = entry.compression_blocks.iter().map(|block| {
+        let mut len = block.compressed_end.saturating_sub(block.compressed_start);
+        if entry.is_encrypted() {
+            len = align(len, AES_BLOCK_SIZE);
+        }
+        let start = base_offset + block.compressed_start;
+        start..start + len
+    });
+
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    for range in iter::once(entry.offset..header_end).chain(uncompressed_payload).chain(blocks) {
+        match ranges.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => ranges.push(range),
+        }
+    }
+    ranges
+}
+
+/// Edit of a [`PakIndex`], see [`PakIndex::size_delta_after`]
+#[derive(Debug, Clone)]
+pub enum IndexChange {
+    /// Add `entry` at `path` (relative to the mount point)
+    Add { path: String, entry: PakEntry },
+    /// Remove the record at `path` (relative to the mount point)
+    Remove { path: String },
+}
+
+/// Make an entry full path relative, see [`PakIndex::extraction_path`]
+pub(crate) fn relative_path(full_path: &str) -> String {
+    let full_path = full_path.replace('\\', "/");
+    let mut rest = full_path.as_str();
+    let mut skip = 0;
+    if let Some(unc) = rest.strip_prefix("//") {
+        // host and share components
+        rest = unc;
+        skip = 2;
+    } else if rest.len() >= 2
+        && rest.as_bytes()[0].is_ascii_alphabetic()
+        && rest.as_bytes()[1] == b':'
+    {
+        rest = &rest[2..];
+    }
+    let components: Vec<_> =
+        rest.split('/').filter(|c| !c.is_empty() && *c != "." && *c != "..").skip(skip).collect();
+    components.join("/")
+}
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum PakIndex {
     V1(PakIndexV1),
     V2(PakIndexV2),
@@ -20,6 +89,325 @@ impl PakIndex {
         }
     }
 
+    /// Normalize an asset path before adding it to an index
+    ///
+    /// Backslashes are converted to slashes, empty and `.` components are removed (so leading
+    /// slashes are trimmed) and the case is preserved. Paths with `..` components or without a
+    /// directory are rejected with an `InvalidInput` error.
+    pub fn normalize_path(raw: &str) -> io::Result<String> {
+        let invalid = |reason: &str| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("asset path {:?} {}", raw, reason))
+        };
+        let raw_slashes = raw.replace('\\', "/");
+        let components: Vec<_> =
+            raw_slashes.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+        if components.contains(&"..") {
+            return Err(invalid("contains a parent directory component"));
+        }
+        if components.len() < 2 {
+            return Err(invalid("is not inside a directory"));
+        }
+        Ok(components.join("/"))
+    }
+
+    /// Load the index described by an already parsed footer
+    ///
+    /// `key` is required if the index is encrypted.
+    pub fn load_standalone<A: Archive>(
+        ar: &mut A,
+        info: &PakInfo,
+        key: Option<&str>,
+    ) -> io::Result<PakIndex> {
+        let key = key.map(aes256_base64_key).transpose()?;
+        PakFile::load_index(info, ar, &key)
+    }
+
+    /// `true` if `path` (relative to the mount point) has a record in this index
+    ///
+    /// This is cheaper than resolving the entry, deleted records are reported as present.
+    pub fn contains(&self, path: &str, version: PakVersion) -> bool {
+        match self {
+            PakIndex::V1(v1) => v1.contains(path),
+            PakIndex::V2(v2) => v2.contains(path, version),
+        }
+    }
+
+    /// Live entry at `path` (relative to the mount point)
+    ///
+    /// V2 indexes are searched through their full directory index. Deleted records are reported
+    /// as missing.
+    pub fn find(&self, path: &str) -> Option<&PakEntry> {
+        let entry = match self {
+            PakIndex::V1(v1) => v1.find(path),
+            PakIndex::V2(v2) => v2.find(path),
+        };
+        entry.filter(|entry| !entry.is_deleted())
+    }
+
+    /// Same as [`PakIndex::find`], ignoring case like UE does
+    ///
+    /// V2 indexes are searched through their path hash index, which is case insensitive.
+    pub fn find_ci(&self, path: &str, version: PakVersion) -> Option<&PakEntry> {
+        let entry = match self {
+            PakIndex::V1(v1) => v1.find_ci(path),
+            PakIndex::V2(v2) => v2.find_ci(path, version),
+        };
+        entry.filter(|entry| !entry.is_deleted())
+    }
+
+    /// Mount point the entry paths are relative to
+    pub fn mount_point(&self) -> &str {
+        match self {
+            PakIndex::V1(v1) => &v1.mount_point,
+            PakIndex::V2(v2) => &v2.mount_point,
+        }
+    }
+
+    /// Path of `path` (relative to the mount point) relative to an extraction directory
+    ///
+    /// The full path is made relative: backslashes are converted to slashes, drive letters
+    /// (`D:/`) and UNC prefixes (`//host/share/`) are stripped and empty, `.` and `..` components
+    /// are removed (so the `../../../` mount points are stripped). The result can't escape the
+    /// extraction directory.
+    pub fn extraction_path(&self, path: &str) -> String {
+        relative_path(&format!("{}{}", self.mount_point(), path))
+    }
+
+    /// Number of entries in the index
+    pub fn num_entries(&self) -> usize {
+        match self {
+            PakIndex::V1(v1) => v1.num_entries(),
+            PakIndex::V2(v2) => v2.num_entries(),
+        }
+    }
+
+    /// Iterate over all entries
+    pub fn entries(&self) -> Box<dyn Iterator<Item = &PakEntry> + '_> {
+        match self {
+            PakIndex::V1(v1) => Box::new(v1.entries()),
+            PakIndex::V2(v2) => Box::new(v2.entries()),
+        }
+    }
+
+    /// Distinct compression block sizes used by compressed entries
+    pub fn block_sizes(&self) -> BTreeSet<u32> {
+        self.entries()
+            .filter(|entry| entry.compression_method_index != 0)
+            .map(|entry| entry.compression_block_size)
+            .collect()
+    }
+
+    /// Live entries with their path relative to the mount point
+    ///
+    /// For V2 indexes, only entries of the full directory index are named.
+    pub fn named_entries(&self) -> Vec<(String, &PakEntry)> {
+        match self {
+            PakIndex::V1(v1) => v1
+                .named_entries()
+                .filter(|(_, entry)| !entry.is_deleted())
+                .map(|(name, entry)| (name.to_owned(), entry))
+                .collect(),
+            PakIndex::V2(v2) => v2.named_entries().collect(),
+        }
+    }
+
+    /// Live entries with their path relative to the mount point, grouped by compression method
+    ///
+    /// Keys are `compression_method_index`, see [`PakEntry::required_method`] for the names.
+    pub fn entries_by_method(&self) -> BTreeMap<u32, Vec<(String, &PakEntry)>> {
+        let mut groups: BTreeMap<u32, Vec<_>> = BTreeMap::new();
+        for (name, entry) in self.named_entries() {
+            groups.entry(entry.compression_method_index).or_default().push((name, entry));
+        }
+        groups
+    }
+
+    /// Full path of every live entry, relative like [`PakIndex::extraction_path`]
+    ///
+    /// The mount point is joined with each entry path, so a `../../../Game/` mount point gives
+    /// `Game/...` asset paths.
+    pub fn paths(&self) -> impl Iterator<Item = String> + '_ {
+        self.named_entries().into_iter().map(move |(name, _)| self.extraction_path(&name))
+    }
+
+    /// Live entries whose full path (as returned by [`PakIndex::paths`]) matches the glob
+    /// `pattern`
+    ///
+    /// `*` doesn't match path separators while `**` matches any number of directories, so
+    /// `Game/Content/UI/**/*.uasset` selects every `.uasset` under `Game/Content/UI`. An invalid
+    /// pattern is reported as an `InvalidInput` error.
+    pub fn matching<'a>(
+        &'a self,
+        pattern: &str,
+    ) -> io::Result<impl Iterator<Item = (String, &'a PakEntry)> + 'a> {
+        let pattern = glob::Pattern::new(pattern)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let options = glob::MatchOptions { require_literal_separator: true, ..Default::default() };
+        Ok(self.named_entries().into_iter().filter_map(move |(name, entry)| {
+            let path = self.extraction_path(&name);
+            pattern.matches_with(&path, options).then_some((path, entry))
+        }))
+    }
+
+    /// Sorted [`PakIndex::paths`] of every live entry
+    pub fn to_path_list(&self) -> Vec<String> {
+        let mut paths: Vec<_> = self.paths().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Layout independent SHA1 digest of the live entries
+    ///
+    /// Live entries are sorted by full path (mount point included, byte order), then for each
+    /// entry the digest is fed with the UTF-8 path, a `0` byte, `size` as a little endian `u64`
+    /// and the 20 bytes of `hash`. Offsets, flags and compression blocks are excluded, so pak files
+    /// with the same content packed in a different order have the same digest.
+    pub fn content_digest(&self) -> [u8; 20] {
+        let mount_point = self.mount_point();
+        let mut entries: Vec<_> = self
+            .named_entries()
+            .into_iter()
+            .map(|(name, entry)| (format!("{}{}", mount_point, name), entry))
+            .collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut sha1 = Sha1::new();
+        for (path, entry) in entries {
+            sha1.update(path.as_bytes());
+            sha1.update([0]);
+            sha1.update(entry.size.to_le_bytes());
+            sha1.update(entry.hash);
+        }
+        sha1.finalize().into()
+    }
+
+    /// Pairs of live entries whose on-disk extents overlap
+    ///
+    /// The extent of an entry covers its header and its payload (`size`, padded to the AES block
+    /// size when encrypted). Aliases, entries sharing the same offset and size, are not reported.
+    pub fn find_overlaps(&self, version: PakVersion) -> Vec<(String, String)> {
+        let mut extents: Vec<_> = self
+            .named_entries()
+            .into_iter()
+            .map(|(name, entry)| (entry.offset, entry_end(entry, version), name))
+            .collect();
+        extents.sort();
+
+        let mut overlaps = Vec::new();
+        for (i, (start, end, name)) in extents.iter().enumerate() {
+            for (other_start, other_end, other_name) in &extents[i + 1..] {
+                if other_start >= end {
+                    break;
+                }
+                if (start, end) != (other_start, other_end) {
+                    overlaps.push((name.clone(), other_name.clone()));
+                }
+            }
+        }
+        overlaps
+    }
+
+    /// End of the furthest live entry (header and payload), `0` if there is no live entry
+    ///
+    /// In a well formed pak file, this is the `index_offset` of the footer.
+    pub fn last_entry_end(&self, version: PakVersion) -> u64 {
+        self.entries()
+            .filter(|entry| !entry.is_deleted())
+            .map(|entry| entry_end(entry, version))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Groups of live entry paths sharing the same payload `hash` and `size`
+    ///
+    /// The hash is computed over the on-disk payload, entries of a group have identical on-disk
+    /// bytes. Only groups of at least two paths are returned, paths of a group are sorted.
+    pub fn duplicate_groups(&self) -> Vec<Vec<String>> {
+        let mut groups: BTreeMap<_, Vec<String>> = BTreeMap::new();
+        for (name, entry) in self.named_entries() {
+            groups.entry((entry.hash, entry.size)).or_default().push(name);
+        }
+        let mut groups: Vec<_> = groups
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .map(|mut group| {
+                group.sort();
+                group
+            })
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    /// Live entries in the order they should be extracted
+    ///
+    /// Each entry is read as a whole, header then compression blocks, so the blocks of a file are
+    /// read together. Entries are ordered by the start of the last byte range they read: entries
+    /// stored contiguously are in offset order, read in a single forward pass, while an entry of
+    /// a fragmented pak file whose blocks are stored away from its header is scheduled next to
+    /// its blocks, only going back for its header. Ties are ordered by offset, then by name. See
+    /// [`PakIndex::seek_distance`] to compare plans.
+    pub fn extraction_plan(&self, version: PakVersion) -> Vec<(String, PakEntry)> {
+        let mut plan: Vec<_> = self
+            .named_entries()
+            .into_iter()
+            .map(|(name, entry)| {
+                let last_read =
+                    read_ranges(entry, version).last().map_or(entry.offset, |r| r.start);
+                ((last_read, entry.offset), name, entry.clone())
+            })
+            .collect();
+        plan.sort_by(|(a_key, a_name, _), (b_key, b_name, _)| {
+            a_key.cmp(b_key).then(a_name.cmp(b_name))
+        });
+        plan.into_iter().map(|(_, name, entry)| (name, entry)).collect()
+    }
+
+    /// Total distance of the seeks needed to extract `entries` in order, from offset 0
+    ///
+    /// Entries are read like [`PakIndex::extraction_plan`] expects: the header, then the
+    /// compression blocks in order.
+    pub fn seek_distance<'a>(
+        entries: impl IntoIterator<Item = &'a PakEntry>,
+        version: PakVersion,
+    ) -> u64 {
+        let mut pos = 0;
+        let mut distance = 0;
+        for range in entries.into_iter().flat_map(|entry| read_ranges(entry, version)) {
+            distance += pos.max(range.start) - pos.min(range.start);
+            pos = range.end;
+        }
+        distance
+    }
+
+    /// Change of the serialized index size once `change` is applied
+    ///
+    /// The delta is computed from the changed records only, without serializing the index. For V1
+    /// indexes this is the change of the footer `index_size`. For V2 indexes the secondary
+    /// sections are included: the path hash index record and the directory index records, see
+    /// [`PakIndexV2::add`] for which ones are written. A full directory index directory is added
+    /// with its first file and removed with its last one, pruned directory index directories are
+    /// kept. Removals assume the encoded entries are rebuilt. Removing a path without a record is
+    /// a no-op.
+    pub fn size_delta_after(&self, change: &IndexChange, version: PakVersion) -> i64 {
+        let v1_record_len =
+            |path: &str, entry: &PakEntry| path.to_owned().ser_len() + entry.ser_len_with(version);
+        match (self, change) {
+            (PakIndex::V1(_), IndexChange::Add { path, entry }) => {
+                v1_record_len(path, entry) as i64
+            }
+            (PakIndex::V1(v1), IndexChange::Remove { path }) => {
+                v1.find(path).map_or(0, |entry| -(v1_record_len(path, entry) as i64))
+            }
+            (PakIndex::V2(v2), IndexChange::Add { path, entry }) => {
+                v2.add_len(path, entry, version) as i64
+            }
+            (PakIndex::V2(v2), IndexChange::Remove { path }) => {
+                v2.remove_len(path, version).map_or(0, |len| -(len as i64))
+            }
+        }
+    }
+
     pub fn ser<A: Archive>(&mut self, ar: &mut A, version: PakVersion) -> io::Result<()> {
         match self {
             PakIndex::V1(v1) => v1.ser_de(ar, version),
@@ -27,3 +415,289 @@ impl PakIndex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ArchiveLen;
+    use crate::pakentry::{PakCompressedBlock, FLAG_DELETED};
+
+    #[test]
+    fn find_overlaps() {
+        let version = PakVersion::EncryptionKeyGuid;
+        let header_size = PakEntry::default().ser_len_with(version);
+        let mut v1 = PakIndexV1::default();
+        for (name, offset) in [("a/x", 0), ("a/y", 100 + header_size - 1), ("a/z", 400), ("a/w", 0)]
+        {
+            v1.add(name.to_owned(), PakEntry { offset, size: 100, ..PakEntry::default() });
+        }
+        let index = PakIndex::V1(v1);
+        assert_eq!(
+            index.find_overlaps(version),
+            [("a/w".to_owned(), "a/y".to_owned()), ("a/x".to_owned(), "a/y".to_owned())]
+        );
+    }
+
+    #[test]
+    fn size_delta_after() {
+        // V2 secondary sections included
+        let index_len = |index: &mut PakIndex, version| {
+            let mut ar = ArchiveLen::new();
+            match index {
+                PakIndex::V1(v1) => v1.ser_de(&mut ar, version).unwrap(),
+                PakIndex::V2(v2) => {
+                    v2.write(&mut ar, version, 0).unwrap();
+                }
+            }
+            ar.len() as i64
+        };
+        let entry =
+            PakEntry { offset: 100, size: 10, uncompressed_size: 10, ..PakEntry::default() };
+        let layouts = [
+            (PakVersion::DeleteRecords, false),
+            (PakVersion::Fnv64BugFix, true),
+            (PakVersion::Fnv64BugFix, false),
+        ];
+        for (version, full_directory_index) in layouts {
+            let mut index = PakIndex::new(version);
+            if let PakIndex::V2(v2) = &mut index {
+                v2.has_path_hash_index = true;
+                v2.has_full_directory_index = full_directory_index;
+            }
+            let mut deltas = Vec::new();
+            // a new directory, then an existing one
+            for path in ["a/x", "a/y"] {
+                let before = index_len(&mut index, version);
+                let add = IndexChange::Add { path: path.to_owned(), entry: entry.clone() };
+                let delta = index.size_delta_after(&add, version);
+                match &mut index {
+                    PakIndex::V1(v1) => {
+                        v1.add(path.to_owned(), entry.clone());
+                    }
+                    PakIndex::V2(v2) => {
+                        v2.add(path.to_owned(), entry.clone(), version).unwrap();
+                    }
+                }
+                assert_eq!(index_len(&mut index, version) - before, delta);
+                deltas.push(delta);
+            }
+
+            // the directory keeps a file
+            let remove = IndexChange::Remove { path: "a/y".to_owned() };
+            assert_eq!(index.size_delta_after(&remove, version), -deltas[1]);
+            let remove = IndexChange::Remove { path: "a/z".to_owned() };
+            assert_eq!(index.size_delta_after(&remove, version), 0);
+        }
+
+        let version = PakVersion::Fnv64BugFix;
+        let mut empty = PakIndexV2::default();
+        empty.has_full_directory_index = true;
+        let add = IndexChange::Add { path: "a/x".to_owned(), entry: entry.clone() };
+        let delta = PakIndex::V2(empty).size_delta_after(&add, version);
+        let mut v2 = PakIndexV2::default();
+        v2.has_full_directory_index = true;
+        v2.add("a/x".to_owned(), entry, version).unwrap();
+        // the last file of the directory
+        let remove = IndexChange::Remove { path: "a/x".to_owned() };
+        assert_eq!(PakIndex::V2(v2).size_delta_after(&remove, version), -delta);
+    }
+
+    #[test]
+    fn duplicate_groups() {
+        let mut v1 = PakIndexV1::default();
+        let entries =
+            [("a/x", [1; 20], 10), ("a/y", [2; 20], 10), ("b/x", [1; 20], 10), ("c", [1; 20], 11)];
+        for (name, hash, size) in entries {
+            v1.add(name.to_owned(), PakEntry { hash, size, ..PakEntry::default() });
+        }
+        let index = PakIndex::V1(v1);
+        assert_eq!(index.duplicate_groups(), [["a/x".to_owned(), "b/x".to_owned()]]);
+    }
+
+    #[test]
+    fn find() {
+        let version = PakVersion::Fnv64BugFix;
+        let entry =
+            PakEntry { offset: 100, size: 10, uncompressed_size: 10, ..PakEntry::default() };
+        let mut v1 = PakIndexV1::default();
+        v1.add("a/x".to_owned(), entry.clone());
+        v1.add("a/y".to_owned(), PakEntry { flags: FLAG_DELETED, ..PakEntry::default() });
+        let mut v2 = PakIndexV2::default();
+        v2.has_full_directory_index = true;
+        v2.add("a/x".to_owned(), entry.clone(), version).unwrap();
+        for index in [PakIndex::V1(v1), PakIndex::V2(v2)] {
+            assert_eq!(index.find("a/x"), Some(&entry));
+            assert_eq!(index.find("a/y"), None);
+            assert_eq!(index.find("a/z"), None);
+            assert_eq!(index.find("x"), None);
+        }
+    }
+
+    #[test]
+    fn entries_by_method() {
+        let mut v1 = PakIndexV1::default();
+        for (name, method) in [("a/x", 1), ("a/y", 0), ("a/z", 1)] {
+            let entry = PakEntry { compression_method_index: method, ..PakEntry::default() };
+            v1.add(name.to_owned(), entry);
+        }
+        v1.add("a/w".to_owned(), PakEntry { flags: FLAG_DELETED, ..PakEntry::default() });
+        let index = PakIndex::V1(v1);
+        let groups = index.entries_by_method();
+        let names =
+            |method| groups[&method].iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(names(0), ["a/y"]);
+        assert_eq!(names(1), ["a/x", "a/z"]);
+    }
+
+    #[test]
+    fn to_path_list() {
+        let mut v1 = PakIndexV1::default();
+        v1.mount_point = "../../../".to_owned();
+        v1.add("b/x".to_owned(), PakEntry::default());
+        v1.add("a/x".to_owned(), PakEntry::default());
+        v1.add("a/y".to_owned(), PakEntry { flags: FLAG_DELETED, ..PakEntry::default() });
+        let index = PakIndex::V1(v1);
+        assert_eq!(index.to_path_list(), ["a/x", "b/x"]);
+    }
+
+    #[test]
+    fn paths() {
+        let mut v1 = PakIndexV1::default();
+        v1.mount_point = "../../../Game/".to_owned();
+        v1.add("Content/x".to_owned(), PakEntry::default());
+        v1.add("Content/y".to_owned(), PakEntry { flags: FLAG_DELETED, ..PakEntry::default() });
+        let index = PakIndex::V1(v1);
+        assert_eq!(index.paths().collect::<Vec<_>>(), ["Game/Content/x"]);
+    }
+
+    #[test]
+    fn matching() {
+        let mut v1 = PakIndexV1::default();
+        v1.mount_point = "../../../Game/".to_owned();
+        for name in ["Content/UI/a.uasset", "Content/UI/Icons/b.uasset", "Content/UI/c.png"] {
+            v1.add(name.to_owned(), PakEntry::default());
+        }
+        let index = PakIndex::V1(v1);
+        let matching = |pattern| {
+            let mut paths: Vec<_> = index.matching(pattern).unwrap().map(|(p, _)| p).collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(
+            matching("Game/Content/UI/**/*.uasset"),
+            ["Game/Content/UI/Icons/b.uasset", "Game/Content/UI/a.uasset"]
+        );
+        assert_eq!(matching("Game/Content/UI/*.uasset"), ["Game/Content/UI/a.uasset"]);
+        assert!(matching("Content/**").is_empty());
+        assert!(index.matching("Game/[").is_err());
+    }
+
+    #[test]
+    fn content_digest() {
+        let entries = [("a/x", [1; 20], 0), ("a/y", [2; 20], 100), ("b/x", [3; 20], 200)];
+        let mut v1 = PakIndexV1::default();
+        for (name, hash, offset) in entries {
+            v1.add(name.to_owned(), PakEntry { hash, offset, size: 100, ..PakEntry::default() });
+        }
+        let index = PakIndex::V1(v1);
+
+        let mut repacked = PakIndexV1::default();
+        for (name, hash, offset) in entries.iter().rev() {
+            let offset = 200 - offset;
+            repacked.add(
+                name.to_string(),
+                PakEntry { hash: *hash, offset, size: 100, ..PakEntry::default() },
+            );
+        }
+        repacked.add("c".to_owned(), PakEntry { flags: FLAG_DELETED, ..PakEntry::default() });
+        let mut repacked = PakIndex::V1(repacked);
+        assert_eq!(index.content_digest(), repacked.content_digest());
+
+        if let PakIndex::V1(v1) = &mut repacked {
+            v1.mount_point = "../../../".to_owned();
+        }
+        assert_ne!(index.content_digest(), repacked.content_digest());
+    }
+
+    #[test]
+    fn normalize_path() {
+        let normalize = |raw| PakIndex::normalize_path(raw).map_err(|err| err.kind());
+        assert_eq!(normalize("Game\\Content\\A.uasset").unwrap(), "Game/Content/A.uasset");
+        assert_eq!(normalize("/Game//Content/./A.uasset").unwrap(), "Game/Content/A.uasset");
+        assert_eq!(normalize("../Game/A.uasset"), Err(io::ErrorKind::InvalidInput));
+        assert_eq!(normalize("Game\\..\\..\\A.uasset"), Err(io::ErrorKind::InvalidInput));
+        assert_eq!(normalize("/A.uasset"), Err(io::ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn extraction_path() {
+        let index = |mount_point: &str| {
+            let mut v1 = PakIndexV1::default();
+            v1.mount_point = mount_point.to_owned();
+            PakIndex::V1(v1)
+        };
+        let path = "Content/A.uasset";
+        assert_eq!(index("../../../Game/").extraction_path(path), "Game/Content/A.uasset");
+        assert_eq!(index("D:/Build/Game/").extraction_path(path), "Build/Game/Content/A.uasset");
+        assert_eq!(index("d:\\Build\\Game\\").extraction_path(path), "Build/Game/Content/A.uasset");
+        assert_eq!(index("//host/share/Game/").extraction_path(path), "Game/Content/A.uasset");
+        assert_eq!(index("\\\\host\\share\\Game\\").extraction_path(path), "Game/Content/A.uasset");
+        assert_eq!(index("/Game/").extraction_path("../../x/./A.uasset"), "Game/x/A.uasset");
+    }
+
+    #[test]
+    fn extraction_plan() {
+        let version = PakVersion::DeleteRecords;
+        let stride = PakEntry::default().ser_len_with(version) + 100;
+        let mut v1 = PakIndexV1::default();
+        for (name, i) in [("a/z", 0), ("a/y", 3), ("a/x", 1), ("a/w", 2)] {
+            let entry = PakEntry { offset: i * stride, size: 100, ..PakEntry::default() };
+            v1.add(name.to_owned(), entry);
+        }
+        let index = PakIndex::V1(v1);
+
+        let mut path_order = index.named_entries();
+        path_order.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let plan = index.extraction_plan(version);
+        let names: Vec<_> = plan.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a/z", "a/x", "a/w", "a/y"]);
+        assert_eq!(PakIndex::seek_distance(plan.iter().map(|(_, entry)| entry), version), 0);
+        let path_order = path_order.into_iter().map(|(_, entry)| entry);
+        assert!(PakIndex::seek_distance(path_order, version) > 0);
+    }
+
+    #[test]
+    fn extraction_plan_fragmented() {
+        let version = PakVersion::DeleteRecords;
+        // compressed entry whose single block is stored past the other entries
+        let block = PakCompressedBlock { compressed_start: 10_000, compressed_end: 10_100 };
+        let fragmented = PakEntry {
+            size: 100,
+            uncompressed_size: 200,
+            compression_method_index: 1,
+            compression_blocks: vec![block],
+            compression_block_size: 200,
+            ..PakEntry::default()
+        };
+        let offset = fragmented.ser_len_with(version);
+        let stride = PakEntry::default().ser_len_with(version) + 100;
+        let mut v1 = PakIndexV1::default();
+        v1.add("a/a".to_owned(), fragmented);
+        for (name, i) in [("a/b", 0), ("a/c", 1)] {
+            let entry = PakEntry { offset: offset + i * stride, size: 100, ..PakEntry::default() };
+            v1.add(name.to_owned(), entry);
+        }
+        let index = PakIndex::V1(v1);
+
+        let plan = index.extraction_plan(version);
+        let names: Vec<_> = plan.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a/b", "a/c", "a/a"]);
+        let mut offset_order: Vec<_> = plan.iter().map(|(_, entry)| entry).collect();
+        offset_order.sort_by_key(|entry| entry.offset);
+        assert!(
+            PakIndex::seek_distance(plan.iter().map(|(_, entry)| entry), version)
+                < PakIndex::seek_distance(offset_order, version)
+        );
+    }
+}
@@ -1,10 +1,12 @@
 use std::fmt;
 use std::{collections::HashMap, io};
 
-use crate::archive::ArchivableWith;
+use log::warn;
+
+use crate::archive::{ArchivableWith, ArchiveLen};
 use crate::{
     archive::{Archivable, Archive},
-    PakEntry, PakVersion,
+    PakEntry, PakIndexV2, PakVersion,
 };
 
 /// FPakEntryPair archivable
@@ -26,6 +28,8 @@ impl ArchivableWith<PakVersion> for PakIndexEntry {
 pub struct PakIndexV1 {
     pub mount_point: String,
     map: HashMap<String, usize>,
+    /// Same as `map` with lowercased names, for case insensitive lookups
+    lowercase_map: HashMap<String, usize>,
     files: Vec<PakIndexEntry>,
 }
 impl fmt::Display for PakIndexV1 {
@@ -36,11 +40,40 @@ impl fmt::Display for PakIndexV1 {
     }
 }
 
+/// Serialized as the mount point and the entries by name
+#[cfg(feature = "serde")]
+impl serde::Serialize for PakIndexV1 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let entries: std::collections::BTreeMap<_, _> = self.named_entries().collect();
+        let mut state = serializer.serialize_struct("PakIndexV1", 2)?;
+        state.serialize_field("mount_point", &self.mount_point)?;
+        state.serialize_field("entries", &entries)?;
+        state.end()
+    }
+}
+
 impl PakIndexV1 {
     pub fn find(&self, name: &str) -> Option<&PakEntry> {
         self.map.get(name).map(|&idx| &self.files[idx].entry)
     }
 
+    /// Same as [`PakIndexV1::find`], ignoring case like UE does
+    pub fn find_ci(&self, name: &str) -> Option<&PakEntry> {
+        self.lowercase_map.get(&name.to_lowercase()).map(|&idx| &self.files[idx].entry)
+    }
+
+    /// Number of entries (deleted records included)
+    pub fn num_entries(&self) -> usize {
+        self.files.len()
+    }
+
+    /// `true` if `name` has a record in this index (deleted records included)
+    pub fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(name)
+    }
+
     pub fn named_entries(&self) -> impl Iterator<Item = (&str, &PakEntry)> {
         self.files.iter().map(|entry| (entry.name.as_str(), &entry.entry))
     }
@@ -49,27 +82,162 @@ impl PakIndexV1 {
         self.files.iter().map(|entry| &entry.entry)
     }
 
-    pub(crate) fn take_entries(self) -> impl Iterator<Item = (String, PakEntry)> {
-        self.files.into_iter().map(|n| (n.name, n.entry))
+    /// Size of the entries once converted to a V2 index of `version`
+    ///
+    /// Returns the size of the compact encoded entries and the size of the `files` array storing
+    /// entries that can't be compact encoded, without building the V2 index.
+    pub fn estimate_v2_size(&self, version: PakVersion) -> (u64, u64) {
+        let mut encoded = ArchiveLen::new();
+        let mut files = ArchiveLen::new();
+        // array length
+        files.write_all(&[0; 4]).expect("ArchiveLen never fails");
+        for entry in self.entries() {
+            if !PakIndexV2::encode_entry(&mut encoded, entry, version).unwrap_or(false) {
+                entry.ser_with(&mut files, version).expect("ArchiveLen never fails");
+            }
+        }
+        (encoded.len(), files.len())
     }
 
     pub fn clear(&mut self) {
         self.map.clear();
+        self.lowercase_map.clear();
         self.files.clear();
     }
 
     pub fn add(&mut self, name: String, entry: PakEntry) -> &mut PakEntry {
         let idx = self.files.len();
         self.map.insert(name.clone(), idx);
+        self.lowercase_map.insert(name.to_lowercase(), idx);
         self.files.push(PakIndexEntry { name, entry });
         &mut self.files[idx].entry
     }
 
+    /// Deserialize entries one at a time, calling `f` for each of them
+    ///
+    /// Unlike [`PakIndexV1::ser_de`], at most one entry is kept in memory.
+    /// Returns the index mount point.
+    pub fn for_each_entry<A, F>(ar: &mut A, version: PakVersion, mut f: F) -> io::Result<String>
+    where
+        A: Archive,
+        F: FnMut(&str, &PakEntry),
+    {
+        Self::try_for_each_entry(ar, version, |name, entry| {
+            f(name, entry);
+            Ok(())
+        })
+    }
+
+    /// Same as [`PakIndexV1::for_each_entry`], stops at the first error returned by `f`
+    pub fn try_for_each_entry<A, F>(ar: &mut A, version: PakVersion, mut f: F) -> io::Result<String>
+    where
+        A: Archive,
+        F: FnMut(&str, &PakEntry) -> io::Result<()>,
+    {
+        let mount_point = String::de(ar)?;
+        let len = u32::de(ar)?;
+        let mut entry = PakIndexEntry::default();
+        for _ in 0..len {
+            entry.ser_de_with(ar, version)?;
+            f(&entry.name, &entry.entry)?;
+        }
+        Ok(mount_point)
+    }
+
     pub fn ser_de<A: Archive>(&mut self, ar: &mut A, version: PakVersion) -> io::Result<()> {
         self.mount_point.ser_de(ar)?;
         self.files.ser_de_with(ar, version)?;
         self.map =
             self.files.iter().enumerate().map(|(idx, entry)| (entry.name.clone(), idx)).collect();
+        self.lowercase_map = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| (entry.name.to_lowercase(), idx))
+            .collect();
+        if self.map.len() != self.files.len() {
+            warn!(
+                "index has {} entries but only {} distinct names, lookups return the last one",
+                self.files.len(),
+                self.map.len()
+            );
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::archive::{ArchiveReader, ArchiveWriter};
+
+    #[test]
+    fn ser_de_round_trip() {
+        let version = PakVersion::EncryptionKeyGuid;
+        let mut index = PakIndexV1 { mount_point: "../../../".to_owned(), ..Default::default() };
+        index.add("a/b".to_owned(), PakEntry { offset: 1, ..PakEntry::default() });
+        index.add(String::new(), PakEntry { offset: 2, ..PakEntry::default() });
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        index.ser_de(&mut ar, version).unwrap();
+
+        let mut decoded = PakIndexV1::default();
+        decoded.ser_de(&mut ArchiveReader(Cursor::new(ar.0.into_inner())), version).unwrap();
+        assert_eq!(decoded.mount_point, "../../../");
+        assert_eq!(decoded.find("a/b").map(|entry| entry.offset), Some(1));
+        assert_eq!(decoded.find("").map(|entry| entry.offset), Some(2));
+    }
+
+    #[test]
+    fn for_each_entry() {
+        let version = PakVersion::EncryptionKeyGuid;
+        let mut index = PakIndexV1 { mount_point: "../../../".to_owned(), ..Default::default() };
+        index.add("a/b".to_owned(), PakEntry { offset: 1, ..PakEntry::default() });
+        index.add("a/c".to_owned(), PakEntry { offset: 2, ..PakEntry::default() });
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        index.ser_de(&mut ar, version).unwrap();
+
+        let mut entries = Vec::new();
+        let mut ar = ArchiveReader(Cursor::new(ar.0.into_inner()));
+        let mount_point = PakIndexV1::for_each_entry(&mut ar, version, |name, entry| {
+            entries.push((name.to_owned(), entry.offset));
+        })
+        .unwrap();
+        assert_eq!(mount_point, "../../../");
+        assert_eq!(entries, [("a/b".to_owned(), 1), ("a/c".to_owned(), 2)]);
+    }
+
+    #[test]
+    fn non_ascii_mount_point() {
+        let version = PakVersion::EncryptionKeyGuid;
+        let mount_point = "../../../Jeu/Forêt/".to_owned();
+        let mut index = PakIndexV1 { mount_point: mount_point.clone(), ..Default::default() };
+        index.add("Château/a.uasset".to_owned(), PakEntry { offset: 1, ..PakEntry::default() });
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        index.ser_de(&mut ar, version).unwrap();
+
+        let mut decoded = PakIndexV1::default();
+        decoded.ser_de(&mut ArchiveReader(Cursor::new(ar.0.into_inner())), version).unwrap();
+        assert_eq!(decoded.mount_point, mount_point);
+        assert_eq!(decoded.find("Château/a.uasset").map(|entry| entry.offset), Some(1));
+    }
+
+    #[test]
+    fn estimate_v2_size() {
+        let version = PakVersion::Fnv64BugFix;
+        let mut index = PakIndexV1::default();
+        index.add("a/b".to_owned(), PakEntry { offset: 1, size: 2, ..PakEntry::default() });
+        let not_encodable = PakEntry { compression_method_index: 1 << 6, ..PakEntry::default() };
+        index.add("a/c".to_owned(), not_encodable.clone());
+
+        let mut v2 = PakIndexV2::default();
+        v2.has_full_directory_index = true;
+        for (name, entry) in index.named_entries() {
+            v2.add(name.to_owned(), entry.clone(), version).unwrap();
+        }
+        let sizes = v2.section_sizes(version);
+        assert_eq!(index.estimate_v2_size(version), (sizes.encoded_entries - 4, sizes.files));
+        assert_eq!(sizes.files, 4 + not_encodable.ser_len_with(version));
+    }
+}
@@ -3,7 +3,11 @@ use std::convert::TryFrom;
 use std::{collections::BTreeMap, io};
 use std::{fmt, mem};
 
-use crate::archive::{ArchivableWith, ArchiveReader, ArchiveWriter};
+use crate::archive::{
+    align, ArchivableWith, ArchiveLen, ArchiveLenSha1, ArchiveReader, ArchiveWriter, ByteOrder,
+    Endian,
+};
+use crate::constants::AES_BLOCK_SIZE;
 use crate::pakentry::FLAG_ENCRYPTED;
 use crate::PakCompressedBlock;
 use crate::{
@@ -11,8 +15,6 @@ use crate::{
     PakEntry, PakVersion,
 };
 
-const AES_BLOCK_SIZE: u64 = 16;
-
 fn legacy_fnv64(s: &str, seed: u64) -> u64 {
     const OFFSET: u64 = 0x00000100000001b3;
     const PRIME: u64 = 0xcbf29ce484222325;
@@ -34,20 +36,6 @@ fn fnv64(s: &str, seed: u64) -> u64 {
     h
 }
 
-/// FPakEntryPair archivable
-#[derive(Debug, Default)]
-struct PakIndexEntry {
-    name: String,
-    entry: PakEntry,
-}
-impl ArchivableWith<PakVersion> for PakIndexEntry {
-    fn ser_de_with<A: Archive>(&mut self, ar: &mut A, version: PakVersion) -> io::Result<()> {
-        self.name.ser_de(ar)?;
-        self.entry.ser_de_with(ar, version)?;
-        Ok(())
-    }
-}
-
 #[derive(Debug, Clone, Copy)]
 pub enum PakEntryLocation {
     Deleted,
@@ -75,6 +63,11 @@ impl RawPakEntryLocation {
         Self { index: -(index as i32) - 1 }
     }
 
+    /// Location of a deleted record, `MIN_int32` like UE
+    pub(crate) fn from_deleted() -> Self {
+        Self { index: i32::MIN }
+    }
+
     fn get(self) -> PakEntryLocation {
         const MAX: i32 = i32::MAX - 1;
         const MIN: i32 = -MAX - 1;
@@ -93,8 +86,35 @@ impl Archivable for RawPakEntryLocation {
     }
 }
 
-fn align(size: u64, alignment: u64) -> u64 {
-    (size + alignment - 1) & !(alignment - 1)
+/// Serialized size of a [`RawPakEntryLocation`]
+const LOCATION_LEN: u64 = 4;
+/// Serialized size of a path hash index record, the `u64` path hash and the location
+const PATH_HASH_RECORD_LEN: u64 = 8 + LOCATION_LEN;
+/// Serialized size of the `u32` file count of a directory index directory
+const DIRECTORY_COUNT_LEN: u64 = 4;
+
+/// Join a directory index directory name and file name
+fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() || dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Serialized size in bytes of each [`PakIndexV2`] section
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SectionSizes {
+    /// Compact encoded entries
+    pub encoded_entries: u64,
+    /// Entries that can't be encoded in the compact format
+    pub files: u64,
+    /// Path hash index
+    pub path_hash_index: u64,
+    /// Pruned directory index (stored with the path hash index)
+    pub pruned_dir: u64,
+    /// Full directory index
+    pub full_dir: u64,
 }
 
 #[derive(Debug, Default)]
@@ -153,6 +173,43 @@ impl fmt::Display for PakIndexV2 {
         Ok(())
     }
 }
+/// Serialized as the mount point, the section flags and hashes, and the live entries by name
+///
+/// Without a full directory index, entries are serialized by path hash (`hashed_entries`).
+#[cfg(feature = "serde")]
+impl serde::Serialize for PakIndexV2 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        struct Hash<'a>(&'a [u8; 20]);
+        impl serde::Serialize for Hash<'_> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                crate::serdehex::serialize(self.0, serializer)
+            }
+        }
+
+        let mut state = serializer.serialize_struct("PakIndexV2", 7)?;
+        state.serialize_field("mount_point", &self.mount_point)?;
+        state.serialize_field("path_hash_seed", &self.path_hash_seed)?;
+        state.serialize_field("has_path_hash_index", &self.has_path_hash_index)?;
+        state.serialize_field("path_hash_index_hash", &Hash(&self.path_hash_index_hash))?;
+        state.serialize_field("has_full_directory_index", &self.has_full_directory_index)?;
+        state
+            .serialize_field("full_directory_index_hash", &Hash(&self.full_directory_index_hash))?;
+        if self.has_full_directory_index {
+            let entries: BTreeMap<_, _> = self.named_entries().collect();
+            state.serialize_field("entries", &entries)?;
+        } else {
+            let entries: BTreeMap<_, _> = self
+                .hashed_entries()
+                .map(|(hash, entry)| (format!("{:016X}", hash), entry))
+                .collect();
+            state.serialize_field("hashed_entries", &entries)?;
+        }
+        state.end()
+    }
+}
+
 impl PakIndexV2 {
     pub fn clear(&mut self) {
         *self = Self {
@@ -164,17 +221,173 @@ impl PakIndexV2 {
         };
     }
 
+    /// Hash `path` (relative to the mount point) the way the path hash index does
+    ///
+    /// The path is lowercased before hashing and the hash function depends on `version`.
+    pub fn hash_path(path: &str, seed: u64, version: PakVersion) -> u64 {
+        let lpath = path.to_lowercase();
+        if version >= PakVersion::Fnv64BugFix {
+            fnv64(&lpath, seed)
+        } else {
+            legacy_fnv64(&lpath, seed)
+        }
+    }
+
+    /// Path hash seed UE computes for the pak file at `pak_path`
+    ///
+    /// This is `FCrc::StrCrc32` of the lowercased file name (directories are ignored): the CRC32
+    /// of its characters, each one hashed as 4 little endian bytes.
+    pub fn filename_seed(pak_path: &str) -> u64 {
+        let filename = pak_path.rsplit(['/', '\\']).next().unwrap_or(pak_path).to_lowercase();
+        let mut crc = !0u32;
+        for ch in filename.chars() {
+            for byte in u32::from(ch).to_le_bytes() {
+                crc ^= u32::from(byte);
+                for _ in 0..8 {
+                    crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+                }
+            }
+        }
+        u64::from(!crc)
+    }
+
+    /// Hash `path` (relative to the mount point) with this index `path_hash_seed`
+    ///
+    /// Computing the hash once per index allows fast repeated lookups in the path hash index.
+    pub fn path_hash(&self, path: &str, version: PakVersion) -> u64 {
+        Self::hash_path(path, self.path_hash_seed, version)
+    }
+
+    /// Pairs of `paths` whose hashes collide with `seed`, in `paths` order
+    ///
+    /// This is a dry run of the collision detection done by [`PakIndexV2::add`], a build tool can
+    /// use it to pick a collision free seed. Paths differing only by case always collide.
+    pub fn check_collisions(
+        paths: &[&str],
+        seed: u64,
+        version: PakVersion,
+    ) -> Vec<(String, String)> {
+        let mut hashes: HashMap<u64, Vec<&str>> = HashMap::new();
+        let mut collisions = Vec::new();
+        for path in paths {
+            let same_hash = hashes.entry(Self::hash_path(path, seed, version)).or_default();
+            for other in same_hash.iter() {
+                collisions.push((other.to_string(), path.to_string()));
+            }
+            same_hash.push(path);
+        }
+        collisions
+    }
+
+    /// `true` if `path` (relative to the mount point) has a record in this index
+    ///
+    /// This doesn't resolve the entry, so deleted records are reported as present.
+    pub fn contains(&self, path: &str, version: PakVersion) -> bool {
+        self.location(path, version).is_some()
+    }
+
+    /// Location of the record of `path` (relative to the mount point) in this index
+    pub(crate) fn location(&self, path: &str, version: PakVersion) -> Option<PakEntryLocation> {
+        if self.has_path_hash_index {
+            let location = self.path_hash_index.get(&self.path_hash(path, version));
+            location.map(|location| location.get())
+        } else {
+            self.directory_location(path)
+        }
+    }
+
+    /// Live entry whose path hash is `hash`, see [`PakIndexV2::path_hash`]
+    ///
+    /// This works without the full directory index (pruned builds).
+    pub fn find_by_hash(&self, hash: u64) -> Option<&PakEntry> {
+        self.path_hash_index.get(&hash).and_then(|location| self.entry(location.get()))
+    }
+
+    /// Live entry at `name` (relative to the mount point) found through the path hash index
+    ///
+    /// `name` is hashed like [`PakIndexV2::add`] does: lowercased (as UE `FString::ToLower`
+    /// does for ASCII paths) then hashed with this index seed and the FNV64 variant of `version`.
+    pub fn find_by_name(&self, name: &str, version: PakVersion) -> Option<&PakEntry> {
+        self.find_by_hash(self.path_hash(name, version))
+    }
+
+    /// Same as [`PakIndexV2::find`], ignoring case like UE does
+    ///
+    /// The path hash index is used if present, otherwise the full directory index is scanned.
+    pub fn find_ci(&self, path: &str, version: PakVersion) -> Option<&PakEntry> {
+        if self.has_path_hash_index {
+            return self.find_by_name(path, version);
+        }
+        let lpath = path.to_lowercase();
+        self.full_entries()
+            .find(|(dir, name, _)| join_path(dir, name).to_lowercase() == lpath)
+            .and_then(|(_, _, location)| self.entry(location))
+    }
+
+    /// Location of the record of `path` (relative to the mount point) in the full directory index
+    fn directory_location(&self, path: &str) -> Option<PakEntryLocation> {
+        let (dir, name) = path.rsplit_once('/')?;
+        self.full_directory_index
+            .get(dir)
+            .or_else(|| self.full_directory_index.get(&format!("{}/", dir)))
+            .and_then(|entries| entries.get(name))
+            .map(|location| location.get())
+    }
+
+    /// Live entry at `path` (relative to the mount point) in the full directory index
+    pub fn find(&self, path: &str) -> Option<&PakEntry> {
+        self.directory_location(path).and_then(|location| self.entry(location))
+    }
+
+    /// Errors [`PakIndexV2::add`] would report for `name`, without changing the index
+    pub(crate) fn check_add(&self, name: &str, version: PakVersion) -> io::Result<()> {
+        if self.has_path_hash_index {
+            let hash = self.path_hash(name, version);
+            if let Some(other) = self.path_hash_index.get(&hash) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "fnv64 hash collision for {:?} against {:?}",
+                        name.to_lowercase(),
+                        other.get()
+                    ),
+                ));
+            }
+        }
+        if self.has_full_directory_index && !name.contains('/') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("asset {:?} is not inside a directory", name),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Add `entry` at `name` (relative to the mount point)
+    ///
+    /// Path hash collisions and paths outside of a directory (when the full directory index is
+    /// enabled) are reported as `InvalidInput` errors, leaving the index unchanged. Without the
+    /// full directory index, only the entry directory is added to the pruned directory index.
+    /// Deleted entries are only stored as a deleted location.
     pub fn add(
         &mut self,
         name: String,
         entry: PakEntry,
         version: PakVersion,
     ) -> io::Result<PakEntryLocation> {
+        self.check_add(&name, version)?;
+        let hash = self.path_hash(&name, version);
+        let dir_name = name.rsplit_once('/');
+
+        self.num_entries += 1;
         let offset = self.encoded_pak_entries.len();
         let mut location = RawPakEntryLocation::from_offset(offset);
-        let cursor = io::Cursor::new(&mut self.encoded_pak_entries);
+        let mut cursor = io::Cursor::new(&mut self.encoded_pak_entries);
+        cursor.set_position(offset as u64);
         let mut ar = ArchiveWriter(cursor);
-        if Self::encode_entry(&mut ar, &entry, version)? {
+        if entry.is_deleted() {
+            location = RawPakEntryLocation::from_deleted();
+        } else if Self::encode_entry(&mut ar, &entry, version)? {
             self.decoded_pak_entries.insert(offset, entry);
         } else {
             location = RawPakEntryLocation::from_index(self.files.len());
@@ -182,42 +395,88 @@ impl PakIndexV2 {
         };
 
         if self.has_path_hash_index {
-            let lname = name.to_lowercase();
-            let hash = if version >= PakVersion::Fnv64BugFix {
-                fnv64(&lname, self.path_hash_seed)
-            } else {
-                legacy_fnv64(&lname, self.path_hash_seed)
-            };
-            if let Some(other) = self.path_hash_index.insert(hash, location) {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!(
-                        "fnv64 hash collision for {:?} at {:?} against {:?}",
-                        lname,
-                        location.get(),
-                        other.get()
-                    ),
-                ));
+            self.path_hash_index.insert(hash, location);
+        }
+        match dir_name {
+            Some((dir, name)) if self.has_full_directory_index => {
+                self.full_directory_index
+                    .entry(dir.to_owned())
+                    .or_default()
+                    .insert(name.to_owned(), location);
+            }
+            // without keep filters, UE prunes every file and keeps the directories
+            Some((dir, _)) => {
+                self.pruned_directory_index.entry(dir.to_owned()).or_default();
             }
+            None => {}
         }
+        Ok(location.get())
+    }
 
-        if self.has_full_directory_index {
-            match name.rsplit_once('/') {
-                Some((dir, name)) => {
-                    self.full_directory_index
-                        .entry(dir.to_owned())
-                        .or_default()
-                        .insert(name.to_owned(), location);
+    /// Serialized size of the records [`PakIndexV2::add`] would write for `entry` at `path`
+    ///
+    /// This is the entry record (compact encoded or in the files array), the path hash index
+    /// record and the directory index records, the directory being counted if `path` creates it.
+    pub(crate) fn add_len(&self, path: &str, entry: &PakEntry, version: PakVersion) -> u64 {
+        let mut len = Self::entry_record_len(entry, version);
+        if self.has_path_hash_index {
+            len += PATH_HASH_RECORD_LEN;
+        }
+        match path.rsplit_once('/') {
+            Some((dir, name)) if self.has_full_directory_index => {
+                len += name.to_owned().ser_len() + LOCATION_LEN;
+                if !self.full_directory_index.contains_key(dir) {
+                    len += dir.to_owned().ser_len() + DIRECTORY_COUNT_LEN;
                 }
-                None => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        format!("asset {:?} is not inside a directory", name),
-                    ));
+            }
+            Some((dir, _))
+                if self.has_path_hash_index && !self.pruned_directory_index.contains_key(dir) =>
+            {
+                len += dir.to_owned().ser_len() + DIRECTORY_COUNT_LEN;
+            }
+            _ => {}
+        }
+        len
+    }
+
+    /// Serialized size of the records of `path` a rebuilt index wouldn't have, `None` without
+    /// a record
+    ///
+    /// The full directory index directory is counted if `path` is its last file. The pruned
+    /// directory index doesn't tell which files a directory holds, its directories are kept.
+    pub(crate) fn remove_len(&self, path: &str, version: PakVersion) -> Option<u64> {
+        let location = self.location(path, version)?;
+        let mut len =
+            self.entry(location).map_or(0, |entry| Self::entry_record_len(entry, version));
+        if self.has_path_hash_index {
+            len += PATH_HASH_RECORD_LEN;
+        }
+        if let Some((dir, name)) = path.rsplit_once('/').filter(|_| self.has_full_directory_index) {
+            let directory = [dir.to_owned(), format!("{}/", dir)].iter().find_map(|dir| {
+                let entries = self.full_directory_index.get(dir)?;
+                entries.contains_key(name).then(|| (dir.clone(), entries.len()))
+            });
+            if let Some((dir, files)) = directory {
+                len += name.to_owned().ser_len() + LOCATION_LEN;
+                if files == 1 {
+                    len += dir.ser_len() + DIRECTORY_COUNT_LEN;
                 }
             }
         }
-        Ok(location.get())
+        Some(len)
+    }
+
+    /// Serialized size of `entry` in the primary index, deleted entries are only a location
+    fn entry_record_len(entry: &PakEntry, version: PakVersion) -> u64 {
+        if entry.is_deleted() {
+            return 0;
+        }
+        // entries that can't be compact encoded are stored in the files array
+        let mut ar = ArchiveLen::new();
+        match Self::encode_entry(&mut ar, entry, version) {
+            Ok(true) => ar.len(),
+            _ => entry.ser_len_with(version),
+        }
     }
 
     pub fn hashed_entries(&self) -> impl Iterator<Item = (u64, &PakEntry)> + '_ {
@@ -230,13 +489,76 @@ impl PakIndexV2 {
         })
     }
 
+    /// Stored entries, encoded entries in offset order followed by the non encodable ones
+    ///
+    /// Unlike [`PakIndexV2::hashed_entries`] and [`PakIndexV2::named_entries`], this doesn't depend
+    /// on which of the path hash index and the full directory index are present. Entries shared
+    /// by several paths are only listed once.
     pub fn entries(&self) -> impl Iterator<Item = &PakEntry> {
-        self.path_hash_index.values().flat_map(move |location| match location.get() {
-            PakEntryLocation::Deleted => None,
-            PakEntryLocation::Offset(i) => {
-                Some(self.decoded_pak_entries.get(&i).expect("a valid offset"))
+        self.entries_in_encoded_order().map(|(_, entry)| entry).chain(self.files.iter())
+    }
+
+    /// Entries inlined in the encoded entries buffer with their offset in it, in offset order
+    ///
+    /// This is the physical layout of the encoded buffer, entries stored in `files` are excluded.
+    pub fn entries_in_encoded_order(&self) -> impl Iterator<Item = (usize, &PakEntry)> {
+        let mut entries: Vec<_> = self.decoded_pak_entries.iter().map(|(i, e)| (*i, e)).collect();
+        entries.sort_by_key(|(offset, _)| *offset);
+        entries.into_iter()
+    }
+
+    /// Decode entries of the encoded entries buffer sequentially, in offset order
+    ///
+    /// Unlike [`PakIndexV2::entries_in_encoded_order`], this doesn't use the decoded entries map,
+    /// each entry is decoded with [`PakEntry::decode_compact`]. Iteration stops after an error.
+    pub fn iter_encoded(
+        &self,
+        version: PakVersion,
+    ) -> impl Iterator<Item = io::Result<PakEntry>> + '_ {
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            let bytes = self.encoded_pak_entries.get(offset..).filter(|b| !b.is_empty())?;
+            match PakEntry::decode_compact(bytes, version) {
+                Ok((entry, consumed)) => {
+                    offset += consumed;
+                    Some(Ok(entry))
+                }
+                Err(err) => {
+                    offset = self.encoded_pak_entries.len();
+                    Some(Err(err))
+                }
             }
-            PakEntryLocation::Index(i) => Some(&self.files[i]),
+        })
+    }
+
+    /// Directory index used by [`PakIndexV2::directories`] and [`PakIndexV2::entries_in`]
+    ///
+    /// The full directory index if present, the pruned directory index otherwise.
+    fn directory_index(&self) -> &BTreeMap<String, BTreeMap<String, RawPakEntryLocation>> {
+        if self.has_full_directory_index {
+            &self.full_directory_index
+        } else {
+            &self.pruned_directory_index
+        }
+    }
+
+    /// Directories of the full directory index, or of the pruned directory index if there is no
+    /// full directory index
+    pub fn directories(&self) -> impl Iterator<Item = &str> {
+        self.directory_index().keys().map(String::as_str)
+    }
+
+    /// Entry names and locations of `dir`, from the same index as [`PakIndexV2::directories`]
+    ///
+    /// `dir` is matched with or without its trailing slash.
+    pub fn entries_in(&self, dir: &str) -> impl Iterator<Item = (&str, PakEntryLocation)> {
+        let index = self.directory_index();
+        let entries = index.get(dir).or_else(|| match dir.strip_suffix('/') {
+            Some(dir) => index.get(dir),
+            None => index.get(&format!("{}/", dir)),
+        });
+        entries.into_iter().flat_map(|entries| {
+            entries.iter().map(|(name, location)| (name.as_str(), location.get()))
         })
     }
 
@@ -256,6 +578,39 @@ impl PakIndexV2 {
         })
     }
 
+    /// Number of entries as stored in the index header
+    pub fn num_entries(&self) -> usize {
+        self.num_entries as usize
+    }
+
+    /// Resolve `location` to its entry, `None` if the entry is deleted
+    pub fn entry(&self, location: PakEntryLocation) -> Option<&PakEntry> {
+        match location {
+            PakEntryLocation::Deleted => None,
+            PakEntryLocation::Offset(i) => self.decoded_pak_entries.get(&i),
+            PakEntryLocation::Index(i) => self.files.get(i),
+        }
+    }
+
+    /// Live entries of the full directory index with their path relative to the mount point
+    pub fn named_entries(&self) -> impl Iterator<Item = (String, &PakEntry)> {
+        self.full_entries().filter_map(move |(dir_name, entry_name, location)| {
+            self.entry(location).map(|entry| (join_path(dir_name, entry_name), entry))
+        })
+    }
+
+    /// Serialized size of each section of this index
+    pub fn section_sizes(&mut self, version: PakVersion) -> SectionSizes {
+        SectionSizes {
+            // u32 length followed by the raw bytes
+            encoded_entries: 4 + self.encoded_pak_entries.len() as u64,
+            files: self.files.ser_de_len_with(version),
+            path_hash_index: self.path_hash_index.ser_de_len(),
+            pruned_dir: self.pruned_directory_index.ser_de_len(),
+            full_dir: self.full_directory_index.ser_de_len(),
+        }
+    }
+
     pub fn ser<A: Archive>(&mut self, ar: &mut A, version: PakVersion) -> io::Result<()> {
         self.ser_de(ar, version, |_ar, _offset, _size, _hash, _ctx| {
             Err(io::Error::new(
@@ -265,47 +620,78 @@ impl PakIndexV2 {
         })
     }
 
-    pub fn de<A: Archive + io::Seek>(&mut self, ar: &mut A, version: PakVersion) -> io::Result<()> {
+    pub fn de<A: Archive>(&mut self, ar: &mut A, version: PakVersion) -> io::Result<()> {
         self.ser_de(ar, version, |ar, offset, _size, _hash, _ctx| {
             ar.seek(io::SeekFrom::Start(offset)).map(|_| ())?;
             Ok(())
         })
     }
 
-    pub fn ser_de<A, F>(&mut self, ar: &mut A, version: PakVersion, mut seek: F) -> io::Result<()>
-    where
-        A: Archive,
-        F: FnMut(&mut A, u64, u64, [u8; 20], &'static str) -> io::Result<()>,
-    {
-        self.mount_point.ser_de(ar)?;
-        self.num_entries.ser_de(ar)?;
-        self.path_hash_seed.ser_de(ar)?;
-        self.has_path_hash_index.ser_de(ar)?;
-        if self.has_path_hash_index {
-            self.path_hash_index_offset.ser_de(ar)?;
-            self.path_hash_index_size.ser_de(ar)?;
-            self.path_hash_index_hash.ser_de(ar)?;
+    /// Write this index followed by its path hash index and full directory index sections
+    ///
+    /// `index_offset` is the position of this index in the pak file, it is required to compute
+    /// the offsets of the secondary sections. The pruned directory index is stored in the path
+    /// hash index section. Each secondary section size and SHA1 is stored in the primary index,
+    /// the primary index size and SHA1 are returned for the pak footer.
+    pub fn write<A: Archive>(
+        &mut self,
+        ar: &mut A,
+        version: PakVersion,
+        index_offset: u64,
+    ) -> io::Result<(u64, [u8; 20])> {
+        fn section(
+            f: impl FnOnce(&mut ArchiveLenSha1<ArchiveWriter<io::Cursor<Vec<u8>>>>) -> io::Result<()>,
+        ) -> io::Result<(Vec<u8>, i64, [u8; 20])> {
+            let mut ar = ArchiveLenSha1::new(ArchiveWriter(io::Cursor::new(Vec::new())));
+            f(&mut ar)?;
+            let (size, hash) = ar.len_sha1();
+            Ok((ar.into_inner().0.into_inner(), size as i64, hash))
         }
 
-        self.has_full_directory_index.ser_de(ar)?;
+        let mut path_hash_section = Vec::new();
+        if self.has_path_hash_index {
+            let (bytes, size, hash) = section(|ar| {
+                self.path_hash_index.ser_de(ar)?;
+                self.pruned_directory_index.ser_de(ar)
+            })?;
+            path_hash_section = bytes;
+            self.path_hash_index_size = size;
+            self.path_hash_index_hash = hash;
+        }
+        let mut full_directory_section = Vec::new();
         if self.has_full_directory_index {
-            self.full_directory_index_offset.ser_de(ar)?;
-            self.full_directory_index_size.ser_de(ar)?;
-            self.full_directory_index_hash.ser_de(ar)?;
+            let (bytes, size, hash) = section(|ar| self.full_directory_index.ser_de(ar))?;
+            full_directory_section = bytes;
+            self.full_directory_index_size = size;
+            self.full_directory_index_hash = hash;
         }
 
-        let mut len = u32::try_from(self.encoded_pak_entries.len())
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
-        len.ser_de(ar)?;
-        if ar.is_reader() {
-            self.encoded_pak_entries.clear();
-            self.encoded_pak_entries.resize(len as usize, 0);
-            ar.read_exact(self.encoded_pak_entries.as_mut_slice())?;
+        // the primary index size doesn't depend on the sections offsets
+        let mut len_ar = ArchiveLen::new();
+        self.ser_de_primary(&mut len_ar, version)?;
+        let to_offset = |offset: u64| i64::try_from(offset).map_err(io::Error::other);
+        self.path_hash_index_offset = to_offset(index_offset + len_ar.len())?;
+        self.full_directory_index_offset = if self.has_full_directory_index {
+            to_offset(index_offset + len_ar.len() + path_hash_section.len() as u64)?
         } else {
-            ar.write_all(self.encoded_pak_entries.as_slice())?;
-        }
+            // INDEX_NONE, not serialized
+            -1
+        };
 
-        self.files.ser_de_with(ar, version)?;
+        let mut sha1_ar = ArchiveLenSha1::new(&mut *ar);
+        self.ser_de_primary(&mut sha1_ar, version)?;
+        let (size, hash) = sha1_ar.len_sha1();
+        ar.write_all(&path_hash_section)?;
+        ar.write_all(&full_directory_section)?;
+        Ok((size, hash))
+    }
+
+    pub fn ser_de<A, F>(&mut self, ar: &mut A, version: PakVersion, mut seek: F) -> io::Result<()>
+    where
+        A: Archive,
+        F: FnMut(&mut A, u64, u64, [u8; 20], &'static str) -> io::Result<()>,
+    {
+        self.ser_de_primary(ar, version)?;
 
         if self.has_path_hash_index && self.path_hash_index_offset != -1 {
             if self.path_hash_index_offset < 0 {
@@ -349,25 +735,18 @@ impl PakIndexV2 {
             self.full_directory_index.ser_de(ar)?;
         }
 
-        if self.has_path_hash_index && ar.is_reader() {
+        if ar.is_reader() {
+            // compact entries are stored in the pak byte order
+            let endian = ar.endian();
+            let full_directory_locations =
+                self.full_directory_index.values().flat_map(|entries| entries.values());
             self.decoded_pak_entries = self
                 .path_hash_index
                 .values()
+                .chain(full_directory_locations)
                 .filter_map(|location| match location.get() {
                     PakEntryLocation::Offset(offset) => {
-                        if offset > self.encoded_pak_entries.len() {
-                            return Some(Err(io::Error::new(
-                                io::ErrorKind::InvalidData,
-                                format!(
-                                    "pak entry location offset {} out of bounds: [0, {}]",
-                                    offset,
-                                    self.encoded_pak_entries.len(),
-                                ),
-                            )));
-                        }
-                        let cursor = io::Cursor::new(&self.encoded_pak_entries[offset..]);
-                        let mut ar = ArchiveReader(cursor);
-                        Some(Self::decode_entry(&mut ar, version).map(|entry| (offset, entry)))
+                        Some(self.decode_at(offset, version, endian).map(|entry| (offset, entry)))
                     }
                     _ => None,
                 })
@@ -376,9 +755,139 @@ impl PakIndexV2 {
         Ok(())
     }
 
+    /// Decode the entry at `offset` in the encoded entries buffer
+    fn decode_at(
+        &self,
+        offset: usize,
+        version: PakVersion,
+        endian: Endian,
+    ) -> io::Result<PakEntry> {
+        if offset > self.encoded_pak_entries.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "pak entry location offset {} out of bounds: [0, {}]",
+                    offset,
+                    self.encoded_pak_entries.len(),
+                ),
+            ));
+        }
+        let cursor = io::Cursor::new(&self.encoded_pak_entries[offset..]);
+        Self::decode_entry(&mut ByteOrder(ArchiveReader(cursor), endian), version)
+    }
+
+    /// Read the full directory index section from `ar` one entry at a time, calling `f` for each
+    /// live entry with its path relative to the mount point
+    ///
+    /// Unlike [`PakIndexV2::ser_de`], the directory index isn't kept: entries are resolved
+    /// against the primary index (see [`PakIndexV2::ser_de_primary`]) as they are read.
+    pub(crate) fn try_for_each_full_entry<A, F>(
+        &self,
+        ar: &mut A,
+        version: PakVersion,
+        mut f: F,
+    ) -> io::Result<()>
+    where
+        A: Archive,
+        F: FnMut(&str, &PakEntry) -> io::Result<()>,
+    {
+        let endian = ar.endian();
+        let dir_count = u32::de(ar)?;
+        for _ in 0..dir_count {
+            let dir_name = String::de(ar)?;
+            let file_count = u32::de(ar)?;
+            for _ in 0..file_count {
+                let file_name = String::de(ar)?;
+                let entry = match RawPakEntryLocation::de(ar)?.get() {
+                    PakEntryLocation::Deleted => continue,
+                    PakEntryLocation::Offset(offset) => self.decode_at(offset, version, endian)?,
+                    PakEntryLocation::Index(i) => self.files.get(i).cloned().ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("pak entry location index {} out of bounds", i),
+                        )
+                    })?,
+                };
+                f(&join_path(&dir_name, &file_name), &entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Name, offset and size of the secondary sections stored outside the primary index
+    pub(crate) fn secondary_sections(&self) -> Vec<(&'static str, u64, u64)> {
+        let mut sections = Vec::new();
+        if self.has_path_hash_index && self.path_hash_index_offset >= 0 {
+            let (offset, size) = (self.path_hash_index_offset, self.path_hash_index_size);
+            sections.push(("PathHashIndex", offset as u64, size as u64));
+        }
+        if self.has_full_directory_index && self.full_directory_index_offset >= 0 {
+            let (offset, size) = (self.full_directory_index_offset, self.full_directory_index_size);
+            sections.push(("FullDirectoryIndex", offset as u64, size as u64));
+        }
+        sections
+    }
+
+    /// Move the secondary sections by `delta` bytes, absent (`INDEX_NONE`) ones stay as is
+    pub(crate) fn move_secondary_sections(&mut self, delta: i64) {
+        for offset in [&mut self.path_hash_index_offset, &mut self.full_directory_index_offset] {
+            if *offset >= 0 {
+                *offset += delta;
+            }
+        }
+    }
+
+    /// Primary index, secondary sections are stored separately
+    pub(crate) fn ser_de_primary<A: Archive>(
+        &mut self,
+        ar: &mut A,
+        version: PakVersion,
+    ) -> io::Result<()> {
+        self.mount_point.ser_de(ar)?;
+        self.num_entries.ser_de(ar)?;
+        self.path_hash_seed.ser_de(ar)?;
+        self.has_path_hash_index.ser_de(ar)?;
+        if self.has_path_hash_index {
+            self.path_hash_index_offset.ser_de(ar)?;
+            self.path_hash_index_size.ser_de(ar)?;
+            self.path_hash_index_hash.ser_de(ar)?;
+        }
+
+        self.has_full_directory_index.ser_de(ar)?;
+        if self.has_full_directory_index {
+            self.full_directory_index_offset.ser_de(ar)?;
+            self.full_directory_index_size.ser_de(ar)?;
+            self.full_directory_index_hash.ser_de(ar)?;
+        }
+
+        let mut len = u32::try_from(self.encoded_pak_entries.len()).map_err(io::Error::other)?;
+        len.ser_de(ar)?;
+        if ar.is_reader() {
+            self.encoded_pak_entries.clear();
+            self.encoded_pak_entries.resize(len as usize, 0);
+            ar.read_exact(self.encoded_pak_entries.as_mut_slice())?;
+        } else {
+            ar.write_all(self.encoded_pak_entries.as_slice())?;
+        }
+
+        self.files.ser_de_with(ar, version)?;
+        Ok(())
+    }
+
+    /// Offset compression blocks are relative to
+    ///
+    /// Before `RelativeChunkOffsets`, compression blocks offsets are absolute file offsets.
+    fn chunk_base_offset(entry: &PakEntry, version: PakVersion) -> u64 {
+        if version < PakVersion::RelativeChunkOffsets {
+            entry.offset
+        } else {
+            0
+        }
+    }
+
     fn can_encode_entry(entry: &PakEntry, version: PakVersion) -> bool {
         let alignment = if entry.is_encrypted() { AES_BLOCK_SIZE } else { 1 };
-        let header_size = entry.ser_len_with(version);
+        let header_size = Self::chunk_base_offset(entry, version) + entry.ser_len_with(version);
 
         if entry.compression_method_index >= (1 << 6) {
             return false;
@@ -410,17 +919,15 @@ impl PakIndexV2 {
                 }
             }
             if entry.compression_blocks.len() > 1 {
-                let mut prev_size = entry.compression_blocks[0].compressed_end
-                    - entry.compression_blocks[0].compressed_start;
-                for compression_block in entry.compression_blocks[1..].iter() {
-                    prev_size = align(prev_size, alignment);
-                    if compression_block.compressed_start
-                        != compression_block.compressed_start + prev_size
-                    {
+                // blocks must be contiguous, each one padded to the alignment
+                let mut expected_start = entry.compression_blocks[0].compressed_start;
+                for compression_block in entry.compression_blocks.iter() {
+                    if compression_block.compressed_start != expected_start {
                         return false;
                     }
-                    prev_size =
+                    let block_size =
                         compression_block.compressed_end - compression_block.compressed_start;
+                    expected_start += align(block_size, alignment);
                 }
             }
         }
@@ -428,7 +935,7 @@ impl PakIndexV2 {
         true
     }
 
-    fn encode_entry<A: Archive>(
+    pub(crate) fn encode_entry<A: Archive>(
         ar: &mut A,
         entry: &PakEntry,
         version: PakVersion,
@@ -440,13 +947,13 @@ impl PakIndexV2 {
         let offset_u32 = u32::try_from(entry.offset).ok();
         let size_u32 = u32::try_from(entry.size).ok();
         let uncompressed_size_u32 = u32::try_from(entry.uncompressed_size).ok();
-        let flags = (u32::from(offset_u32.is_some()) * (1 << 31))
-            | (u32::from(uncompressed_size_u32.is_some()) * (1 << 30))
-            | (u32::from(size_u32.is_some()) * (1 << 29))
+        let flags = (u32::from(offset_u32.is_some()) << 31)
+            | (u32::from(uncompressed_size_u32.is_some()) << 30)
+            | (u32::from(size_u32.is_some()) << 29)
             | (entry.compression_method_index << 23)
-            | (u32::from(entry.is_encrypted()) * (1 << 22))
+            | (u32::from(entry.is_encrypted()) << 22)
             | ((entry.compression_blocks.len() as u32) << 6)
-            | (entry.compression_block_size >> 11);
+            | ((entry.compression_block_size >> 11) & 0x3f);
 
         flags.ser(ar)?;
         match offset_u32 {
@@ -468,7 +975,7 @@ impl PakIndexV2 {
                 for compression_block in &entry.compression_blocks {
                     let block_size =
                         compression_block.compressed_end - compression_block.compressed_start;
-                    block_size.ser(ar)?;
+                    u32::try_from(block_size).map_err(io::Error::other)?.ser(ar)?;
                 }
             }
         }
@@ -476,7 +983,32 @@ impl PakIndexV2 {
         Ok(true)
     }
 
-    fn decode_entry<A: Archive>(ar: &mut A, version: PakVersion) -> io::Result<PakEntry> {
+    pub(crate) fn decode_entry<A: Archive>(
+        ar: &mut A,
+        version: PakVersion,
+    ) -> io::Result<PakEntry> {
+        Self::decode_entry_impl(ar, version, None)
+    }
+
+    /// Decode a compact encoded entry knowing the pak compression block size
+    ///
+    /// The compact format stores the compression block size in 11 bits units, so a block size
+    /// that isn't a multiple of 2048 bytes can't be recovered. Tools knowing the cook
+    /// `CompressionBlockSize` can pass it as `block_size`, it is clamped to the entry
+    /// uncompressed size.
+    pub fn decode_entry_with_block_size<A: Archive>(
+        ar: &mut A,
+        version: PakVersion,
+        block_size: u32,
+    ) -> io::Result<PakEntry> {
+        Self::decode_entry_impl(ar, version, Some(block_size))
+    }
+
+    fn decode_entry_impl<A: Archive>(
+        ar: &mut A,
+        version: PakVersion,
+        block_size: Option<u32>,
+    ) -> io::Result<PakEntry> {
         let mut entry = PakEntry::default();
         let value = u32::de(ar)?;
         entry.compression_method_index = (value >> 23) & 0x3f;
@@ -512,33 +1044,333 @@ impl PakIndexV2 {
 
         let compression_blocks_len = ((value >> 6) & 0xffff) as usize;
         if compression_blocks_len > 0 {
-            entry.compression_block_size = if entry.uncompressed_size < 65536 {
-                entry.uncompressed_size as u32
-            } else {
-                (value & 0x3f) << 11
+            entry.compression_block_size = match block_size {
+                Some(block_size) => u64::from(block_size).min(entry.uncompressed_size) as u32,
+                None if entry.uncompressed_size < 65536 => entry.uncompressed_size as u32,
+                None => (value & 0x3f) << 11,
             };
         }
 
+        // the header size depends on the number of compression blocks
         entry.compression_blocks.clear();
+        entry.compression_blocks.resize(compression_blocks_len, PakCompressedBlock::default());
+        let header_size = Self::chunk_base_offset(&entry, version) + entry.ser_len_with(version);
         if compression_blocks_len == 1 && !entry.is_encrypted() {
-            let compressed_start = entry.ser_de_len_with(version);
-            entry.compression_blocks.push(PakCompressedBlock {
-                compressed_start,
-                compressed_end: (compressed_start + entry.size),
-            });
+            entry.compression_blocks[0] = PakCompressedBlock {
+                compressed_start: header_size,
+                compressed_end: (header_size + entry.size),
+            };
         } else if compression_blocks_len > 0 {
             let alignment = if entry.is_encrypted() { AES_BLOCK_SIZE } else { 1 };
-            let mut compressed_start = entry.ser_de_len_with(version);
-            for _ in 0..compression_blocks_len {
+            let mut compressed_start = header_size;
+            for compression_block in &mut entry.compression_blocks {
                 let block_size = u64::from(u32::de(ar)?);
                 let compressed_end = compressed_start + block_size;
-                entry
-                    .compression_blocks
-                    .push(PakCompressedBlock { compressed_start, compressed_end });
+                *compression_block = PakCompressedBlock { compressed_start, compressed_end };
                 compressed_start += align(block_size, alignment);
             }
         }
 
+        entry.validate_compression_blocks()?;
         Ok(entry)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn encode(entry: &PakEntry, version: PakVersion) -> Vec<u8> {
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        assert!(PakIndexV2::encode_entry(&mut ar, entry, version).expect("encode to work"));
+        ar.0.into_inner()
+    }
+
+    #[test]
+    fn add_deleted() {
+        let version = PakVersion::Fnv64BugFix;
+        let mut index = PakIndexV2 {
+            has_path_hash_index: true,
+            has_full_directory_index: true,
+            ..PakIndexV2::default()
+        };
+        let entry = PakEntry { flags: crate::pakentry::FLAG_DELETED, ..PakEntry::default() };
+        let location = index.add("Game/A.txt".to_owned(), entry, version).unwrap();
+        assert!(matches!(location, PakEntryLocation::Deleted));
+        assert!(index.contains("Game/A.txt", version));
+        assert_eq!(index.find("Game/A.txt"), None);
+        assert_eq!(index.num_entries(), 1);
+        assert!(index.encoded_pak_entries.is_empty() && index.files.is_empty());
+    }
+
+    #[test]
+    fn filename_seed() {
+        let seed = PakIndexV2::filename_seed("Content/Paks/pakchunk0-WindowsNoEditor.pak");
+        assert_eq!(seed, 0xe786_2a5e);
+        assert_eq!(PakIndexV2::filename_seed("C:\\Paks\\PAKCHUNK0-WindowsNoEditor.pak"), seed);
+    }
+
+    #[test]
+    fn find_by_name() {
+        let entry =
+            PakEntry { offset: 100, size: 10, uncompressed_size: 10, ..PakEntry::default() };
+        for version in [PakVersion::PathHashIndex, PakVersion::Fnv64BugFix] {
+            let mut index = PakIndexV2 {
+                path_hash_seed: 0x1234,
+                has_path_hash_index: true,
+                ..PakIndexV2::default()
+            };
+            index.add("Game/Content/A.uasset".to_owned(), entry.clone(), version).unwrap();
+            assert_eq!(index.find_by_name("game/content/a.uasset", version), Some(&entry));
+            assert_eq!(index.find_by_name("Game/Content/A.uasset", version), Some(&entry));
+            assert_eq!(index.find_by_name("Game/Content/B.uasset", version), None);
+            let hash = PakIndexV2::hash_path("game/content/a.uasset", 0x1234, version);
+            assert_eq!(index.find_by_hash(hash), Some(&entry));
+            assert_eq!(index.find("Game/Content/A.uasset"), None, "no full directory index");
+        }
+    }
+
+    #[test]
+    fn directories() {
+        let version = PakVersion::Fnv64BugFix;
+        let mut index = PakIndexV2 { has_full_directory_index: true, ..PakIndexV2::default() };
+        for name in ["a/x", "a/y", "a/b/z"] {
+            index.add(name.to_owned(), PakEntry::default(), version).unwrap();
+        }
+        assert_eq!(index.directories().collect::<Vec<_>>(), ["a", "a/b"]);
+        let names = |dir| index.entries_in(dir).map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names("a"), ["x", "y"]);
+        assert_eq!(names("a/b/"), ["z"]);
+        assert!(names("c").is_empty());
+
+        index.has_full_directory_index = false;
+        assert_eq!(index.directories().count(), 0, "the pruned directory index is empty");
+    }
+
+    #[test]
+    fn decode_entry_with_block_size() {
+        let version = PakVersion::Fnv64BugFix;
+        // 0x10400 isn't a multiple of 2048, the packed block size is lossy
+        let mut entry = PakEntry {
+            size: 0x100,
+            uncompressed_size: 0x10400,
+            compression_method_index: 1,
+            compression_block_size: 0x10400,
+            compression_blocks: vec![PakCompressedBlock::default()],
+            ..PakEntry::default()
+        };
+        let header_size = entry.ser_len_with(version);
+        entry.compression_blocks[0] = PakCompressedBlock {
+            compressed_start: header_size,
+            compressed_end: header_size + 0x100,
+        };
+        let encoded = encode(&entry, version);
+
+        let decoded = PakIndexV2::decode_entry(&mut ArchiveReader(Cursor::new(&encoded)), version);
+        assert_eq!(decoded.unwrap().compression_block_size, 0x10000);
+        let mut ar = ArchiveReader(Cursor::new(&encoded));
+        let decoded = PakIndexV2::decode_entry_with_block_size(&mut ar, version, 0x10400).unwrap();
+        assert_eq!(decoded, entry);
+        let mut ar = ArchiveReader(Cursor::new(&encoded));
+        let decoded = PakIndexV2::decode_entry_with_block_size(&mut ar, version, 0x20000).unwrap();
+        assert_eq!(decoded.compression_block_size, 0x10400);
+    }
+
+    #[test]
+    fn encode_size_flags() {
+        let entry = PakEntry {
+            offset: 0x1000,
+            size: 0x2000,
+            uncompressed_size: 1 << 33,
+            compression_method_index: 1,
+            compression_block_size: 0x10000,
+            ..PakEntry::default()
+        };
+        let encoded = encode(&entry, PakVersion::Fnv64BugFix);
+        let flags = u32::de(&mut ArchiveReader(Cursor::new(&encoded))).unwrap();
+        assert_eq!(flags & (1 << 31), 1 << 31, "offset fits in u32");
+        assert_eq!(flags & (1 << 30), 0, "uncompressed size doesn't fit in u32");
+        assert_eq!(flags & (1 << 29), 1 << 29, "size fits in u32");
+        assert_eq!(encoded.len(), 4 + 4 + 8 + 4);
+    }
+
+    #[test]
+    fn decode_compact() {
+        let version = PakVersion::Fnv64BugFix;
+        let entry = PakEntry {
+            offset: 0x1000,
+            size: 0x1234,
+            uncompressed_size: 0x1234,
+            ..PakEntry::default()
+        };
+        let mut encoded = entry.encode_compact(version).unwrap().expect("entry to be encodable");
+        let len = encoded.len();
+        encoded.extend_from_slice(&[0xff; 8]);
+
+        let (decoded, consumed) = PakEntry::decode_compact(&encoded, version).unwrap();
+        let internal =
+            PakIndexV2::decode_entry(&mut ArchiveReader(Cursor::new(&encoded)), version).unwrap();
+        assert_eq!(consumed, len);
+        assert_eq!(decoded, internal);
+        assert_eq!(decoded, entry);
+    }
+
+    #[test]
+    fn contains() {
+        let version = PakVersion::Fnv64BugFix;
+        let mut index = PakIndexV2 {
+            path_hash_seed: 0x1234,
+            has_path_hash_index: true,
+            has_full_directory_index: true,
+            ..PakIndexV2::default()
+        };
+        index.add("Game/Content/A.uasset".to_owned(), PakEntry::default(), version).unwrap();
+        assert!(index.contains("Game/Content/A.uasset", version));
+        assert!(index.contains("game/content/a.uasset", version));
+        assert!(!index.contains("Game/Content/B.uasset", version));
+
+        index.has_path_hash_index = false;
+        assert!(index.contains("Game/Content/A.uasset", version));
+        assert!(!index.contains("Game/A.uasset", version));
+    }
+
+    #[test]
+    fn num_entries() {
+        let version = PakVersion::Fnv64BugFix;
+        let mut index = PakIndexV2 { has_full_directory_index: true, ..PakIndexV2::default() };
+        assert_eq!(index.num_entries(), 0);
+        index.add("Game/A.uasset".to_owned(), PakEntry::default(), version).unwrap();
+        index.add("Game/B.uasset".to_owned(), PakEntry::default(), version).unwrap();
+        assert_eq!(index.num_entries(), 2);
+    }
+
+    #[test]
+    fn entries_in_encoded_order() {
+        let version = PakVersion::Fnv64BugFix;
+        let mut index = PakIndexV2 { has_full_directory_index: true, ..PakIndexV2::default() };
+        for (name, offset) in [("Game/b", 0x3000), ("Game/a", 0x1000), ("Game/c", 0x2000)] {
+            let entry = PakEntry { offset, ..PakEntry::default() };
+            index.add(name.to_owned(), entry, version).unwrap();
+        }
+        let entries: Vec<_> = index.entries_in_encoded_order().collect();
+        let offsets: Vec<_> = entries.iter().map(|(_, entry)| entry.offset).collect();
+        assert_eq!(offsets, [0x3000, 0x1000, 0x2000]);
+        assert!(entries.windows(2).all(|w| w[0].0 < w[1].0));
+        for (offset, entry) in entries {
+            let encoded = &index.encoded_pak_entries[offset..];
+            assert_eq!(&PakEntry::decode_compact(encoded, version).unwrap().0, entry);
+        }
+    }
+
+    #[test]
+    fn check_collisions() {
+        let version = PakVersion::Fnv64BugFix;
+        let paths = ["Game/A.uasset", "Game/B.uasset", "game/a.uasset"];
+        assert_eq!(
+            PakIndexV2::check_collisions(&paths, 0x1234, version),
+            [("Game/A.uasset".to_owned(), "game/a.uasset".to_owned())]
+        );
+        assert!(PakIndexV2::check_collisions(&paths[..2], 0x1234, version).is_empty());
+
+        let mut index = PakIndexV2 {
+            path_hash_seed: 0x1234,
+            has_path_hash_index: true,
+            ..PakIndexV2::default()
+        };
+        for (i, path) in paths.iter().enumerate() {
+            let res = index.add(path.to_string(), PakEntry::default(), version);
+            assert_eq!(res.is_err(), i == 2);
+        }
+    }
+
+    #[test]
+    fn iter_encoded() {
+        let version = PakVersion::Fnv64BugFix;
+        let mut index = PakIndexV2 { has_full_directory_index: true, ..PakIndexV2::default() };
+        for (name, offset) in [("Game/b", 0x3000), ("Game/a", 0x1000)] {
+            let entry = PakEntry { offset, ..PakEntry::default() };
+            index.add(name.to_owned(), entry, version).unwrap();
+        }
+        index.add("Game/c".to_owned(), compressed_entry(version), version).unwrap();
+
+        let decoded: Vec<_> = index.iter_encoded(version).collect::<io::Result<_>>().unwrap();
+        let expected: Vec<_> = index.entries_in_encoded_order().map(|(_, e)| e.clone()).collect();
+        assert_eq!(decoded, expected);
+
+        index.encoded_pak_entries.truncate(index.encoded_pak_entries.len() - 1);
+        let results: Vec<_> = index.iter_encoded(version).collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[2].is_err());
+    }
+
+    fn compressed_entry(version: PakVersion) -> PakEntry {
+        let mut entry = PakEntry {
+            offset: 0x1000,
+            size: 0x800,
+            uncompressed_size: 0x1000,
+            compression_method_index: 1,
+            compression_block_size: 0x1000,
+            compression_blocks: vec![PakCompressedBlock::default()],
+            ..PakEntry::default()
+        };
+        let header_size = entry.ser_len_with(version);
+        let compressed_start = if version < PakVersion::RelativeChunkOffsets {
+            entry.offset + header_size
+        } else {
+            header_size
+        };
+        entry.compression_blocks[0] =
+            PakCompressedBlock { compressed_start, compressed_end: compressed_start + entry.size };
+        entry
+    }
+
+    #[test]
+    fn encode_multi_block() {
+        let version = PakVersion::Fnv64BugFix;
+        for flags in [0, crate::pakentry::FLAG_ENCRYPTED] {
+            let alignment = if flags == 0 { 1 } else { AES_BLOCK_SIZE };
+            let mut entry = PakEntry {
+                offset: 0x1000,
+                uncompressed_size: 0x28000,
+                compression_method_index: 1,
+                compression_block_size: 0x10000,
+                compression_blocks: vec![PakCompressedBlock::default(); 3],
+                flags,
+                ..PakEntry::default()
+            };
+            let mut start = entry.ser_len_with(version);
+            for (block, size) in entry.compression_blocks.iter_mut().zip([0x301, 0x2F5, 0x10]) {
+                *block =
+                    PakCompressedBlock { compressed_start: start, compressed_end: start + size };
+                start += align(size, alignment);
+            }
+            entry.size = start - entry.ser_len_with(version);
+            let encoded = entry.encode_compact(version).unwrap().expect("entry to be encodable");
+            let (decoded, _) = PakEntry::decode_compact(&encoded, version).unwrap();
+            assert_eq!(decoded, entry);
+
+            // a gap between blocks can't be encoded
+            entry.compression_blocks[2].compressed_start += 16;
+            entry.compression_blocks[2].compressed_end += 16;
+            assert_eq!(entry.encode_compact(version).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn decode_chunk_offsets() {
+        for &version in &[PakVersion::IndexEncryption, PakVersion::RelativeChunkOffsets] {
+            let entry = compressed_entry(version);
+            let encoded = entry.encode_compact(version).unwrap().expect("entry to be encodable");
+            let (decoded, _) = PakEntry::decode_compact(&encoded, version).unwrap();
+            assert_eq!(decoded, entry, "{:?}", version);
+        }
+        assert_eq!(
+            compressed_entry(PakVersion::IndexEncryption).compression_blocks[0].compressed_start,
+            0x1000
+                + compressed_entry(PakVersion::RelativeChunkOffsets).compression_blocks[0]
+                    .compressed_start
+        );
+    }
+}
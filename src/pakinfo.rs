@@ -1,13 +1,14 @@
-use std::io;
+use std::{error, fmt, io};
 
 use crate::{
-    archive::{Archivable, Archive},
+    archive::{Archivable, Archive, Endian},
     constants::*,
-    PakVersion,
+    Guid, PakVersion,
 };
 
 /// FPakInfo archivable
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PakInfo {
     /// Pak file magic value.
     pub magic: u32,
@@ -18,15 +19,18 @@ pub struct PakInfo {
     /// Size (in bytes) of pak file index.
     pub index_size: u64,
     /// Index SHA1 value.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serdehex"))]
     pub index_hash: [u8; 20],
     /// Flag indicating if the pak index has been encrypted.
     pub encrypted_index: bool,
     /// Flag indicating if the pak index has been frozen
     pub index_is_frozen: bool,
-    /// Encryption key guid. Empty if we should use the embedded key.
-    pub encryption_key_guid: [u32; 4],
+    /// Encryption key guid. Zero if we should use the embedded key.
+    pub encryption_key_guid: Guid,
     /// Compression methods used in this pak file (i.e. "Zlib", "Gzip", "LZ4", "Oodle", ...)
     pub compression_methods: Vec<String>,
+    /// Byte order of the pak file, set to the archive byte order when reading.
+    pub endian: Endian,
 }
 
 impl Default for PakInfo {
@@ -39,8 +43,9 @@ impl Default for PakInfo {
             index_hash: [0; 20],
             encrypted_index: false,
             index_is_frozen: false,
-            encryption_key_guid: [0; 4],
+            encryption_key_guid: Guid::ZERO,
             compression_methods: vec![String::new()],
+            endian: Endian::Little,
         }
     }
 }
@@ -49,11 +54,173 @@ impl PakInfo {
     pub fn new(version: PakVersion) -> Self {
         Self { version, ..Default::default() }
     }
+
+    /// Append compression methods of `other` that are missing from this pak
+    ///
+    /// Returns a table that maps `other` compression method indexes to the merged indexes.
+    /// Method names are compared case insensitively and the empty name is the "no compression"
+    /// method at index 0. Nothing is changed if the merged list exceeds the version limit.
+    pub fn merge_methods(&mut self, other: &[String]) -> Result<Vec<u32>, PakInfoError> {
+        let mut merged = self.compression_methods.clone();
+        let mut remap = Vec::with_capacity(other.len());
+        for name in other {
+            let idx = if name.is_empty() {
+                0
+            } else if let Some(idx) = merged.iter().position(|m| m.eq_ignore_ascii_case(name)) {
+                idx
+            } else {
+                merged.push(name.clone());
+                merged.len() - 1
+            };
+            remap.push(idx as u32);
+        }
+
+        let count = merged.len() - 1;
+        let max = self.max_compression_methods();
+        if count > max {
+            return Err(PakInfoError::TooManyCompressionMethods { count, max });
+        }
+        self.compression_methods = merged;
+        Ok(remap)
+    }
+
+    /// Index of the compression method named `name`, registering it if missing
+    ///
+    /// Index 0 is the "no compression" method, returned for the empty name. Names are compared
+    /// case insensitively and registering fails if the version limit is reached.
+    pub fn compression_method_index(&mut self, name: &str) -> io::Result<u32> {
+        let remap = self.merge_methods(&[name.to_owned()])?;
+        Ok(remap[0])
+    }
+
+    /// Create a builder that validates fields consistency
+    pub fn builder() -> PakInfoBuilder {
+        PakInfoBuilder { info: PakInfo::default() }
+    }
+
+    /// Whether the pak is encrypted with the key registered under `encryption_key_guid` instead
+    /// of the embedded key (i.e. a secondary key of crypto.json)
+    pub fn requires_named_key(&self) -> bool {
+        !self.encryption_key_guid.is_zero()
+    }
+
+    /// Maximum number of named compression methods this version can store
+    pub fn max_compression_methods(&self) -> usize {
+        match self.version {
+            PakVersion::FNameBasedCompressionMethod422 => MAX_NUM_COMPRESSION_METHODS - 1,
+            _ => MAX_NUM_COMPRESSION_METHODS,
+        }
+    }
+}
+
+/// Inconsistency found by [`PakInfoBuilder::build`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PakInfoError {
+    /// Index encryption requires `PakVersion::IndexEncryption` or later
+    EncryptedIndexUnsupported(PakVersion),
+    /// Encryption key guid requires `PakVersion::EncryptionKeyGuid` or later
+    EncryptionKeyGuidUnsupported(PakVersion),
+    /// Frozen index is only stored by `PakVersion::FrozenIndex`
+    FrozenIndexUnsupported(PakVersion),
+    /// More compression methods than the version can store
+    TooManyCompressionMethods { count: usize, max: usize },
+}
+
+impl fmt::Display for PakInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PakInfoError::EncryptedIndexUnsupported(version) => {
+                write!(f, "index encryption is not supported by version {}", version)
+            }
+            PakInfoError::EncryptionKeyGuidUnsupported(version) => {
+                write!(f, "encryption key guid is not supported by version {}", version)
+            }
+            PakInfoError::FrozenIndexUnsupported(version) => {
+                write!(f, "frozen index is not supported by version {}", version)
+            }
+            PakInfoError::TooManyCompressionMethods { count, max } => {
+                write!(f, "{} compression methods, at most {} are supported", count, max)
+            }
+        }
+    }
+}
+
+impl error::Error for PakInfoError {}
+
+impl From<PakInfoError> for io::Error {
+    fn from(err: PakInfoError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+/// [`PakInfo`] builder, see [`PakInfo::builder`]
+#[derive(Debug, Clone)]
+pub struct PakInfoBuilder {
+    info: PakInfo,
+}
+
+impl PakInfoBuilder {
+    pub fn version(mut self, version: PakVersion) -> Self {
+        self.info.version = version;
+        self
+    }
+
+    pub fn index(mut self, offset: u64, size: u64, hash: [u8; 20]) -> Self {
+        self.info.index_offset = offset;
+        self.info.index_size = size;
+        self.info.index_hash = hash;
+        self
+    }
+
+    pub fn encrypted_index(mut self, encrypted_index: bool) -> Self {
+        self.info.encrypted_index = encrypted_index;
+        self
+    }
+
+    pub fn index_is_frozen(mut self, index_is_frozen: bool) -> Self {
+        self.info.index_is_frozen = index_is_frozen;
+        self
+    }
+
+    pub fn encryption_key_guid(mut self, encryption_key_guid: Guid) -> Self {
+        self.info.encryption_key_guid = encryption_key_guid;
+        self
+    }
+
+    /// Append a compression method (i.e. "Zlib", "Oodle", ...)
+    pub fn compression_method(mut self, name: &str) -> Self {
+        self.info.compression_methods.push(name.to_string());
+        self
+    }
+
+    /// Validate fields consistency and build the [`PakInfo`]
+    pub fn build(self) -> Result<PakInfo, PakInfoError> {
+        let info = self.info;
+        if info.encrypted_index && info.version < PakVersion::IndexEncryption {
+            return Err(PakInfoError::EncryptedIndexUnsupported(info.version));
+        }
+        if !info.encryption_key_guid.is_zero() && info.version < PakVersion::EncryptionKeyGuid {
+            return Err(PakInfoError::EncryptionKeyGuidUnsupported(info.version));
+        }
+        if info.index_is_frozen && info.version != PakVersion::FrozenIndex {
+            return Err(PakInfoError::FrozenIndexUnsupported(info.version));
+        }
+        // the first compression method is the implicit "no compression" one
+        let count = info.compression_methods.len() - 1;
+        let max = info.max_compression_methods();
+        if count > max {
+            return Err(PakInfoError::TooManyCompressionMethods { count, max });
+        }
+        Ok(info)
+    }
 }
 
 impl Archivable for PakInfo {
     fn ser_de<A: Archive>(&mut self, ar: &mut A) -> io::Result<()> {
         let version = self.version;
+        if ar.is_reader() {
+            self.endian = ar.endian();
+        }
         if version >= PakVersion::EncryptionKeyGuid {
             self.encryption_key_guid.ser_de(ar)?;
         }
@@ -62,11 +229,13 @@ impl Archivable for PakInfo {
         encrypted_index_u8.ser_de(ar)?;
         self.encrypted_index = encrypted_index_u8 != 0;
 
+        // when reading, `magic` is the expected magic value (`PAK_FILE_MAGIC` by default)
+        let expected_magic = self.magic;
         self.magic.ser_de(ar)?;
-        if self.magic != PAK_FILE_MAGIC {
+        if self.magic != expected_magic {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                format!("magic {:x} is not {:x}", self.magic, PAK_FILE_MAGIC),
+                format!("magic {:x} is not {:x}", self.magic, expected_magic),
             ));
         }
 
@@ -89,7 +258,7 @@ impl Archivable for PakInfo {
             }
 
             if self.version < PakVersion::EncryptionKeyGuid {
-                self.encryption_key_guid = Default::default(); // [0, 0, 0, 0]
+                self.encryption_key_guid = Guid::ZERO;
             }
         }
 
@@ -100,9 +269,11 @@ impl Archivable for PakInfo {
         }
 
         if self.version < PakVersion::FNameBasedCompressionMethod422 {
-            self.compression_methods.push("Zlib".to_string());
-            self.compression_methods.push("Gzip".to_string());
-            self.compression_methods.push("Oodle".to_string());
+            if ar.is_reader() {
+                self.compression_methods.push("Zlib".to_string());
+                self.compression_methods.push("Gzip".to_string());
+                self.compression_methods.push("Oodle".to_string());
+            }
         } else {
             const LEN: usize = COMPRESSION_METHOD_NAME_LEN * MAX_NUM_COMPRESSION_METHODS;
             let mut buffer = &mut [0u8; LEN][..];
@@ -118,15 +289,37 @@ impl Archivable for PakInfo {
                     i += COMPRESSION_METHOD_NAME_LEN;
                     let cstr = &buffer[pos..i];
                     let compression_method = std::str::from_utf8(cstr)
-                        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?
+                        .map_err(io::Error::other)?
                         .trim_end_matches('\0')
                         .to_string();
-                    self.compression_methods.push(compression_method);
+                    // unused slots are empty
+                    if !compression_method.is_empty() {
+                        self.compression_methods.push(compression_method);
+                    }
                 }
             } else {
-                for (i, compression_method) in self.compression_methods[1..].iter().enumerate() {
+                let methods = &self.compression_methods[1..];
+                if methods.len() > buffer.len() / COMPRESSION_METHOD_NAME_LEN {
+                    return Err(PakInfoError::TooManyCompressionMethods {
+                        count: methods.len(),
+                        max: buffer.len() / COMPRESSION_METHOD_NAME_LEN,
+                    }
+                    .into());
+                }
+                for (i, compression_method) in methods.iter().enumerate() {
+                    let name = compression_method.as_bytes();
+                    if name.len() > COMPRESSION_METHOD_NAME_LEN {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "compression method name {:?} is longer than {} bytes",
+                                compression_method, COMPRESSION_METHOD_NAME_LEN
+                            ),
+                        ));
+                    }
+                    // names are null padded
                     let pos = i * COMPRESSION_METHOD_NAME_LEN;
-                    buffer[pos..].copy_from_slice(compression_method.as_bytes());
+                    buffer[pos..pos + name.len()].copy_from_slice(name);
                 }
                 buffer.ser_de(ar)?;
             }
@@ -141,7 +334,7 @@ mod tests {
     use std::io::Cursor;
 
     use super::*;
-    use crate::archive::{ArchiveReader, ArchiveWriter};
+    use crate::archive::{ArchiveReader, ArchiveWriter, ByteOrder};
 
     fn read(version: PakVersion, ro: &[u8]) -> PakInfo {
         let mut pakinfo = PakInfo::new(version);
@@ -167,4 +360,135 @@ mod tests {
         assert_eq!(pakinfo.version, PakVersion::EncryptionKeyGuid);
         assert_eq!(write(pakinfo, ro.len()), ro);
     }
+
+    #[test]
+    fn v8_roundtrip() {
+        for version in [PakVersion::FNameBasedCompressionMethod422, PakVersion::Fnv64BugFix] {
+            let mut pakinfo = PakInfo::new(version);
+            pakinfo.index_offset = 0x1234;
+            pakinfo.index_size = 0x56;
+            pakinfo.encryption_key_guid = Guid([1, 2, 3, 4]);
+            let len = pakinfo.ser_de_len() as usize;
+            let bytes = write(pakinfo, len);
+            let mut pakinfo = read(version, &bytes);
+            assert_eq!((pakinfo.index_offset, pakinfo.index_size), (0x1234, 0x56));
+            assert_eq!(pakinfo.encryption_key_guid, Guid([1, 2, 3, 4]));
+            assert!(pakinfo.requires_named_key());
+            assert_eq!(pakinfo.compression_methods, [""]);
+            assert_eq!(pakinfo.ser_de_len(), len as u64);
+            assert_eq!(write(pakinfo, len), bytes);
+        }
+    }
+
+    #[test]
+    fn big_endian() {
+        let version = PakVersion::Fnv64BugFix;
+        let mut pakinfo = PakInfo::new(version);
+        pakinfo.index_offset = 0x1234;
+        pakinfo.index_size = 0x56;
+        let len = pakinfo.ser_de_len() as usize;
+        let le = write(pakinfo.clone(), len);
+        let mut be = vec![0u8; len];
+        pakinfo
+            .ser_de(&mut ByteOrder(ArchiveWriter(Cursor::new(&mut be[..])), Endian::Big))
+            .expect("serialize to work");
+        assert_ne!(le, be);
+
+        for (bytes, endian) in [(&le, Endian::Little), (&be, Endian::Big)] {
+            let mut pakinfo = PakInfo::new(version);
+            let mut ar = ByteOrder(ArchiveReader(Cursor::new(&bytes[..])), endian);
+            pakinfo.ser_de(&mut ar).expect("deserialize to work");
+            assert_eq!(pakinfo.endian, endian);
+            assert_eq!((pakinfo.index_offset, pakinfo.index_size), (0x1234, 0x56));
+
+            // the magic doesn't match in the other byte order
+            let other = if endian == Endian::Big { Endian::Little } else { Endian::Big };
+            let mut ar = ByteOrder(ArchiveReader(Cursor::new(&bytes[..])), other);
+            let err = PakInfo::new(version).ser_de(&mut ar).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn compression_methods_roundtrip() {
+        let version = PakVersion::Fnv64BugFix;
+        let mut pakinfo = PakInfo::new(version);
+        for name in &["Zlib", "Oodle", "LZ4"] {
+            pakinfo.compression_method_index(name).unwrap();
+        }
+        let len = pakinfo.ser_de_len() as usize;
+        let bytes = write(pakinfo.clone(), len);
+        let names = &bytes[len - COMPRESSION_METHOD_NAME_LEN * MAX_NUM_COMPRESSION_METHODS..];
+        assert_eq!(&names[..5], b"Zlib\0");
+        assert!(names[4..COMPRESSION_METHOD_NAME_LEN].iter().all(|b| *b == 0));
+        assert_eq!(&names[COMPRESSION_METHOD_NAME_LEN..][..6], b"Oodle\0");
+        assert!(names[3 * COMPRESSION_METHOD_NAME_LEN..].iter().all(|b| *b == 0));
+        assert_eq!(read(version, &bytes).compression_methods, pakinfo.compression_methods);
+
+        pakinfo.compression_methods.push("X".repeat(COMPRESSION_METHOD_NAME_LEN + 1));
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        assert!(pakinfo.ser_de(&mut ar).is_err());
+
+        // legacy versions have implicit methods, which are not written
+        let mut pakinfo = PakInfo::new(PakVersion::DeleteRecords);
+        let len = pakinfo.ser_de_len() as usize;
+        assert_eq!(pakinfo.compression_methods, [""]);
+        let bytes = write(pakinfo, len);
+        let pakinfo = read(PakVersion::DeleteRecords, &bytes);
+        assert_eq!(pakinfo.compression_methods, ["", "Zlib", "Gzip", "Oodle"]);
+    }
+
+    #[test]
+    fn merge_methods() {
+        let methods = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let mut pakinfo = PakInfo::new(PakVersion::Fnv64BugFix);
+        pakinfo.compression_methods = methods(&["", "Zlib", "Oodle"]);
+        let remap = pakinfo.merge_methods(&methods(&["", "Oodle", "LZ4", "zlib"])).unwrap();
+        assert_eq!(remap, [0, 2, 3, 1]);
+        assert_eq!(pakinfo.compression_methods, methods(&["", "Zlib", "Oodle", "LZ4"]));
+
+        let err = pakinfo.merge_methods(&methods(&["", "Gzip", "Custom", "Other"])).unwrap_err();
+        assert_eq!(err, PakInfoError::TooManyCompressionMethods { count: 6, max: 5 });
+        assert_eq!(pakinfo.compression_methods.len(), 4);
+    }
+
+    #[test]
+    fn compression_method_index() {
+        let mut pakinfo = PakInfo::new(PakVersion::FNameBasedCompressionMethod422);
+        assert_eq!(pakinfo.compression_method_index("").unwrap(), 0);
+        assert_eq!(pakinfo.compression_method_index("Zlib").unwrap(), 1);
+        assert_eq!(pakinfo.compression_method_index("Oodle").unwrap(), 2);
+        assert_eq!(pakinfo.compression_method_index("zlib").unwrap(), 1);
+        for name in &["Gzip", "LZ4"] {
+            pakinfo.compression_method_index(name).unwrap();
+        }
+        let err = pakinfo.compression_method_index("Custom").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(pakinfo.compression_methods.len(), 5);
+    }
+
+    #[test]
+    fn builder() {
+        let err = PakInfo::builder()
+            .version(PakVersion::CompressionEncryption)
+            .encrypted_index(true)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, PakInfoError::EncryptedIndexUnsupported(PakVersion::CompressionEncryption));
+
+        let err = PakInfo::builder()
+            .version(PakVersion::PathHashIndex)
+            .index_is_frozen(true)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, PakInfoError::FrozenIndexUnsupported(PakVersion::PathHashIndex));
+
+        let mut builder = PakInfo::builder().version(PakVersion::FNameBasedCompressionMethod422);
+        for name in &["Zlib", "Gzip", "Oodle", "LZ4"] {
+            builder = builder.compression_method(name);
+        }
+        assert!(builder.clone().build().is_ok());
+        let err = builder.compression_method("Custom").build().unwrap_err();
+        assert_eq!(err, PakInfoError::TooManyCompressionMethods { count: 5, max: 4 });
+    }
 }
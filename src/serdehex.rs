@@ -0,0 +1,26 @@
+use std::fmt::Write;
+
+use serde::{de, Deserialize, Deserializer, Serializer};
+
+/// Serialize a SHA1 hash as an uppercase hex string, see `#[serde(with = "crate::serdehex")]`
+pub fn serialize<S: Serializer>(hash: &[u8; 20], serializer: S) -> Result<S::Ok, S::Error> {
+    let mut hex = String::with_capacity(40);
+    for byte in hash {
+        write!(hex, "{:02X}", byte).expect("writing to a String never fails");
+    }
+    serializer.serialize_str(&hex)
+}
+
+/// Deserialize a SHA1 hash from a hex string
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 20], D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    let mut hash = [0u8; 20];
+    if hex.len() != 40 || !hex.is_ascii() {
+        return Err(de::Error::invalid_length(hex.len(), &"40 hex digits"));
+    }
+    for (byte, digits) in hash.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        let digits = std::str::from_utf8(digits).expect("ASCII digits");
+        *byte = u8::from_str_radix(digits, 16).map_err(de::Error::custom)?;
+    }
+    Ok(hash)
+}
@@ -0,0 +1,28 @@
+use std::io::{Cursor, Write};
+
+use crate::archive::{ArchiveReader, ArchiveSeekWriter};
+use crate::{PakError, PakFile, PakFileBuilder, PakVersion};
+
+/// Build a complete pak file in memory containing `entries` (name, content)
+///
+/// Panics if the pak file can't be built.
+pub fn build_pak_in_memory(version: PakVersion, entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut ar = ArchiveSeekWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(version);
+    for (name, content) in entries {
+        let mut writer = builder.add(&mut ar, name.to_string());
+        writer.write_all(content).expect("write entry");
+        writer.finalize().expect("finalize entry");
+    }
+    builder.finalize(&mut ar).expect("finalize pak file");
+    ar.0.into_inner()
+}
+
+/// Load a pak file from memory, trying every known version
+pub fn load_pak_from_memory(bytes: &[u8], key: Option<&str>) -> Result<PakFile, PakError> {
+    let mut ar = ArchiveReader(Cursor::new(bytes));
+    match key {
+        Some(key) => PakFile::load_any_with_key(&mut ar, key),
+        None => PakFile::load_any(&mut ar),
+    }
+}
@@ -0,0 +1,1472 @@
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use aes::Aes256;
+use block_modes::block_padding::NoPadding;
+use block_modes::{BlockMode, Ecb};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+use ue4pak::archive::{
+    Archivable, ArchivableWith, Archive, ArchiveReader, ArchiveSeekWriter, ArchiveWriter,
+    ByteOrder, Endian,
+};
+use ue4pak::constants::PAK_FILE_MAGIC;
+use ue4pak::testutil::{build_pak_in_memory, load_pak_from_memory};
+use ue4pak::{
+    EncryptionStatus, ExtractSummary, Guid, KeyRegistry, PakEntry, PakError, PakFile,
+    PakFileBuilder, PakIndex, PakIndexV2, PakInfo, PakVersion, RepackIssue,
+};
+
+const ENTRIES: &[(&str, &[u8])] =
+    &[("Game/Content/a.txt", b"hello"), ("Game/Content/Sub/b.bin", &[0, 1, 2, 3, 4, 5, 6, 7])];
+
+#[test]
+fn load_any_version_8() {
+    let content: Vec<u8> = (0..3000u32).map(|i| (i % 7) as u8).collect();
+    let versions =
+        [PakVersion::FNameBasedCompressionMethod422, PakVersion::FNameBasedCompressionMethod];
+    for version in versions {
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        let mut builder = PakFileBuilder::new(version);
+        let mut writer =
+            builder.add_compressed(&mut ar, "Game/a.bin".to_owned(), "Zlib", 1024).unwrap();
+        writer.write_all(&content).unwrap();
+        writer.finalize().unwrap();
+        builder.finalize(&mut ar).unwrap();
+        let bytes = ar.0.into_inner();
+
+        let pak = load_pak_from_memory(&bytes, None).unwrap();
+        assert_eq!(pak.info().version, version);
+        assert_eq!(pak.info().compression_methods, ["", "Zlib"]);
+        let entry = pak.index().find("Game/a.bin").unwrap();
+        let mut ar = ArchiveReader(Cursor::new(&bytes));
+        assert_eq!(pak.read_entry_verified(&mut ar, entry).unwrap(), content);
+    }
+}
+
+#[test]
+fn load_big_endian() {
+    let content: Vec<u8> = (0..3000u32).map(|i| (i % 7) as u8).collect();
+    let mut ar = ByteOrder(ArchiveWriter(Cursor::new(Vec::new())), Endian::Big);
+    let mut builder = PakFileBuilder::new(PakVersion::RelativeChunkOffsets);
+    let mut writer = builder.add(&mut ar, "Game/a.bin".to_owned());
+    writer.write_all(&content).unwrap();
+    writer.finalize().unwrap();
+    builder.finalize(&mut ar).unwrap();
+    let bytes = ar.0 .0.into_inner();
+
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    assert_eq!(pak.info().endian, Endian::Big);
+    assert_eq!(pak.info().version, PakVersion::RelativeChunkOffsets);
+    let entry = pak.index().find("Game/a.bin").unwrap();
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    assert_eq!(pak.read_entry_verified(&mut ar, entry).unwrap(), content);
+}
+
+#[test]
+fn version_from_raw() {
+    for &version in PakVersion::list() {
+        let decoded = PakVersion::from_raw(version.raw()).unwrap();
+        assert_eq!(decoded.raw(), version.raw());
+    }
+    assert_eq!(PakVersion::from_raw(8), Some(PakVersion::FNameBasedCompressionMethod));
+    assert_eq!(PakVersion::from_raw(11), Some(PakVersion::Fnv64BugFix));
+    assert_eq!(PakVersion::from_raw(0), None);
+    assert_eq!(PakVersion::from_raw(12), None);
+}
+
+#[test]
+fn roundtrip() {
+    for &version in PakVersion::list() {
+        if version == PakVersion::FrozenIndex {
+            continue;
+        }
+        let bytes = build_pak_in_memory(version, ENTRIES);
+        let pak = load_pak_from_memory(&bytes, None).expect("pak file to load");
+        assert_eq!(pak.info().version, version);
+        let entries = pak.index().named_entries();
+        assert_eq!(entries.len(), ENTRIES.len(), "{:?}", version);
+        for ((name, entry), (expected_name, content)) in entries.iter().zip(ENTRIES) {
+            assert_eq!(name, expected_name, "{:?}", version);
+            assert_eq!(entry.size, content.len() as u64, "{:?} {}", version, name);
+            assert!(pak.index().contains(name, version), "{:?} {}", version, name);
+        }
+    }
+}
+
+#[test]
+fn roundtrip_v2_sections() {
+    let version = PakVersion::Fnv64BugFix;
+    let bytes = build_pak_in_memory(version, ENTRIES);
+    let pak = load_pak_from_memory(&bytes, None).expect("pak file to load");
+    let index = match pak.index() {
+        PakIndex::V2(index) => index,
+        PakIndex::V1(_) => panic!("expecting a V2 index"),
+    };
+    assert!(index.has_path_hash_index);
+    assert!(index.has_full_directory_index);
+    assert_eq!(index.hashed_entries().count(), ENTRIES.len());
+    assert_eq!(index.full_entries().count(), ENTRIES.len());
+    assert_eq!(index.num_entries(), ENTRIES.len());
+    // the path hash index and full directory index sections are stored after the primary index
+    let footer_size = PakInfo::new(version).ser_de_len();
+    assert!(pak.info().index_offset + pak.info().index_size + footer_size < bytes.len() as u64);
+}
+
+#[test]
+fn custom_magic() {
+    let mut bytes = build_pak_in_memory(PakVersion::FNameBasedCompressionMethod, ENTRIES);
+    let magic = PAK_FILE_MAGIC.to_le_bytes();
+    let pos = bytes.windows(4).rposition(|w| w == magic).expect("footer magic");
+    bytes[pos..pos + 4].copy_from_slice(&0x12345678u32.to_le_bytes());
+
+    assert!(load_pak_from_memory(&bytes, None).is_err());
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    let pak = PakFile::load_any_with_magic(&mut ar, None, 0x12345678).expect("pak file to load");
+    assert_eq!(pak.info().version, PakVersion::FNameBasedCompressionMethod);
+}
+
+#[test]
+fn stream_entries() {
+    let bytes = build_pak_in_memory(PakVersion::FNameBasedCompressionMethod, ENTRIES);
+    let mut names = Vec::new();
+    PakFile::stream_entries(&mut ArchiveReader(Cursor::new(&bytes)), None, |name, _| {
+        names.push(name.to_owned());
+        Ok(())
+    })
+    .expect("entries to stream");
+    assert_eq!(names, ["Game/Content/a.txt", "Game/Content/Sub/b.bin"]);
+
+    let mut count = 0;
+    let err = PakFile::stream_entries(&mut ArchiveReader(Cursor::new(&bytes)), None, |_, _| {
+        count += 1;
+        Err(io::Error::new(io::ErrorKind::Interrupted, "stop"))
+    })
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    assert_eq!(count, 1);
+}
+
+const KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+const OTHER_KEY: &str = "HwAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+#[test]
+fn builder_entry_headers() {
+    let content = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    for key in [None, Some(KEY)] {
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        let mut builder = PakFileBuilder::new(PakVersion::FNameBasedCompressionMethod);
+        if let Some(key) = key {
+            builder.encrypted(key).unwrap();
+        }
+        for name in ["Game/Content/a.txt", "Game/Content/b.txt"] {
+            let mut writer = builder.add(&mut ar, name.to_owned());
+            writer.write_all(content).unwrap();
+            writer.finalize().unwrap();
+        }
+        builder.finalize(&mut ar).unwrap();
+        let bytes = ar.0.into_inner();
+
+        let pak = load_pak_from_memory(&bytes, key).expect("pak file to load");
+        for (_, entry) in pak.index().named_entries() {
+            assert_eq!(entry.is_encrypted(), key.is_some());
+            assert_eq!(entry.size, content.len() as u64);
+            let header = PakEntry::de_with(
+                &mut ArchiveReader(Cursor::new(&bytes[entry.offset as usize..])),
+                pak.info().version,
+            )
+            .unwrap();
+            assert_eq!(header.hash, entry.hash);
+            let start = pak.payload_offset(entry).unwrap() as usize;
+            let mut payload =
+                bytes[start..start + (entry.size as usize).div_ceil(16) * 16].to_vec();
+            if let Some(cipher) = pak.cipher() {
+                cipher.decrypt(&mut payload).unwrap();
+            }
+            assert_eq!(&payload[..content.len()], content);
+        }
+    }
+}
+
+#[test]
+fn builder_streams_payloads() {
+    let content: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+    for key in [None, Some(KEY)] {
+        fn build<A: Archive>(ar: &mut A, key: Option<&str>, content: &[u8]) {
+            let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+            if let Some(key) = key {
+                builder.encrypted(key).unwrap();
+            }
+            let mut writer = builder.add(&mut *ar, "Game/Content/a.bin".to_owned());
+            for chunk in content.chunks(1000) {
+                writer.write_all(chunk).unwrap();
+            }
+            writer.finalize().unwrap();
+            builder.finalize(ar).unwrap();
+        }
+        let mut buffered = ArchiveWriter(Cursor::new(Vec::new()));
+        build(&mut buffered, key, &content);
+        let mut streamed = ArchiveSeekWriter(Cursor::new(Vec::new()));
+        build(&mut streamed, key, &content);
+        assert_eq!(streamed.0.get_ref(), buffered.0.get_ref());
+
+        // the payload reaches the archive before the entry is finalized
+        let mut ar = ArchiveSeekWriter(Cursor::new(Vec::new()));
+        let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+        if let Some(key) = key {
+            builder.encrypted(key).unwrap();
+        }
+        let mut writer = builder.add(&mut ar, "Game/Content/a.bin".to_owned());
+        writer.write_all(&content).unwrap();
+        let written = writer.get_mut().0.get_ref().len();
+        assert!(written >= content.len() - 16);
+        writer.finalize().unwrap();
+    }
+}
+
+#[test]
+fn read_entry_prefix() {
+    let bytes = build_pak_in_memory(PakVersion::Fnv64BugFix, ENTRIES);
+    let pak = load_pak_from_memory(&bytes, None).expect("pak file to load");
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    for (name, entry) in pak.index().named_entries() {
+        let content = ENTRIES.iter().find(|(n, _)| *n == name).unwrap().1;
+        let prefix = pak.read_entry_prefix(&mut ar, entry, 3, None).unwrap();
+        assert_eq!(prefix, &content[..3]);
+        let prefix = pak.read_entry_prefix(&mut ar, entry, 100, None).unwrap();
+        assert_eq!(prefix, content);
+    }
+}
+
+#[test]
+fn read_encrypted_entry_prefix() {
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::FNameBasedCompressionMethod);
+    builder.encrypted(KEY).unwrap();
+    let content = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    let mut writer = builder.add(&mut ar, "Game/Content/a.txt".to_owned());
+    writer.write_all(content).unwrap();
+    writer.finalize().unwrap();
+    builder.finalize(&mut ar).unwrap();
+    let bytes = ar.0.into_inner();
+
+    let pak = load_pak_from_memory(&bytes, Some(KEY)).expect("pak file to load");
+    let entries = pak.index().named_entries();
+    let entry = entries[0].1;
+    assert!(entry.is_encrypted());
+    assert_eq!(entry.size, content.len() as u64);
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    assert_eq!(pak.read_entry_prefix(&mut ar, entry, 20, None).unwrap(), &content[..20]);
+    assert_eq!(pak.read_entry_prefix(&mut ar, entry, 100, None).unwrap(), &content[..]);
+}
+
+#[test]
+fn key_registry() {
+    let version = PakVersion::FNameBasedCompressionMethod;
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(version);
+    builder.encrypted(KEY).unwrap();
+    let mut writer = builder.add(&mut ar, "Game/Content/a.txt".to_owned());
+    writer.write_all(b"encrypted content").unwrap();
+    writer.finalize().unwrap();
+    builder.finalize(&mut ar).unwrap();
+    let mut bytes = ar.0.into_inner();
+
+    let mut registry = KeyRegistry::new();
+    registry.insert(Guid::ZERO, KEY).unwrap();
+    let pak = load_with_registry(&bytes, &registry).unwrap();
+    assert!(!pak.info().requires_named_key());
+    let entry = pak.index().find("Game/Content/a.txt").unwrap();
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    assert_eq!(pak.read_entry_verified(&mut ar, entry).unwrap(), b"encrypted content");
+
+    // the guid starts the footer
+    let guid = Guid([1, 2, 3, 4]);
+    let footer_pos = bytes.len() - PakInfo::new(version).ser_de_len() as usize;
+    let mut guid_bytes = Vec::new();
+    guid.ser(&mut ArchiveWriter(&mut guid_bytes)).unwrap();
+    bytes[footer_pos..footer_pos + 16].copy_from_slice(&guid_bytes);
+
+    let pak = load_with_registry(&bytes, &registry).unwrap();
+    assert_eq!(pak.info().encryption_key_guid, guid);
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    let res = pak.read_entry_verified(&mut ar, entry);
+    assert!(matches!(res, Err(PakError::MissingKey)));
+
+    registry.insert(Guid::ZERO, OTHER_KEY).unwrap();
+    registry.insert(guid, KEY).unwrap();
+    let pak = load_with_registry(&bytes, &registry).unwrap();
+    assert!(pak.info().requires_named_key());
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    assert_eq!(pak.read_entry_verified(&mut ar, entry).unwrap(), b"encrypted content");
+}
+
+fn load_with_registry(bytes: &[u8], registry: &KeyRegistry) -> Result<PakFile, PakError> {
+    PakFile::load_any_with_registry(&mut ArchiveReader(Cursor::new(bytes)), registry)
+}
+
+#[test]
+fn edit_finalized_entry() {
+    for version in [PakVersion::DeleteRecords, PakVersion::Fnv64BugFix] {
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        let mut builder = PakFileBuilder::new(version);
+        let mut writer = builder.add(&mut ar, "Game/a.txt".to_owned());
+        writer.write_all(b"content").unwrap();
+        writer.finalize().unwrap().offset = 42;
+        builder.finalize(&mut ar).unwrap();
+        let bytes = ar.0.into_inner();
+
+        let pak = load_pak_from_memory(&bytes, None).unwrap();
+        assert_eq!(pak.index().find("Game/a.txt").unwrap().offset, 42);
+    }
+}
+
+#[test]
+fn add_alias() {
+    let version = PakVersion::Fnv64BugFix;
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(version);
+    let mut writer = builder.add(&mut ar, "Game/Content/a.txt".to_owned());
+    writer.write_all(b"shared content").unwrap();
+    let entry = writer.finalize().unwrap().clone();
+    builder.add_alias("Game/Content/b.txt".to_owned(), &entry).unwrap();
+    let out_of_range = PakEntry { offset: entry.offset + 1, ..entry.clone() };
+    assert!(builder.add_alias("Game/Content/c.txt".to_owned(), &out_of_range).is_err());
+    builder.finalize(&mut ar).unwrap();
+    let bytes = ar.0.into_inner();
+
+    let pak = load_pak_from_memory(&bytes, None).expect("pak file to load");
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    let entries = pak.index().named_entries();
+    assert_eq!(entries.len(), 2);
+    for (_, entry) in entries {
+        assert_eq!(pak.read_entry_prefix(&mut ar, entry, 100, None).unwrap(), b"shared content");
+    }
+    assert_eq!(pak.index().duplicate_groups().len(), 1);
+}
+
+#[test]
+fn load_any_forgiving() {
+    let mut bytes = build_pak_in_memory(PakVersion::Fnv64BugFix, ENTRIES);
+    let footer_len = PakInfo::new(PakVersion::Fnv64BugFix).ser_de_len() as usize;
+    // version field follows the encryption key guid, the encrypted index flag and the magic
+    let version_pos = bytes.len() - footer_len + 16 + 1 + 4;
+    bytes[version_pos..version_pos + 4].copy_from_slice(&12i32.to_le_bytes());
+
+    assert!(PakFile::load_any(&mut ArchiveReader(Cursor::new(&bytes))).is_err());
+    let (pak, unknown_version) =
+        PakFile::load_any_forgiving(&mut ArchiveReader(Cursor::new(&bytes)), None).unwrap();
+    assert_eq!(unknown_version, Some(12));
+    assert_eq!(pak.info().version, PakVersion::Fnv64BugFix);
+    assert_eq!(pak.index().named_entries().len(), ENTRIES.len());
+
+    let bytes = build_pak_in_memory(PakVersion::Fnv64BugFix, ENTRIES);
+    let (_, unknown_version) =
+        PakFile::load_any_forgiving(&mut ArchiveReader(Cursor::new(&bytes)), None).unwrap();
+    assert_eq!(unknown_version, None);
+}
+
+#[test]
+fn read_entry_prefix_at() {
+    let bytes = Arc::new(build_pak_in_memory(PakVersion::Fnv64BugFix, ENTRIES));
+    let pak = load_pak_from_memory(&bytes, None).expect("pak file to load");
+    let entries = pak.index().named_entries();
+    thread::scope(|scope| {
+        for (name, entry) in &entries {
+            let (pak, src) = (&pak, bytes.clone());
+            scope.spawn(move || {
+                let content = ENTRIES.iter().find(|(n, _)| n == name).unwrap().1;
+                assert_eq!(pak.read_entry_prefix_at(&src, entry, 100, None).unwrap(), content);
+            });
+        }
+    });
+    let out_of_bounds = PakEntry { offset: bytes.len() as u64, size: 1, ..PakEntry::default() };
+    assert!(pak.read_entry_prefix_at(&bytes, &out_of_bounds, 1, None).is_err());
+}
+
+#[test]
+fn from_parts() {
+    let bytes = build_pak_in_memory(PakVersion::Fnv64BugFix, ENTRIES);
+    let pak = load_pak_from_memory(&bytes, None).expect("pak file to load");
+    let mut data = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut index = ArchiveWriter(Cursor::new(Vec::new()));
+    pak.split(&mut ArchiveReader(Cursor::new(&bytes)), &mut data, &mut index).unwrap();
+    let mut data = ArchiveReader(Cursor::new(data.0.into_inner()));
+
+    let (info, index) = pak.into_parts();
+    let mut v1_info = info.clone();
+    v1_info.version = PakVersion::FNameBasedCompressionMethod;
+    assert!(PakFile::from_parts(v1_info, PakIndex::new(PakVersion::PathHashIndex)).is_err());
+
+    let pak = PakFile::from_parts(info, index).unwrap();
+    for (name, entry) in pak.index().named_entries() {
+        let content = ENTRIES.iter().find(|(n, _)| *n == name).unwrap().1;
+        assert_eq!(pak.read_entry_prefix(&mut data, entry, 100, None).unwrap(), content);
+    }
+}
+
+#[test]
+fn split() {
+    let v1 = build_pak_in_memory(PakVersion::FNameBasedCompressionMethod, ENTRIES);
+    let encrypted_v1 = encrypt_index(&v1, KEY);
+    let v2 = build_pak_in_memory(PakVersion::Fnv64BugFix, ENTRIES);
+    for bytes in [v1, encrypted_v1, v2] {
+        let pak = load_pak_from_memory(&bytes, Some(KEY)).unwrap();
+        let mut data = ArchiveWriter(Cursor::new(Vec::new()));
+        let mut index = ArchiveWriter(Cursor::new(Vec::new()));
+        let split =
+            pak.split(&mut ArchiveReader(Cursor::new(&bytes)), &mut data, &mut index).unwrap();
+        let (data, index) = (data.0.into_inner(), index.0.into_inner());
+        assert_eq!(split.index_offset, pak.info().index_offset);
+        assert_eq!((data.len() as u64, index.len() as u64), (split.data_size, split.index_size));
+
+        let index_pak = load_pak_from_memory(&index, Some(KEY)).unwrap();
+        assert_eq!(index_pak.info().index_offset, 0);
+        assert_eq!(index_pak.index().named_entries(), pak.index().named_entries());
+
+        let mut out = ArchiveWriter(Vec::new());
+        let (mut data, mut index) =
+            (ArchiveReader(Cursor::new(data)), ArchiveReader(Cursor::new(index)));
+        index_pak.recombine(&split, &mut data, &mut index, &mut out).unwrap();
+        assert_eq!(out.0, bytes);
+    }
+}
+
+#[test]
+fn load_standalone() {
+    for &version in &[PakVersion::FNameBasedCompressionMethod, PakVersion::Fnv64BugFix] {
+        let bytes = build_pak_in_memory(version, ENTRIES);
+        let mut ar = ArchiveReader(Cursor::new(&bytes));
+        let info = PakFile::load_footer(&mut ar).unwrap();
+        assert_eq!(info.version, version);
+        let index = PakIndex::load_standalone(&mut ar, &info, None).unwrap();
+        assert_eq!(index.named_entries().len(), ENTRIES.len());
+    }
+}
+
+#[test]
+fn validate_for_version() {
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::FNameBasedCompressionMethod);
+    for name in ["Game/A.txt", "root.txt", "game/a.txt"] {
+        let mut writer = builder.add(&mut ar, name.to_owned());
+        writer.write_all(b"content").unwrap();
+        writer.finalize().unwrap();
+    }
+    assert!(builder.validate_for_version(PakVersion::FNameBasedCompressionMethod).is_empty());
+    assert_eq!(
+        builder.validate_for_version(PakVersion::Fnv64BugFix),
+        [
+            RepackIssue::NotInDirectory("root.txt".to_owned()),
+            RepackIssue::Collision("game/a.txt".to_owned(), "Game/A.txt".to_owned()),
+        ]
+    );
+    builder.finalize(&mut ar).unwrap();
+
+    // V2 indexes report issues when entries are added
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+    let mut add = |name: &str| {
+        let mut writer = builder.add(&mut ar, name.to_owned());
+        writer.write_all(b"content").unwrap();
+        writer.finalize().map(|_| ())
+    };
+    add("Game/A.txt").unwrap();
+    assert_eq!(add("root.txt").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(add("game/a.txt").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    add("Game/B.txt").unwrap();
+    builder.deleted("Game/C.txt").unwrap();
+    builder.finalize(&mut ar).unwrap();
+    let pak = load_pak_from_memory(&ar.0.into_inner(), None).unwrap();
+    assert_eq!(pak.index().num_entries(), 3);
+    assert!(pak.index().find("Game/A.txt").is_some());
+    assert!(pak.index().contains("Game/C.txt", PakVersion::Fnv64BugFix));
+    assert!(pak.index().find("Game/C.txt").is_none());
+
+    let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+    builder.set_path_hash_index(false).unwrap();
+    builder.deleted("Game/A.txt").unwrap();
+    assert!(builder.set_full_directory_index(false).is_err());
+    let mut builder = PakFileBuilder::new(PakVersion::FNameBasedCompressionMethod);
+    assert!(builder.set_path_hash_index(false).is_err());
+    let mut builder = PakFileBuilder::new(PakVersion::RelativeChunkOffsets);
+    assert_eq!(builder.deleted("Game/A.txt").unwrap_err().kind(), io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn set_path_hash_seed() {
+    let version = PakVersion::Fnv64BugFix;
+    let seed = PakIndexV2::filename_seed("pakchunk1-Windows.pak");
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(version);
+    builder.set_path_hash_seed(seed).unwrap();
+    let mut writer = builder.add(&mut ar, "Game/A.txt".to_owned());
+    writer.write_all(b"content").unwrap();
+    writer.finalize().unwrap();
+    assert!(builder.set_path_hash_seed(0).is_err());
+    builder.finalize(&mut ar).unwrap();
+
+    let pak = load_pak_from_memory(&ar.0.into_inner(), None).unwrap();
+    match pak.index() {
+        PakIndex::V2(v2) => {
+            assert_eq!(v2.path_hash_seed, seed);
+            assert!(v2.find_by_name("game/a.txt", version).is_some());
+        }
+        PakIndex::V1(_) => panic!("expected a V2 index"),
+    }
+}
+
+#[test]
+fn pruned_directory_index() {
+    let version = PakVersion::Fnv64BugFix;
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(version);
+    builder.set_full_directory_index(false).unwrap();
+    for (name, content) in ENTRIES {
+        let mut writer = builder.add(&mut ar, name.to_string());
+        writer.write_all(content).unwrap();
+        writer.finalize().unwrap();
+    }
+    builder.finalize(&mut ar).unwrap();
+    let bytes = ar.0.into_inner();
+
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let v2 = match pak.index() {
+        PakIndex::V2(v2) => v2,
+        PakIndex::V1(_) => panic!("expected a V2 index"),
+    };
+    assert!(!v2.has_full_directory_index);
+    assert_eq!(v2.directories().collect::<Vec<_>>(), ["Game/Content", "Game/Content/Sub"]);
+    assert_eq!(v2.pruned_entries().count(), 0);
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    for (name, content) in ENTRIES {
+        let entry = v2.find_by_name(name, version).unwrap();
+        assert_eq!(&pak.read_entry(&mut ar, entry).unwrap(), content);
+        assert!(pak.index().find_ci(name, version).is_some());
+    }
+    // entry paths are unknown, extraction can't name the files
+    let out_dir = test_dir("pruned_directory_index");
+    let err = pak.extract_all(&mut ar, &out_dir, None).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+    let mut builder = PakFileBuilder::new(version);
+    builder.set_full_directory_index(false).unwrap();
+    builder.set_path_hash_index(false).unwrap();
+    assert!(builder.finalize(&mut ArchiveWriter(Cursor::new(Vec::new()))).is_err());
+}
+
+#[test]
+fn read_full_index_bytes() {
+    let bytes = build_pak_in_memory(PakVersion::Fnv64BugFix, ENTRIES);
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    let info = PakFile::load_footer(&mut ar).unwrap();
+    let sections = PakFile::read_full_index_bytes(&mut ar, &info, None).unwrap();
+    let names: Vec<_> = sections.keys().copied().collect();
+    assert_eq!(names, ["FullDirectoryIndex", "PakIndex", "PathHashIndex"]);
+    assert_eq!(sections["PakIndex"].len() as u64, info.index_size);
+
+    // the primary index starts with the mount point
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let mount_point = String::de(&mut ArchiveReader(Cursor::new(&sections["PakIndex"]))).unwrap();
+    assert_eq!(mount_point, pak.index().mount_point());
+
+    let bytes = build_pak_in_memory(PakVersion::FNameBasedCompressionMethod, ENTRIES);
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    let info = PakFile::load_footer(&mut ar).unwrap();
+    let sections = PakFile::read_full_index_bytes(&mut ar, &info, None).unwrap();
+    assert_eq!(sections.keys().copied().collect::<Vec<_>>(), ["PakIndex"]);
+}
+
+#[test]
+fn index_sections_hashes() {
+    let bytes = build_pak_in_memory(PakVersion::Fnv64BugFix, ENTRIES);
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    let info = PakFile::load_footer(&mut ar).unwrap();
+    let sections = PakFile::read_full_index_bytes(&mut ar, &info, None).unwrap();
+
+    // sections are written one after the other, each one has its own hash. Corrupted bytes are
+    // the entry count, the first path hash and the first directory name character.
+    let mut start = info.index_offset as usize;
+    for (name, pos) in [("PakIndex", 4), ("PathHashIndex", 4), ("FullDirectoryIndex", 8)] {
+        let mut corrupted = bytes.clone();
+        corrupted[start + pos] ^= 0x01;
+        start += sections[name].len();
+        let err = load_pak_from_memory(&corrupted, None).unwrap_err();
+        assert!(err.to_string().contains(&format!("Corrupt {}", name)), "{}", err);
+    }
+}
+
+#[test]
+fn take_entry_reader() {
+    let bytes = build_pak_in_memory(PakVersion::Fnv64BugFix, ENTRIES);
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let mut reader =
+        pak.take_entry_reader(ArchiveReader(Cursor::new(&bytes)), "Game/Content/a.txt").unwrap();
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content).unwrap();
+    assert_eq!(content, b"hello");
+
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let err = pak.take_entry_reader(ArchiveReader(Cursor::new(&bytes)), "missing").err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn take_encrypted_entry_reader() {
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+    builder.encrypted(KEY).unwrap();
+    let content: Vec<u8> = (0..100).collect();
+    let mut writer = builder.add(&mut ar, "Game/Content/a.bin".to_owned());
+    writer.write_all(&content).unwrap();
+    writer.finalize().unwrap();
+    builder.finalize(&mut ar).unwrap();
+    let bytes = ar.0.into_inner();
+
+    let pak = load_pak_from_memory(&bytes, Some(KEY)).unwrap();
+    let mut reader =
+        pak.take_entry_reader(ArchiveReader(Cursor::new(&bytes)), "Game/Content/a.bin").unwrap();
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).unwrap();
+    assert_eq!(read, content);
+}
+
+#[test]
+fn take_compressed_entry_reader() {
+    let content: Vec<u8> = (0..5000u32).map(|i| (i % 7) as u8).collect();
+    for key in [None, Some(KEY)] {
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+        if let Some(key) = key {
+            builder.encrypted(key).unwrap();
+        }
+        let name = "Game/Content/a.bin".to_owned();
+        let mut writer = builder.add_compressed(&mut ar, name, "Zlib", 1024).unwrap();
+        writer.write_all(&content).unwrap();
+        writer.finalize().unwrap();
+        builder.finalize(&mut ar).unwrap();
+        let bytes = ar.0.into_inner();
+
+        let pak = load_pak_from_memory(&bytes, key).unwrap();
+        let mut reader = pak
+            .take_entry_reader(ArchiveReader(Cursor::new(&bytes)), "Game/Content/a.bin")
+            .unwrap();
+        assert_eq!(reader.entry().compression_blocks.len(), 5);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, content);
+    }
+}
+
+#[test]
+fn force_index_hash() {
+    for &version in &[PakVersion::FNameBasedCompressionMethod, PakVersion::Fnv64BugFix] {
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        let mut builder = PakFileBuilder::new(version);
+        builder.force_index_hash([0xAB; 20]);
+        let mut writer = builder.add(&mut ar, "Game/Content/a.txt".to_owned());
+        writer.write_all(b"hello").unwrap();
+        writer.finalize().unwrap();
+        builder.finalize(&mut ar).unwrap();
+        let bytes = ar.0.into_inner();
+
+        let err = PakFile::load_version(&mut ArchiveReader(Cursor::new(&bytes)), version)
+            .expect_err("corrupt index to be rejected");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("Corrupt PakIndex"), "{}", err);
+    }
+}
+
+#[test]
+fn validate_layout() {
+    for &version in &[PakVersion::FNameBasedCompressionMethod, PakVersion::Fnv64BugFix] {
+        let bytes = build_pak_in_memory(version, ENTRIES);
+        let pak = load_pak_from_memory(&bytes, None).unwrap();
+        assert_eq!(pak.index().last_entry_end(version), pak.info().index_offset);
+        pak.validate_layout().unwrap();
+
+        let (mut info, index) = pak.into_parts();
+        info.index_offset -= 1;
+        let pak = PakFile::from_parts(info, index).unwrap();
+        let err = pak.validate_layout().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn read_entry() {
+    for &version in PakVersion::list() {
+        if version == PakVersion::FrozenIndex {
+            continue;
+        }
+        let bytes = build_pak_in_memory(version, ENTRIES);
+        let pak = load_pak_from_memory(&bytes, None).expect("pak file to load");
+        let mut ar = ArchiveReader(Cursor::new(&bytes));
+        for ((_, entry), (_, content)) in pak.index().named_entries().iter().zip(ENTRIES) {
+            assert_eq!(&pak.read_entry(&mut ar, entry).unwrap(), content, "{:?}", version);
+        }
+
+        let entry = &pak.index().named_entries()[0].1;
+        let wrong_size = PakEntry { size: entry.size + 1, ..(*entry).clone() };
+        let err = pak.read_entry(&mut ar, &wrong_size).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn load_at_offset() {
+    let version = PakVersion::Fnv64BugFix;
+    let mut bytes = build_pak_in_memory(version, ENTRIES);
+    let footer_offset = bytes.len() as u64 - PakInfo::new(version).ser_de_len();
+    bytes.extend_from_slice(&[0xCD; 100]);
+
+    assert!(load_pak_from_memory(&bytes, None).is_err());
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    let pak = PakFile::load_at_offset(&mut ar, footer_offset, None).unwrap();
+    assert_eq!(pak.info().version, version);
+    for ((_, entry), (_, content)) in pak.index().named_entries().iter().zip(ENTRIES) {
+        assert_eq!(&pak.read_entry(&mut ar, entry).unwrap(), content);
+    }
+}
+
+/// Build a pak file with a single entry made of `content` compressed by `compress` in 1024 bytes
+/// blocks, each block is padded and encrypted if `key` is set
+///
+/// The builder doesn't compress, the compressed blocks are imported as is.
+fn build_compressed_pak(
+    version: PakVersion,
+    compression_method_index: u32,
+    content: &[u8],
+    key: Option<&str>,
+    compress: impl Fn(&[u8]) -> Vec<u8>,
+) -> Vec<u8> {
+    let compressed: Vec<Vec<u8>> = content.chunks(1024).map(compress).collect();
+    let blocks: Vec<Vec<u8>> = match key {
+        Some(key) => compressed.iter().map(|block| encrypt(key, block)).collect(),
+        None => compressed.clone(),
+    };
+    let mut entry = PakEntry {
+        size: blocks.iter().map(|b| b.len() as u64).sum(),
+        uncompressed_size: content.len() as u64,
+        compression_method_index,
+        compression_block_size: 1024,
+        compression_blocks: vec![Default::default(); blocks.len()],
+        // FLAG_ENCRYPTED
+        flags: if key.is_some() { 0x01 } else { 0 },
+        ..PakEntry::default()
+    };
+    let mut start = entry.ser_len_with(version);
+    for (i, block) in entry.compression_blocks.iter_mut().enumerate() {
+        block.compressed_start = start;
+        block.compressed_end = start + compressed[i].len() as u64;
+        start += blocks[i].len() as u64;
+    }
+    entry.hash = Sha1::digest(&blocks.concat()).into();
+
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(version);
+    let mut writer = builder.import(&mut ar, "Game/Content/a.bin".to_owned(), entry);
+    for compressed in &blocks {
+        writer.write_all(compressed).unwrap();
+    }
+    writer.finalize().unwrap();
+    builder.finalize(&mut ar).unwrap();
+    ar.0.into_inner()
+}
+
+#[test]
+fn import_mismatch() {
+    let entry = PakEntry {
+        size: 5,
+        uncompressed_size: 5,
+        hash: Sha1::digest(b"hello").into(),
+        ..PakEntry::default()
+    };
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+    let mut writer = builder.import(&mut ar, "Game/Content/a.txt".to_owned(), entry.clone());
+    writer.write_all(b"hell").unwrap();
+    match writer.finalize().unwrap_err() {
+        PakError::ImportMismatch { field, expected, got } => {
+            assert_eq!((field.as_str(), expected, got), ("size", 5, 4));
+        }
+        err => panic!("unexpected error {:?}", err),
+    }
+
+    let mut writer = builder.import(&mut ar, "Game/Content/a.txt".to_owned(), entry);
+    writer.write_all(b"hellO").unwrap();
+    let err = writer.finalize().unwrap_err();
+    assert!(matches!(err, PakError::HashMismatch { .. }), "{:?}", err);
+}
+
+/// Pad `data` to the AES block size with zeros and encrypt it with the base64 encoded `key`
+fn encrypt(key: &str, data: &[u8]) -> Vec<u8> {
+    let mut buffer = data.to_vec();
+    buffer.resize(data.len().div_ceil(16) * 16, 0);
+    let key = base64::decode(key).unwrap();
+    let cipher = Ecb::<Aes256, NoPadding>::new_from_slices(&key, &[]).unwrap();
+    let len = buffer.len();
+    cipher.encrypt(&mut buffer, len).unwrap();
+    buffer
+}
+
+/// Encrypt the V1 index of the pak file `bytes` with `key`, as UE does with index encryption
+fn encrypt_index(bytes: &[u8], key: &str) -> Vec<u8> {
+    let mut info = load_pak_from_memory(bytes, None).unwrap().info().clone();
+    let (offset, size) = (info.index_offset as usize, info.index_size as usize);
+    let mut index = bytes[offset..offset + size].to_vec();
+    index.resize(size.div_ceil(16) * 16, 0);
+    info.encrypted_index = true;
+    info.index_size = index.len() as u64;
+    info.index_hash = Sha1::digest(&index).into();
+    let mut encrypted = bytes[..offset].to_vec();
+    encrypted.extend_from_slice(&encrypt(key, &index));
+    info.ser(&mut ArchiveWriter(&mut encrypted)).unwrap();
+    encrypted
+}
+
+fn zlib_compress(block: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(block).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn add_compressed() {
+    let content: Vec<u8> = (0..5000u32).map(|i| (i % 7) as u8).collect();
+    let versions = [
+        PakVersion::CompressionEncryption,
+        PakVersion::DeleteRecords,
+        PakVersion::FNameBasedCompressionMethod,
+        PakVersion::Fnv64BugFix,
+    ];
+    for version in versions {
+        for key in [None, Some(KEY)] {
+            let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+            let mut builder = PakFileBuilder::new(version);
+            if let Some(key) = key {
+                builder.encrypted(key).unwrap();
+            }
+            let mut writer = builder.add(&mut ar, "Game/Content/first.txt".to_owned());
+            writer.write_all(b"first entry").unwrap();
+            writer.finalize().unwrap();
+            let name = "Game/Content/a.bin".to_owned();
+            let mut writer = builder.add_compressed(&mut ar, name, "Zlib", 2048).unwrap();
+            for chunk in content.chunks(100) {
+                writer.write_all(chunk).unwrap();
+            }
+            // complete blocks are compressed while writing
+            assert!(writer.size() > 0 && writer.size() < 2048);
+            let entry = writer.finalize().unwrap();
+            assert_eq!(entry.compression_blocks.len(), 3);
+            assert_eq!(entry.uncompressed_size, content.len() as u64);
+            assert!(entry.size < entry.uncompressed_size);
+            builder.finalize(&mut ar).unwrap();
+            let bytes = ar.0.into_inner();
+
+            let pak = load_pak_from_memory(&bytes, key).unwrap();
+            let entry = pak.index().find("Game/Content/a.bin").unwrap();
+            assert_eq!(entry.required_method(&pak.info().compression_methods), Some("Zlib"));
+            let mut ar = ArchiveReader(Cursor::new(&bytes));
+            assert_eq!(pak.read_entry_verified(&mut ar, entry).unwrap(), content);
+        }
+    }
+
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::DeleteRecords);
+    assert!(builder.add_compressed(&mut ar, "Game/a".to_owned(), "Oodle", 2048).is_err());
+    assert!(builder.add_compressed(&mut ar, "Game/a".to_owned(), "Zlib", 0).is_err());
+}
+
+#[test]
+fn add_compressed_streamed() {
+    let content: Vec<u8> = (0..5000u32).map(|i| (i % 7) as u8).collect();
+    for version in [PakVersion::DeleteRecords, PakVersion::Fnv64BugFix] {
+        for key in [None, Some(KEY)] {
+            fn build<A: Archive>(
+                ar: &mut A,
+                version: PakVersion,
+                key: Option<&str>,
+                content: &[u8],
+            ) {
+                let mut builder = PakFileBuilder::new(version);
+                if let Some(key) = key {
+                    builder.encrypted(key).unwrap();
+                }
+                let name = "Game/Content/a.bin".to_owned();
+                let mut writer = builder.add_compressed(&mut *ar, name, "Zlib", 2048).unwrap();
+                writer.set_uncompressed_size(content.len() as u64).unwrap();
+                writer.write_all(content).unwrap();
+                writer.finalize().unwrap();
+                builder.finalize(ar).unwrap();
+            }
+            let mut buffered = ArchiveWriter(Cursor::new(Vec::new()));
+            build(&mut buffered, version, key, &content);
+            let mut streamed = ArchiveSeekWriter(Cursor::new(Vec::new()));
+            build(&mut streamed, version, key, &content);
+            assert_eq!(streamed.0.get_ref(), buffered.0.get_ref());
+
+            let bytes = streamed.0.into_inner();
+            let pak = load_pak_from_memory(&bytes, key).unwrap();
+            let entry = pak.index().find("Game/Content/a.bin").unwrap();
+            let mut ar = ArchiveReader(Cursor::new(&bytes));
+            assert_eq!(pak.read_entry_verified(&mut ar, entry).unwrap(), content);
+        }
+    }
+
+    let mut ar = ArchiveSeekWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+    let name = "Game/Content/a.bin".to_owned();
+    let mut writer = builder.add_compressed(&mut ar, name, "Zlib", 2048).unwrap();
+    writer.set_uncompressed_size(content.len() as u64 + 1).unwrap();
+    writer.write_all(&content).unwrap();
+    assert!(writer.set_uncompressed_size(1).is_err());
+    assert!(writer.finalize().is_err());
+}
+
+#[test]
+fn add_auto() {
+    let compressible: Vec<u8> = (0..5000u32).map(|i| (i % 7) as u8).collect();
+    // xorshift output doesn't compress
+    let mut state = 0x2545_F491u32;
+    let incompressible: Vec<u8> = (0..5000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+
+    let version = PakVersion::DeleteRecords;
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(version);
+    builder.encrypted(KEY).unwrap();
+    for (name, content) in [("Game/a.bin", &compressible), ("Game/b.bin", &incompressible)] {
+        let mut writer = builder.add_auto(&mut ar, name.to_owned());
+        writer.write_all(content).unwrap();
+        writer.finalize().unwrap();
+    }
+    builder.set_min_compression_savings(1.0);
+    let mut writer = builder.add_auto(&mut ar, "Game/c.bin".to_owned());
+    writer.write_all(&compressible).unwrap();
+    writer.finalize().unwrap();
+    builder.finalize(&mut ar).unwrap();
+    let bytes = ar.0.into_inner();
+
+    let pak = load_pak_from_memory(&bytes, Some(KEY)).unwrap();
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    for (name, content, compressed) in [
+        ("Game/a.bin", &compressible, true),
+        ("Game/b.bin", &incompressible, false),
+        ("Game/c.bin", &compressible, false),
+    ] {
+        let entry = pak.index().find(name).unwrap();
+        assert_eq!(entry.compression_method_index != 0, compressed, "{}", name);
+        assert_eq!(&pak.read_entry_verified(&mut ar, entry).unwrap(), content);
+    }
+}
+
+#[test]
+fn compression_block_ranges() {
+    let content: Vec<u8> = (0..2000u32).map(|i| (i % 7) as u8).collect();
+    let blocks: Vec<Vec<u8>> = content.chunks(1024).map(zlib_compress).collect();
+    for version in [PakVersion::DeleteRecords, PakVersion::RelativeChunkOffsets] {
+        // a first entry so that the compressed entry offset isn't 0
+        let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+        let mut builder = PakFileBuilder::new(version);
+        let mut writer = builder.add(&mut ar, "Game/Content/first.txt".to_owned());
+        writer.write_all(b"first entry").unwrap();
+        writer.finalize().unwrap();
+        let offset = ar.0.position();
+
+        let mut entry = PakEntry {
+            size: blocks.iter().map(|b| b.len() as u64).sum(),
+            uncompressed_size: content.len() as u64,
+            compression_method_index: 1,
+            compression_block_size: 1024,
+            compression_blocks: vec![Default::default(); blocks.len()],
+            hash: Sha1::digest(&blocks.concat()).into(),
+            ..PakEntry::default()
+        };
+        let payload_offset = offset + entry.ser_len_with(version);
+        let base_offset = if version < PakVersion::RelativeChunkOffsets { 0 } else { offset };
+        let mut start = payload_offset - base_offset;
+        for (block, compressed) in entry.compression_blocks.iter_mut().zip(&blocks) {
+            block.compressed_start = start;
+            block.compressed_end = start + compressed.len() as u64;
+            start = block.compressed_end;
+        }
+        let mut writer = builder.import(&mut ar, "Game/Content/a.bin".to_owned(), entry);
+        writer.write_all(&blocks.concat()).unwrap();
+        writer.finalize().unwrap();
+        builder.finalize(&mut ar).unwrap();
+        let bytes = ar.0.into_inner();
+
+        let pak = load_pak_from_memory(&bytes, None).unwrap();
+        let entries = pak.index().named_entries();
+        let (_, entry) = entries.iter().find(|(name, _)| name.ends_with("a.bin")).unwrap();
+        let first_end = payload_offset + blocks[0].len() as u64;
+        let second_end = first_end + blocks[1].len() as u64;
+        assert_eq!(
+            pak.compression_block_ranges(entry),
+            [payload_offset..first_end, first_end..second_end],
+            "{:?}",
+            version
+        );
+        let mut ar = ArchiveReader(Cursor::new(&bytes));
+        assert_eq!(pak.read_entry_verified(&mut ar, entry).unwrap(), content);
+    }
+}
+
+#[test]
+fn read_zlib_entry() {
+    let content: Vec<u8> = (0..3000u32).map(|i| (i % 7) as u8).collect();
+    let bytes = build_compressed_pak(PakVersion::DeleteRecords, 1, &content, None, zlib_compress);
+
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let entries = pak.index().named_entries();
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    assert_eq!(pak.read_entry(&mut ar, entries[0].1).unwrap(), content);
+    let prefix = pak.read_entry_prefix(&mut ar, entries[0].1, 1500, None).unwrap();
+    assert_eq!(prefix, &content[..1500]);
+}
+
+#[test]
+fn read_gzip_entry() {
+    // legacy pak files have "Zlib", "Gzip" and "Oodle" methods injected, Gzip is index 2
+    let content: Vec<u8> = (0..2500u32).map(|i| (i % 13) as u8).collect();
+    let bytes =
+        build_compressed_pak(PakVersion::RelativeChunkOffsets, 2, &content, None, |chunk| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(chunk).unwrap();
+            encoder.finish().unwrap()
+        });
+
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let entries = pak.index().named_entries();
+    assert_eq!(entries[0].1.required_method(&pak.info().compression_methods), Some("Gzip"));
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    assert_eq!(pak.read_entry(&mut ar, entries[0].1).unwrap(), content);
+}
+
+#[test]
+fn read_oodle_entry() {
+    // legacy pak files have "Zlib", "Gzip" and "Oodle" methods injected, Oodle is index 3
+    let content: Vec<u8> = (0..2500u32).map(|i| (i % 11) as u8).collect();
+    let invert = |block: &[u8]| block.iter().map(|b| !b).collect::<Vec<u8>>();
+    let bytes = build_compressed_pak(PakVersion::DeleteRecords, 3, &content, None, invert);
+
+    let mut pak = load_pak_from_memory(&bytes, None).unwrap();
+    let entry = pak.index().named_entries()[0].1.clone();
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    let err = pak.read_entry(&mut ar, &entry).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    assert!(err.to_string().contains("Oodle"));
+
+    pak.set_oodle_decompressor(Box::new(|input, output| {
+        assert_eq!(input.len(), output.len());
+        output.iter_mut().zip(input).for_each(|(o, i)| *o = !i);
+        Ok(())
+    }));
+    assert_eq!(pak.read_entry(&mut ar, &entry).unwrap(), content);
+    assert_eq!(pak.read_entry_prefix(&mut ar, &entry, 1030, None).unwrap(), &content[..1030]);
+}
+
+#[test]
+fn read_entry_with_key() {
+    // the index isn't encrypted, the pak file can be loaded without the key
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+    builder.encrypted(KEY).unwrap();
+    let content = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    let mut writer = builder.add(&mut ar, "Game/Content/a.txt".to_owned());
+    writer.write_all(content).unwrap();
+    writer.finalize().unwrap();
+    builder.finalize(&mut ar).unwrap();
+    let bytes = ar.0.into_inner();
+
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let entries = pak.index().named_entries();
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    assert!(pak.read_entry(&mut ar, entries[0].1).is_err());
+    assert_eq!(pak.read_entry_with_key(&mut ar, entries[0].1, KEY).unwrap(), content);
+
+    // compressed blocks are decrypted before being decompressed
+    let content: Vec<u8> = (0..3000u32).map(|i| (i % 5) as u8).collect();
+    let version = PakVersion::RelativeChunkOffsets;
+    let bytes = build_compressed_pak(version, 1, &content, Some(KEY), zlib_compress);
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let entries = pak.index().named_entries();
+    assert!(entries[0].1.is_encrypted());
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    assert_eq!(pak.read_entry_with_key(&mut ar, entries[0].1, KEY).unwrap(), content);
+}
+
+#[test]
+fn add_encrypted() {
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::DeleteRecords);
+    let content = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    let mut writer = builder.add_encrypted(&mut ar, "Game/a.txt".to_owned(), KEY).unwrap();
+    writer.write_all(content).unwrap();
+    writer.finalize().unwrap();
+    let mut writer = builder.add(&mut ar, "Game/b.txt".to_owned());
+    writer.write_all(content).unwrap();
+    writer.finalize().unwrap();
+    assert!(builder.add_encrypted(&mut ar, "Game/c.txt".to_owned(), "not a key").is_err());
+    builder.finalize(&mut ar).unwrap();
+    let bytes = ar.0.into_inner();
+
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    let entry = pak.index().find("Game/a.txt").unwrap();
+    assert!(entry.is_encrypted());
+    assert_eq!((entry.size, entry.uncompressed_size), (36, 36));
+    pak.verify_entry(&mut ar, entry).unwrap();
+    assert_eq!(pak.read_entry_with_key(&mut ar, entry, KEY).unwrap(), content);
+    let entry = pak.index().find("Game/b.txt").unwrap();
+    assert!(!entry.is_encrypted());
+    assert_eq!(pak.read_entry_verified(&mut ar, entry).unwrap(), content);
+}
+
+#[test]
+fn typed_errors() {
+    let err = load_pak_from_memory(&[0u8; 256], None).unwrap_err();
+    assert!(matches!(err, PakError::UnsupportedVersion), "{}", err);
+    let bytes = build_pak_in_memory(PakVersion::DeleteRecords, ENTRIES);
+    let err = load_pak_from_memory(&bytes, Some("not a key")).unwrap_err();
+    assert!(matches!(err, PakError::InvalidKey(_)), "{}", err);
+
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::DeleteRecords);
+    let res = builder.add_compressed(&mut ar, "Game/a".to_owned(), "Oodle", 2048);
+    assert!(matches!(res, Err(PakError::UnsupportedCompression(method)) if method == "Oodle"));
+    let mut writer = builder.add_encrypted(&mut ar, "Game/a.txt".to_owned(), KEY).unwrap();
+    writer.write_all(b"encrypted").unwrap();
+    let offset = writer.finalize().unwrap().offset;
+    builder.finalize(&mut ar).unwrap();
+    let mut bytes = ar.0.into_inner();
+
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let entry = pak.index().find("Game/a.txt").unwrap();
+    let err = pak.read_entry(&mut ArchiveReader(Cursor::new(&bytes)), entry).unwrap_err();
+    assert!(matches!(err, PakError::MissingKey), "{}", err);
+    // the last payload byte
+    bytes[(pak.payload_offset(entry).unwrap() + 15) as usize] ^= 0x01;
+    let err = pak.verify_entry(&mut ArchiveReader(Cursor::new(&bytes)), entry).unwrap_err();
+    match err {
+        PakError::HashMismatch { context, expected, .. } => {
+            assert_eq!(context, format!("entry at {}", offset));
+            assert_eq!(expected, entry.hash);
+        }
+        err => panic!("unexpected error {}", err),
+    }
+    // typed errors survive a round trip through io::Error
+    let err = io::Error::from(PakError::MissingKey);
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(matches!(PakError::from(err), PakError::MissingKey));
+}
+
+#[test]
+fn load_any_with_keys() {
+    let bytes = build_pak_in_memory(PakVersion::EncryptionKeyGuid, ENTRIES);
+    let load = |bytes: &[u8], keys: &[&str]| {
+        PakFile::load_any_with_keys(&mut ArchiveReader(Cursor::new(bytes)), keys)
+    };
+    // keys aren't checked without index encryption
+    assert!(load(&bytes, &[OTHER_KEY]).is_ok());
+
+    let bytes = encrypt_index(&bytes, KEY);
+    let pak = load(&bytes, &[OTHER_KEY, KEY]).unwrap();
+    assert!(pak.info().encrypted_index);
+    assert_eq!(pak.index().named_entries().len(), ENTRIES.len());
+    let err = load(&bytes, &[OTHER_KEY, OTHER_KEY]).unwrap_err();
+    assert!(matches!(err, PakError::NoMatchingKey { tried: 2 }), "{}", err);
+    let err = load(&bytes, &[]).unwrap_err();
+    assert!(matches!(err, PakError::MissingKey), "{}", err);
+    let err = load(&bytes, &[KEY, "not a key"]).unwrap_err();
+    assert!(matches!(err, PakError::InvalidKey(_)), "{}", err);
+}
+
+#[test]
+fn wrong_index_key() {
+    let bytes = build_pak_in_memory(PakVersion::FNameBasedCompressionMethod, ENTRIES);
+    let bytes = encrypt_index(&bytes, KEY);
+    load_pak_from_memory(&bytes, Some(KEY)).unwrap();
+    let err = load_pak_from_memory(&bytes, Some(OTHER_KEY)).unwrap_err();
+    assert!(matches!(err, PakError::WrongKey), "{}", err);
+    assert!(err.to_string().contains("wrong encryption key"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn metadata_json() {
+    for version in [PakVersion::EncryptionKeyGuid, PakVersion::Fnv64BugFix] {
+        let bytes = build_pak_in_memory(version, ENTRIES);
+        let pak = load_pak_from_memory(&bytes, None).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&pak.metadata_json()).unwrap();
+        assert_eq!(json["info"]["encryption_key_guid"], "00000000-0000-0000-0000-000000000000");
+        let index = if version < PakVersion::PathHashIndex { "V1" } else { "V2" };
+        let entries = json["index"][index]["entries"].as_object().unwrap();
+        assert_eq!(entries.len(), ENTRIES.len());
+
+        let (name, entry) = &pak.index().named_entries()[0];
+        let hash: String = entry.hash.iter().map(|byte| format!("{:02X}", byte)).collect();
+        assert_eq!(entries[name.as_str()]["hash"], hash.as_str());
+        let decoded: PakEntry = serde_json::from_value(entries[name.as_str()].clone()).unwrap();
+        assert_eq!(&&decoded, entry);
+        let info: PakInfo = serde_json::from_value(json["info"].clone()).unwrap();
+        assert_eq!(info.index_hash, pak.info().index_hash);
+        assert_eq!(info.version, version);
+    }
+}
+
+#[test]
+fn entry_reader() {
+    let content: Vec<u8> = (0..3000u32).map(|i| (i % 7) as u8).collect();
+    let bytes = build_compressed_pak(PakVersion::DeleteRecords, 1, &content, None, zlib_compress);
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let entries = pak.index().named_entries();
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    let mut reader = pak.entry_reader(&mut ar, entries[0].1).unwrap();
+    let mut start = [0u8; 10];
+    reader.read_exact(&mut start).unwrap();
+    let mut rest = Vec::new();
+    io::copy(&mut reader, &mut rest).unwrap();
+    assert_eq!([&start[..], &rest].concat(), content);
+
+    // uncompressed payloads larger than a read chunk
+    let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+    builder.encrypted(KEY).unwrap();
+    let mut writer = builder.add(&mut ar, "Game/Content/a.bin".to_owned());
+    writer.write_all(&content).unwrap();
+    writer.finalize().unwrap();
+    builder.finalize(&mut ar).unwrap();
+    let bytes = ar.0.into_inner();
+
+    let pak = load_pak_from_memory(&bytes, Some(KEY)).unwrap();
+    let entries = pak.index().named_entries();
+    let mut read = Vec::new();
+    let ar = ArchiveReader(Cursor::new(&bytes));
+    pak.entry_reader(ar, entries[0].1).unwrap().read_to_end(&mut read).unwrap();
+    assert_eq!(read, content);
+}
+
+#[test]
+fn read_entry_verified() {
+    let content: Vec<u8> = (0..3000u32).map(|i| (i % 7) as u8).collect();
+    let mut bytes =
+        build_compressed_pak(PakVersion::DeleteRecords, 1, &content, None, zlib_compress);
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let entry = pak.index().named_entries()[0].1.clone();
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    assert_eq!(pak.read_entry_verified(&mut ar, &entry).unwrap(), content);
+
+    let last_payload_byte = (pak.payload_offset(&entry).unwrap() + entry.size - 1) as usize;
+    bytes[last_payload_byte] ^= 0xFF;
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    let err = pak.read_entry_verified(&mut ar, &entry).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("hash"));
+}
+
+#[test]
+fn find_ci() {
+    let entries: &[(&str, &[u8])] =
+        &[("Game/Content/Maps/Level.umap", b"map"), ("Game/content/UI/icon.PNG", b"png")];
+    for version in [PakVersion::DeleteRecords, PakVersion::Fnv64BugFix] {
+        let bytes = build_pak_in_memory(version, entries);
+        let pak = load_pak_from_memory(&bytes, None).unwrap();
+        let index = pak.index();
+        for path in ["game/content/maps/level.umap", "GAME/CONTENT/MAPS/LEVEL.UMAP"] {
+            let entry = index.find_ci(path, version).expect("entry to be found");
+            assert_eq!(entry.uncompressed_size, 3, "{:?} {:?}", version, path);
+            assert!(index.find(path).is_none());
+        }
+        assert!(index.find_ci("Game/Content/UI/Icon.png", version).is_some());
+        assert!(index.find_ci("Game/Content/UI/Other.png", version).is_none());
+    }
+}
+
+/// Empty directory for a test in the system temporary directory
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ue4pak-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn extract_all() {
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::DeleteRecords);
+    for (name, content) in ENTRIES {
+        let mut writer = builder.add(&mut ar, name.to_string());
+        writer.write_all(content).unwrap();
+        writer.finalize().unwrap();
+    }
+    builder.deleted("Game/Content/c.txt").unwrap();
+    builder.finalize(&mut ar).unwrap();
+    let mut bytes = ar.0.into_inner();
+
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    let out_dir = test_dir("extract_all");
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    assert_eq!(pak.extract_all(&mut ar, &out_dir, None).unwrap(), ENTRIES.len());
+    for (name, content) in ENTRIES {
+        assert_eq!(fs::read(out_dir.join(name)).unwrap(), *content);
+    }
+    assert!(!out_dir.join("Game/Content/c.txt").exists());
+
+    // remapped paths can't escape the output directory
+    let out_dir = test_dir("extract_all_remap");
+    let remap = |full_path: &str| match full_path.strip_prefix("Game/Content/") {
+        Some("a.txt") => Some(PathBuf::from("../a.txt")),
+        Some(path) => Some(PathBuf::from(path)),
+        None => None,
+    };
+    let mut failed = Vec::new();
+    let summary = pak
+        .extract_all_with(&mut ar, &out_dir, None, remap, |path, err| {
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            failed.push(path.to_owned());
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+    assert_eq!(summary, ExtractSummary { extracted: 1, failed: 1 });
+    assert_eq!(failed, ["Game/Content/a.txt"]);
+    assert_eq!(fs::read(out_dir.join("Sub/b.bin")).unwrap(), ENTRIES[1].1);
+
+    // corrupt entries are reported and skipped
+    let a = pak.index().named_entries().into_iter().find(|(n, _)| n.ends_with("a.txt")).unwrap();
+    let payload_offset = pak.payload_offset(a.1).unwrap() as usize;
+    bytes[payload_offset] ^= 0xFF;
+    let out_dir = test_dir("extract_all_corrupt");
+    let mut ar = ArchiveReader(Cursor::new(&bytes));
+    assert!(pak.extract_all(&mut ar, &out_dir, None).is_err());
+    let summary = pak
+        .extract_all_with(
+            &mut ar,
+            &out_dir,
+            None,
+            |path| Some(PathBuf::from(path)),
+            |_, err| {
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+                ControlFlow::Continue(())
+            },
+        )
+        .unwrap();
+    assert_eq!(summary, ExtractSummary { extracted: 1, failed: 1 });
+    assert!(!out_dir.join("Game/Content/a.txt").exists());
+
+    // test_dir removes existing directories
+    for name in ["extract_all", "extract_all_remap", "extract_all_corrupt"] {
+        test_dir(name);
+    }
+}
+
+/// V2 pak file with a compressed and an uncompressed entry, without path hash index
+fn build_without_path_hash_index(key: Option<&str>) -> Vec<u8> {
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+    if let Some(key) = key {
+        builder.encrypted(key).unwrap();
+    }
+    builder.set_path_hash_index(false).unwrap();
+    let name = "Game/Content/a.bin".to_owned();
+    let mut writer = builder.add_compressed(&mut ar, name, "Zlib", 0x10000).unwrap();
+    writer.write_all(&vec![7u8; 0x18000]).unwrap();
+    writer.finalize().unwrap();
+    let mut writer = builder.add(&mut ar, "Game/Content/b.txt".to_owned());
+    writer.write_all(b"hello").unwrap();
+    writer.finalize().unwrap();
+    builder.finalize(&mut ar).unwrap();
+    ar.0.into_inner()
+}
+
+#[test]
+fn last_entry_end_without_path_hash_index() {
+    let bytes = build_without_path_hash_index(None);
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    assert_eq!(pak.index().entries().count(), 2);
+    assert_eq!(pak.index().last_entry_end(PakVersion::Fnv64BugFix), pak.info().index_offset);
+    pak.validate_layout().unwrap();
+}
+
+#[test]
+fn block_sizes_without_path_hash_index() {
+    let bytes = build_without_path_hash_index(None);
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    assert_eq!(pak.index().block_sizes().into_iter().collect::<Vec<_>>(), [0x10000]);
+}
+
+#[test]
+fn encryption_status_without_path_hash_index() {
+    let bytes = build_without_path_hash_index(None);
+    let pak = load_pak_from_memory(&bytes, None).unwrap();
+    assert_eq!(pak.encryption_status(), EncryptionStatus::None);
+
+    let bytes = build_without_path_hash_index(Some(KEY));
+    let pak = load_pak_from_memory(&bytes, Some(KEY)).unwrap();
+    assert_eq!(pak.encryption_status(), EncryptionStatus::EntriesOnly);
+}
+
+#[test]
+fn stream_entries_checked() {
+    let bytes = build_pak_in_memory(PakVersion::Fnv64BugFix, ENTRIES);
+    let mut names = Vec::new();
+    PakFile::stream_entries(&mut ArchiveReader(Cursor::new(&bytes)), None, |name, entry| {
+        assert!(entry.size > 0);
+        names.push(name.to_owned());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(names, ["Game/Content/a.txt", "Game/Content/Sub/b.bin"]);
+
+    // the index hash is checked before any entry is visited
+    let mut bytes = build_pak_in_memory(PakVersion::FNameBasedCompressionMethod, ENTRIES);
+    let info = PakFile::load_footer(&mut ArchiveReader(Cursor::new(&bytes))).unwrap();
+    bytes[(info.index_offset + info.index_size - 1) as usize] ^= 0xFF;
+    let mut count = 0;
+    let err = PakFile::stream_entries(&mut ArchiveReader(Cursor::new(&bytes)), None, |_, _| {
+        count += 1;
+        Ok(())
+    })
+    .unwrap_err();
+    assert!(matches!(err, PakError::HashMismatch { .. }), "{:?}", err);
+    assert_eq!(count, 0);
+
+    // encrypted indexes are decrypted section by section
+    let bytes = build_pak_in_memory(PakVersion::FNameBasedCompressionMethod, ENTRIES);
+    let bytes = encrypt_index(&bytes, KEY);
+    let mut names = Vec::new();
+    PakFile::stream_entries(&mut ArchiveReader(Cursor::new(&bytes)), Some(KEY), |name, _| {
+        names.push(name.to_owned());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(names, ["Game/Content/a.txt", "Game/Content/Sub/b.bin"]);
+    let err = PakFile::stream_entries(&mut ArchiveReader(Cursor::new(&bytes)), None, |_, _| Ok(()))
+        .unwrap_err();
+    assert!(matches!(err, PakError::MissingKey), "{:?}", err);
+
+    // without the full directory index, entry paths are unknown
+    let mut ar = ArchiveWriter(Cursor::new(Vec::new()));
+    let mut builder = PakFileBuilder::new(PakVersion::Fnv64BugFix);
+    builder.set_full_directory_index(false).unwrap();
+    let mut writer = builder.add(&mut ar, "Game/Content/a.txt".to_owned());
+    writer.write_all(b"hello").unwrap();
+    writer.finalize().unwrap();
+    builder.finalize(&mut ar).unwrap();
+    let bytes = ar.0.into_inner();
+    let err = PakFile::stream_entries(&mut ArchiveReader(Cursor::new(&bytes)), None, |_, _| Ok(()))
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+}